@@ -0,0 +1,200 @@
+/*
+    Assembler expressions: numbers in the spellings the disassembler prints
+    ($20eb, 0x20eb) plus 20EBH, decimal and 'c' characters, symbols, `$` for
+    the current address, and the C operators + - * / % & | ^ << >> ~ with
+    parentheses.
+*/
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExprError {
+    Undefined(String),    // May resolve on the second pass
+    Syntax(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(i64),
+    Symbol(String),
+    Here,                 // $
+    Op(&'static str),
+    Open,
+    Close,
+}
+
+const OPERATORS: [&str; 12] = ["<<", ">>", "+", "-", "*", "/", "%", "&", "|", "^", "~", "!"];
+
+fn is_symbol_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || c == '.' || c == '?' || c == '@'
+}
+
+fn is_symbol_char(c: char) -> bool {
+    is_symbol_start(c) || c.is_ascii_digit()
+}
+
+/// Whether `text` can be used as a label or EQU name.
+pub fn is_symbol(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars.next().is_some_and(is_symbol_start) && chars.all(is_symbol_char)
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    let lower: String = text.to_ascii_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()
+    }
+    else if let Some(hex) = lower.strip_suffix('h') {
+        i64::from_str_radix(hex, 16).ok()
+    }
+    else if let Some(binary) = lower.strip_suffix('b') {
+        i64::from_str_radix(binary, 2).ok()
+    }
+    else {
+        lower.parse().ok()
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, ExprError> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut i: usize = 0;
+    while i < chars.len() {
+        let c: char = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        }
+        else if c == '$' {
+            let start: usize = i + 1;
+            let mut end: usize = start;
+            while end < chars.len() && chars[end].is_ascii_hexdigit() {
+                end += 1;
+            }
+            if end == start {
+                tokens.push(Token::Here);
+            }
+            else {
+                let digits: String = chars[start..end].iter().collect();
+                tokens.push(Token::Number(i64::from_str_radix(&digits, 16)
+                    .map_err(|_| ExprError::Syntax(format!("bad number `${}`", digits)))?));
+            }
+            i = end;
+        }
+        else if c.is_ascii_digit() {
+            let start: usize = i;
+            while i < chars.len() && chars[i].is_ascii_alphanumeric() {
+                i += 1;
+            }
+            let word: String = chars[start..i].iter().collect();
+            tokens.push(Token::Number(parse_number(&word)
+                .ok_or_else(|| ExprError::Syntax(format!("bad number `{}`", word)))?));
+        }
+        else if is_symbol_start(c) {
+            let start: usize = i;
+            while i < chars.len() && is_symbol_char(chars[i]) {
+                i += 1;
+            }
+            tokens.push(Token::Symbol(chars[start..i].iter().collect()));
+        }
+        else if c == '\'' || c == '"' {
+            if i + 2 >= chars.len() || chars[i + 2] != c {
+                return Err(ExprError::Syntax("character constants hold one character".to_string()));
+            }
+            tokens.push(Token::Number(chars[i + 1] as i64));
+            i += 3;
+        }
+        else if c == '(' {
+            tokens.push(Token::Open);
+            i += 1;
+        }
+        else if c == ')' {
+            tokens.push(Token::Close);
+            i += 1;
+        }
+        else {
+            let rest: String = chars[i..].iter().take(2).collect();
+            let op: &'static str = OPERATORS.iter()
+                .find(|op| rest.starts_with(*op))
+                .ok_or_else(|| ExprError::Syntax(format!("unexpected `{}`", c)))?;
+            tokens.push(Token::Op(op));
+            i += op.len();
+        }
+    }
+    Ok(tokens)
+}
+
+// Binary operators from loosest to tightest binding
+const PRECEDENCE: [&[&str]; 6] = [&["|"], &["^"], &["&"], &["<<", ">>"], &["+", "-"], &["*", "/", "%"]];
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    position: usize,
+    here: i64,
+    symbols: &'a HashMap<String, i64>,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+    fn binary(&mut self, level: usize) -> Result<i64, ExprError> {
+        if level == PRECEDENCE.len() {
+            return self.unary();
+        }
+        let mut value: i64 = self.binary(level + 1)?;
+        while let Some(&Token::Op(op)) = self.peek() {
+            if !PRECEDENCE[level].contains(&op) {
+                break;
+            }
+            self.position += 1;
+            let rhs: i64 = self.binary(level + 1)?;
+            value = match op {
+                "|" => value | rhs,
+                "^" => value ^ rhs,
+                "&" => value & rhs,
+                "<<" => value.wrapping_shl(rhs as u32),
+                ">>" => value.wrapping_shr(rhs as u32),
+                "+" => value.wrapping_add(rhs),
+                "-" => value.wrapping_sub(rhs),
+                "*" => value.wrapping_mul(rhs),
+                "/" | "%" if rhs == 0 => return Err(ExprError::Syntax("division by zero".to_string())),
+                "/" => value / rhs,
+                _ => value % rhs,
+            };
+        }
+        Ok(value)
+    }
+    fn unary(&mut self) -> Result<i64, ExprError> {
+        match self.next() {
+            Some(Token::Op("-")) => Ok(self.unary()?.wrapping_neg()),
+            Some(Token::Op("+")) => self.unary(),
+            Some(Token::Op("~")) | Some(Token::Op("!")) => Ok(!self.unary()?),
+            Some(Token::Number(value)) => Ok(value),
+            Some(Token::Here) => Ok(self.here),
+            Some(Token::Symbol(name)) => self.symbols.get(&name).cloned().ok_or(ExprError::Undefined(name)),
+            Some(Token::Open) => {
+                let value: i64 = self.binary(0)?;
+                match self.next() {
+                    Some(Token::Close) => Ok(value),
+                    _ => Err(ExprError::Syntax("missing `)`".to_string())),
+                }
+            },
+            Some(token) => Err(ExprError::Syntax(format!("unexpected {:?}", token))),
+            None => Err(ExprError::Syntax("expression ends early".to_string())),
+        }
+    }
+}
+
+/// Evaluates `text` with `here` as the value of `$`.
+pub fn evaluate(text: &str, here: u16, symbols: &HashMap<String, i64>) -> Result<i64, ExprError> {
+    let mut parser = Parser { tokens: tokenize(text)?, position: 0, here: here as i64, symbols };
+    let value: i64 = parser.binary(0)?;
+    match parser.peek() {
+        None => Ok(value),
+        Some(token) => Err(ExprError::Syntax(format!("unexpected {:?}", token))),
+    }
+}
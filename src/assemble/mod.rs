@@ -0,0 +1,453 @@
+/*
+    A two-pass 8080 assembler.  It reads the spellings the disassembler
+    prints, so a listing can be edited and assembled again:
+
+        ORG     $0000
+    count   EQU     $20eb           ; names for addresses outside the code
+    start:  LDA     count
+    0x0003 JNZ     start            ; a leading 0xADDR column is skipped
+            DB      $01,"text",'c'
+            DW      start,$+2
+            DS      16
+
+    The first pass sizes every line and fixes the labels, the second
+    evaluates operands and emits bytes.  Encodings come from the shared
+    opcode table, so every documented form is accepted.
+*/
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
+use disassemble::{OperandKind, OPCODES};
+use symbols::{Symbol, SymbolTable};
+
+pub mod expr;
+
+use self::expr::ExprError;
+
+// Bytes shown on each listing line
+const LISTING_BYTES: usize = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssembleError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for AssembleError {}
+
+/// The assembled image, from the lowest to the highest address written,
+/// with any gaps between filled with zeros.
+#[derive(Debug, Clone)]
+pub struct Assembly {
+    pub origin: u16,
+    pub bytes: Vec<u8>,
+    pub listing: String,
+    pub labels: BTreeMap<String, u16>,    // Labels and EQUs
+}
+
+impl Assembly {
+    /// The labels as a symbol table, for the disassembler and debugger.
+    pub fn symbol_table(&self) -> SymbolTable {
+        let mut symbols = SymbolTable::new();
+        for (name, &addr) in &self.labels {
+            symbols.insert(Symbol::new(addr, name));
+        }
+        symbols
+    }
+}
+
+/// One source line taken apart.
+struct Statement<'a> {
+    line: usize,
+    source: &'a str,
+    label: Option<&'a str>,
+    op: Option<String>,           // Mnemonic or directive, upper case
+    operands: Vec<&'a str>,
+}
+
+/// Where an instruction's operand bytes come from.
+#[derive(Debug, Clone, Copy)]
+enum Field {
+    None,
+    Byte(usize),          // Operand index of an 8-bit value
+    Word(usize),          // Operand index of a 16-bit value
+    Vector(usize),        // Operand index of an RST number
+}
+
+/// Splits at commas outside quotes.
+fn split_operands(text: &str) -> Vec<&str> {
+    let mut operands: Vec<&str> = Vec::new();
+    let mut quote: Option<char> = None;
+    let mut start: usize = 0;
+    for (i, c) in text.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {},
+            None if c == '\'' || c == '"' => quote = Some(c),
+            None if c == ',' => {
+                operands.push(text[start..i].trim());
+                start = i + 1;
+            },
+            None => {},
+        }
+    }
+    let last: &str = text[start..].trim();
+    if !last.is_empty() || !operands.is_empty() {
+        operands.push(last);
+    }
+    operands
+}
+
+/// Drops a `;` comment, ignoring semicolons inside quotes.
+fn strip_comment(line: &str) -> &str {
+    let mut quote: Option<char> = None;
+    for (i, c) in line.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {},
+            None if c == '\'' || c == '"' => quote = Some(c),
+            None if c == ';' => return &line[..i],
+            None => {},
+        }
+    }
+    line
+}
+
+fn is_address_column(word: &str) -> bool {
+    (word.starts_with("0x") || word.starts_with("0X")) && word.len() > 2 &&
+        word[2..].chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn parse_line(line: usize, source: &str) -> Result<Statement<'_>, AssembleError> {
+    let mut rest: &str = strip_comment(source).trim();
+    let first: &str = rest.split_whitespace().next().unwrap_or("");
+    if is_address_column(first) {
+        rest = rest[first.len()..].trim_start();
+    }
+    let mut label: Option<&str> = None;
+    let first: &str = rest.split(|c: char| c.is_whitespace() || c == ':').next().unwrap_or("");
+    if rest[first.len()..].starts_with(':') {
+        label = Some(first);
+        rest = rest[first.len() + 1..].trim_start();
+    }
+    else {
+        // `name EQU value` needs no colon
+        let mut words = rest.split_whitespace();
+        if let (Some(name), Some(op)) = (words.next(), words.next()) {
+            if op.eq_ignore_ascii_case("EQU") {
+                label = Some(name);
+                rest = rest[name.len()..].trim_start();
+            }
+        }
+    }
+    if let Some(name) = label {
+        if !expr::is_symbol(name) {
+            return Err(AssembleError { line, message: format!("bad label `{}`", name) });
+        }
+    }
+    let op: &str = rest.split_whitespace().next().unwrap_or("");
+    let operands: Vec<&str> = split_operands(rest[op.len()..].trim());
+    Ok(Statement {
+        line,
+        source,
+        label,
+        op: if op.is_empty() { None } else { Some(op.to_ascii_uppercase()) },
+        operands,
+    })
+}
+
+/// Finds the opcode for `mnemonic` with these operands.  Register and pair
+/// operands must match exactly; anything else is taken as an expression.
+fn encode(mnemonic: &str, operands: &[&str]) -> Option<(u8, Field)> {
+    'opcodes: for (opcode, info) in OPCODES.iter().enumerate() {
+        if info.undocumented || info.mnemonic.name() != mnemonic {
+            continue;
+        }
+        let kinds: Vec<OperandKind> = info.operands.iter().filter_map(|kind| *kind).collect();
+        if kinds.len() != operands.len() {
+            continue;
+        }
+        let mut field = Field::None;
+        for (index, (kind, text)) in kinds.iter().zip(operands).enumerate() {
+            match *kind {
+                OperandKind::Reg(r) => if !text.eq_ignore_ascii_case(r.name()) { continue 'opcodes; },
+                OperandKind::Pair(rp) => if !text.eq_ignore_ascii_case(rp.name()) { continue 'opcodes; },
+                OperandKind::Imm8 | OperandKind::Port => field = Field::Byte(index),
+                OperandKind::Imm16 | OperandKind::Addr => field = Field::Word(index),
+                OperandKind::Vector(_) => field = Field::Vector(index),
+            }
+        }
+        return Some((opcode as u8, field));
+    }
+    None
+}
+
+fn string_literal(text: &str) -> Option<&str> {
+    let quote: char = text.chars().next()?;
+    if (quote == '"' || quote == '\'') && text.len() >= 2 && text.ends_with(quote) {
+        Some(&text[1..text.len() - 1])
+    }
+    else {
+        None
+    }
+}
+
+/// Bytes a DB item takes: strings give one per character.
+fn db_item_len(item: &str) -> usize {
+    match string_literal(item) {
+        Some(text) if text.len() != 1 => text.len(),
+        _ => 1,
+    }
+}
+
+struct Assembler<'a> {
+    statements: Vec<Statement<'a>>,
+    symbols: HashMap<String, i64>,
+    image: Vec<Option<u8>>,
+    listing: String,
+}
+
+impl<'a> Assembler<'a> {
+    fn error(line: usize, message: String) -> AssembleError {
+        AssembleError { line, message }
+    }
+    fn evaluate(&self, line: usize, text: &str, here: u16) -> Result<i64, AssembleError> {
+        expr::evaluate(text, here, &self.symbols).map_err(|error| match error {
+            ExprError::Undefined(name) => Assembler::error(line, format!("undefined symbol `{}`", name)),
+            ExprError::Syntax(message) => Assembler::error(line, format!("{} in `{}`", message, text)),
+        })
+    }
+    fn define(&mut self, line: usize, name: &str, value: i64) -> Result<(), AssembleError> {
+        if self.symbols.insert(name.to_string(), value).is_some() {
+            return Err(Assembler::error(line, format!("`{}` defined twice", name)));
+        }
+        Ok(())
+    }
+    /// Bytes the statement at `index` emits or reserves, given its address.
+    fn size(&self, index: usize, here: u16) -> Result<usize, AssembleError> {
+        let statement = &self.statements[index];
+        let line: usize = statement.line;
+        let op: &str = match statement.op {
+            Some(ref op) => op,
+            None => return Ok(0),
+        };
+        match op {
+            "ORG" | "EQU" | "END" => Ok(0),
+            "DB" => Ok(statement.operands.iter().map(|item| db_item_len(item)).sum()),
+            "DW" => Ok(statement.operands.len() * 2),
+            "DS" => {
+                let count: i64 = self.evaluate(line, statement.operands.first().unwrap_or(&""), here)?;
+                if !(0..=0x10000).contains(&count) {
+                    return Err(Assembler::error(line, format!("bad DS size {}", count)));
+                }
+                Ok(count as usize)
+            },
+            _ => match encode(op, &statement.operands) {
+                Some((opcode, _)) => Ok(OPCODES[opcode as usize].length as usize),
+                None => Err(Assembler::error(line, format!("no form of {} takes `{}`", op,
+                                                           statement.operands.join(",")))),
+            },
+        }
+    }
+    /// Pass one: addresses for labels, and EQU values.
+    fn first_pass(&mut self) -> Result<(), AssembleError> {
+        let mut here: u32 = 0;
+        let mut pending: Vec<(usize, u16)> = Vec::new();  // EQUs with forward references, and $ at each
+        for index in 0..self.statements.len() {
+            let (line, label, op) = {
+                let statement = &self.statements[index];
+                (statement.line, statement.label, statement.op.clone())
+            };
+            match op.as_deref() {
+                Some("END") => break,
+                Some("EQU") => {
+                    let name: &str = label.ok_or_else(|| Assembler::error(line, "EQU needs a name".to_string()))?;
+                    match expr::evaluate(self.statements[index].operands.first().unwrap_or(&""), here as u16,
+                                         &self.symbols) {
+                        Ok(value) => self.define(line, name, value)?,
+                        Err(ExprError::Undefined(_)) => pending.push((index, here as u16)),
+                        Err(ExprError::Syntax(message)) => return Err(Assembler::error(line, message)),
+                    }
+                    continue;
+                },
+                Some("ORG") => {
+                    let value: i64 = self.evaluate(line, self.statements[index].operands.first().unwrap_or(&""),
+                                                   here as u16)?;
+                    here = (value as u32) & 0xffff;
+                },
+                _ => {},
+            }
+            if let Some(name) = label {
+                self.define(line, name, here as i64)?;
+            }
+            here += self.size(index, here as u16)? as u32;
+            if here > 0x10000 {
+                return Err(Assembler::error(line, "past the end of memory".to_string()));
+            }
+        }
+        // EQUs that refer to later labels or EQUs
+        while !pending.is_empty() {
+            let before: usize = pending.len();
+            let mut unresolved: Vec<(usize, u16)> = Vec::new();
+            for (index, here) in pending {
+                let statement = &self.statements[index];
+                match expr::evaluate(statement.operands.first().unwrap_or(&""), here, &self.symbols) {
+                    Ok(value) => {
+                        let (line, name) = (statement.line, statement.label.unwrap_or(""));
+                        self.define(line, name, value)?;
+                    },
+                    Err(_) => unresolved.push((index, here)),
+                }
+            }
+            if unresolved.len() == before {
+                let (index, here) = unresolved[0];
+                let statement = &self.statements[index];
+                return Err(self.evaluate(statement.line, statement.operands[0], here).err().unwrap_or_else(||
+                    Assembler::error(statement.line, "circular EQU".to_string())));
+            }
+            pending = unresolved;
+        }
+        Ok(())
+    }
+    fn emit(&mut self, line: usize, addr: u16, bytes: &[u8]) -> Result<(), AssembleError> {
+        for (i, &byte) in bytes.iter().enumerate() {
+            let at: usize = addr as usize + i;
+            if self.image[at].is_some() {
+                return Err(Assembler::error(line, format!("overwrites ${:04x}", at)));
+            }
+            self.image[at] = Some(byte);
+        }
+        Ok(())
+    }
+    fn value(&self, line: usize, text: &str, here: u16, bits: u32) -> Result<i64, AssembleError> {
+        let value: i64 = self.evaluate(line, text, here)?;
+        if value < -(1 << (bits - 1)) || value >= 1 << bits {
+            return Err(Assembler::error(line, format!("`{}` is {}, too big for {} bits", text, value, bits)));
+        }
+        Ok(value & ((1 << bits) - 1))
+    }
+    /// The bytes of the statement at `index`, which starts at `here`.
+    fn statement_bytes(&self, index: usize, here: u16) -> Result<Vec<u8>, AssembleError> {
+        let statement = &self.statements[index];
+        let line: usize = statement.line;
+        let mut bytes: Vec<u8> = Vec::new();
+        match statement.op.as_deref() {
+            None | Some("ORG") | Some("EQU") | Some("END") | Some("DS") => {},
+            Some("DB") => for item in &statement.operands {
+                match string_literal(item) {
+                    Some(text) if text.len() != 1 => bytes.extend(text.bytes()),
+                    _ => bytes.push(self.value(line, item, here, 8)? as u8),
+                }
+            },
+            Some("DW") => for item in &statement.operands {
+                let word: i64 = self.value(line, item, here, 16)?;
+                bytes.push(word as u8);
+                bytes.push((word >> 8) as u8);
+            },
+            Some(op) => {
+                let (opcode, field) = encode(op, &statement.operands).unwrap_or((0, Field::None));
+                match field {
+                    Field::None => bytes.push(opcode),
+                    Field::Byte(i) => {
+                        bytes.push(opcode);
+                        bytes.push(self.value(line, statement.operands[i], here, 8)? as u8);
+                    },
+                    Field::Word(i) => {
+                        let word: i64 = self.value(line, statement.operands[i], here, 16)?;
+                        bytes.extend(&[opcode, word as u8, (word >> 8) as u8]);
+                    },
+                    Field::Vector(i) => {
+                        let n: i64 = self.evaluate(line, statement.operands[i], here)?;
+                        if !(0..8).contains(&n) {
+                            return Err(Assembler::error(line, format!("RST {} is not 0-7", n)));
+                        }
+                        bytes.push(0xc7 | (n as u8) << 3);
+                    },
+                }
+            },
+        }
+        Ok(bytes)
+    }
+    fn list(&mut self, addr: Option<u16>, bytes: &[u8], source: &str) {
+        let column: String = match addr {
+            Some(addr) => format!("{:04X}", addr),
+            None => String::new(),
+        };
+        let hex: Vec<String> = bytes.iter().take(LISTING_BYTES).map(|b| format!("{:02X}", b)).collect();
+        self.listing.push_str(format!("{:<4}  {:<12}  {}", column, hex.join(" "), source).trim_end());
+        self.listing.push('\n');
+        // The rest of a long DB or DW on lines of their own
+        let mut offset: usize = LISTING_BYTES;
+        while offset < bytes.len() {
+            let hex: Vec<String> = bytes[offset..].iter().take(LISTING_BYTES).map(|b| format!("{:02X}", b)).collect();
+            let at: u16 = addr.unwrap_or(0).wrapping_add(offset as u16);
+            self.listing.push_str(&format!("{:04X}  {}\n", at, hex.join(" ")));
+            offset += LISTING_BYTES;
+        }
+    }
+    /// Pass two: emits bytes and the listing.
+    fn second_pass(&mut self) -> Result<(), AssembleError> {
+        let mut here: u16 = 0;
+        for index in 0..self.statements.len() {
+            let (line, source, op) = {
+                let statement = &self.statements[index];
+                (statement.line, statement.source, statement.op.clone())
+            };
+            match op.as_deref() {
+                Some("END") => {
+                    self.list(None, &[], source);
+                    break;
+                },
+                Some("EQU") => {
+                    let name: &str = self.statements[index].label.unwrap_or("");
+                    let value: u16 = self.symbols[name] as u16;
+                    self.list(Some(value), &[], source);
+                    continue;
+                },
+                Some("ORG") => {
+                    here = self.evaluate(line, self.statements[index].operands[0], here)? as u16;
+                },
+                _ => {},
+            }
+            let bytes: Vec<u8> = self.statement_bytes(index, here)?;
+            self.emit(line, here, &bytes)?;
+            let shown: Option<u16> = if op.is_some() || self.statements[index].label.is_some() { Some(here) } else { None };
+            self.list(shown, &bytes, source);
+            here = here.wrapping_add(self.size(index, here)? as u16);
+        }
+        Ok(())
+    }
+}
+
+/// Assembles `source`.  Errors name the first line that failed.
+pub fn assemble(source: &str) -> Result<Assembly, AssembleError> {
+    let mut statements: Vec<Statement> = Vec::new();
+    for (index, line) in source.lines().enumerate() {
+        statements.push(parse_line(index + 1, line)?);
+    }
+    let mut assembler = Assembler {
+        statements,
+        symbols: HashMap::new(),
+        image: vec![None; 0x10000],
+        listing: String::new(),
+    };
+    assembler.first_pass()?;
+    assembler.second_pass()?;
+    let first: Option<usize> = assembler.image.iter().position(|byte| byte.is_some());
+    let last: Option<usize> = assembler.image.iter().rposition(|byte| byte.is_some());
+    let (origin, bytes): (u16, Vec<u8>) = match (first, last) {
+        (Some(first), Some(last)) =>
+            (first as u16, assembler.image[first..=last].iter().map(|byte| byte.unwrap_or(0)).collect()),
+        _ => (0, Vec::new()),
+    };
+    let labels: BTreeMap<String, u16> = assembler.symbols.iter()
+        .map(|(name, &value)| (name.clone(), value as u16))
+        .collect();
+    Ok(Assembly { origin, bytes, listing: assembler.listing, labels })
+}
@@ -0,0 +1,75 @@
+/*
+    Compares two execution traces written by `rust8080 --trace` (or another
+    emulator's, reshaped to the same fields) and reports the first
+    instruction where they disagree.  Exits with 0 if the traces match, 1 if
+    they diverge and 2 on errors.
+*/
+extern crate rust8080;
+
+use std::env;
+use std::fs::File;
+use std::io::BufReader;
+use std::process;
+use rust8080::trace;
+
+const USAGE: &str = "usage: tracediff [--ignore FIELD,FIELD,...] <left> <right>";
+
+fn open(path: &str) -> BufReader<File> {
+    match File::open(path) {
+        Ok(file) => BufReader::new(file),
+        Err(error) => {
+            eprintln!("{}: {}", path, error);
+            process::exit(2);
+        },
+    }
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let mut ignore: Vec<String> = Vec::new();
+    let mut paths: Vec<String> = Vec::new();
+    while let Some(arg) = args.next() {
+        if arg == "--ignore" {
+            match args.next() {
+                Some(fields) => ignore.extend(fields.split(',').map(|field| field.to_string())),
+                None => {
+                    eprintln!("--ignore needs a list of fields\n{}", USAGE);
+                    process::exit(2);
+                },
+            }
+        }
+        else {
+            paths.push(arg);
+        }
+    }
+    if paths.len() != 2 {
+        eprintln!("{}", USAGE);
+        process::exit(2);
+    }
+    let divergence = match trace::diff(open(&paths[0]), open(&paths[1]), &ignore) {
+        Ok(divergence) => divergence,
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(2);
+        },
+    };
+    let divergence = match divergence {
+        Some(divergence) => divergence,
+        None => {
+            println!("traces match");
+            return;
+        },
+    };
+    if divergence.fields.is_empty() {
+        println!("traces diverge at line {}: one ends early", divergence.line);
+    }
+    else {
+        println!("traces diverge at line {}: {} differ", divergence.line, divergence.fields.join(", "));
+    }
+    if let Some(previous) = divergence.previous {
+        println!("  both   {}", previous);
+    }
+    println!("  left   {}", divergence.left.as_deref().unwrap_or("(end of trace)"));
+    println!("  right  {}", divergence.right.as_deref().unwrap_or("(end of trace)"));
+    process::exit(1);
+}
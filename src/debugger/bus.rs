@@ -0,0 +1,50 @@
+/*
+    A bus wrapper that records every access the CPU makes, so the debugger
+    can check watchpoints after each instruction.
+*/
+use std::cell::RefCell;
+use std::rc::Rc;
+use vm::Bus;
+
+/// One read or write made through a `WatchedBus`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Access {
+    pub addr: u16,
+    pub value: u8,        // Byte read, or byte written
+    pub old: u8,          // Byte at `addr` before a write; `value` for reads
+    pub write: bool,
+}
+
+/// Accesses since the log was last cleared, shared with the debugger.
+pub type AccessLog = Rc<RefCell<Vec<Access>>>;
+
+pub struct WatchedBus {
+    pub inner: Box<dyn Bus>,
+    log: AccessLog,
+}
+
+impl WatchedBus {
+    pub fn new(inner: Box<dyn Bus>, log: AccessLog) -> WatchedBus {
+        WatchedBus { inner, log }
+    }
+}
+
+impl Bus for WatchedBus {
+    fn read(&self, addr: u16) -> u8 {
+        let value: u8 = self.inner.read(addr);
+        self.log.borrow_mut().push(Access { addr, value, old: value, write: false });
+        value
+    }
+    fn write(&mut self, addr: u16, value: u8) {
+        let old: u8 = self.inner.read(addr);
+        self.inner.write(addr, value);
+        self.log.borrow_mut().push(Access { addr, value, old, write: true });
+    }
+    fn load(&mut self, addr: u16, data: &[u8]) {
+        self.inner.load(addr, data);
+    }
+    // Bulk reads are debugger and video peeks, not CPU accesses
+    fn read_range(&self, addr: u16, len: usize) -> Vec<u8> {
+        self.inner.read_range(addr, len)
+    }
+}
@@ -0,0 +1,265 @@
+/*
+    Debugger expressions, for breakpoint conditions and command arguments:
+    registers (a..l, bc, de, hl, sp, pc), flags (z, s, p, cy, ac), bytes in
+    memory as [addr], symbol names, numbers ($20eb, 0x20eb, 20ebh, decimal)
+    and the C operators with their usual precedence.  Comparisons and
+    logical operators give 1 or 0.
+*/
+use symbols::SymbolTable;
+use vm::Vm;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Source {
+    A, B, C, D, E, H, L,
+    Bc, De, Hl, Sp, Pc,
+    Z, S, P, Cy, Ac,
+}
+
+const SOURCES: [(&str, Source); 17] = [
+    ("a", Source::A), ("b", Source::B), ("c", Source::C), ("d", Source::D),
+    ("e", Source::E), ("h", Source::H), ("l", Source::L),
+    ("bc", Source::Bc), ("de", Source::De), ("hl", Source::Hl),
+    ("sp", Source::Sp), ("pc", Source::Pc),
+    ("z", Source::Z), ("s", Source::S), ("p", Source::P), ("cy", Source::Cy), ("ac", Source::Ac),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    Number(i64),
+    Source(Source),
+    Byte(Box<Node>),      // [addr]
+    Unary(&'static str, Box<Node>),
+    Binary(&'static str, Box<Node>, Box<Node>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Op(&'static str),
+}
+
+// Longest first so "<=" is not read as "<"
+const OPERATORS: [&str; 24] = [
+    "<<", ">>", "<=", ">=", "==", "!=", "&&", "||",
+    "<", ">", "+", "-", "*", "/", "%", "&", "|", "^", "~", "!", "(", ")", "[", "]",
+];
+
+// Binary operators from loosest to tightest binding
+const PRECEDENCE: [&[&str]; 10] = [
+    &["||"], &["&&"], &["|"], &["^"], &["&"], &["==", "!="], &["<", "<=", ">", ">="],
+    &["<<", ">>"], &["+", "-"], &["*", "/", "%"],
+];
+
+fn parse_number(text: &str) -> Option<i64> {
+    let lower: String = text.to_ascii_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()
+    }
+    else if let Some(hex) = lower.strip_suffix('h') {
+        i64::from_str_radix(hex, 16).ok()
+    }
+    else {
+        lower.parse().ok()
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut i: usize = 0;
+    while i < chars.len() {
+        let c: char = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        }
+        else if c == '$' || c.is_ascii_digit() {
+            let start: usize = i;
+            i += 1;
+            while i < chars.len() && chars[i].is_ascii_alphanumeric() {
+                i += 1;
+            }
+            let word: String = chars[start..i].iter().collect();
+            let value: Option<i64> = match word.strip_prefix('$') {
+                Some(hex) => i64::from_str_radix(hex, 16).ok(),
+                None => parse_number(&word),
+            };
+            tokens.push(Token::Number(value.ok_or_else(|| format!("bad number `{}`", word))?));
+        }
+        else if c.is_ascii_alphabetic() || c == '_' || c == '.' {
+            let start: usize = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_' || chars[i] == '.') {
+                i += 1;
+            }
+            tokens.push(Token::Name(chars[start..i].iter().collect()));
+        }
+        else {
+            let rest: String = chars[i..].iter().take(2).collect();
+            let op: &'static str = OPERATORS.iter()
+                .find(|op| rest.starts_with(*op))
+                .ok_or_else(|| format!("unexpected `{}`", c))?;
+            tokens.push(Token::Op(op));
+            i += op.len();
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    position: usize,
+    symbols: &'a SymbolTable,
+}
+
+impl<'a> Parser<'a> {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+    fn expect(&mut self, op: &str) -> Result<(), String> {
+        match self.next() {
+            Some(Token::Op(found)) if found == op => Ok(()),
+            _ => Err(format!("missing `{}`", op)),
+        }
+    }
+    fn binary(&mut self, level: usize) -> Result<Node, String> {
+        if level == PRECEDENCE.len() {
+            return self.unary();
+        }
+        let mut node: Node = self.binary(level + 1)?;
+        while let Some(&Token::Op(op)) = self.tokens.get(self.position) {
+            if !PRECEDENCE[level].contains(&op) {
+                break;
+            }
+            self.position += 1;
+            node = Node::Binary(op, Box::new(node), Box::new(self.binary(level + 1)?));
+        }
+        Ok(node)
+    }
+    fn unary(&mut self) -> Result<Node, String> {
+        match self.next() {
+            Some(Token::Op("+")) => self.unary(),
+            Some(Token::Op(op)) if op == "-" || op == "~" || op == "!" => {
+                Ok(Node::Unary(op, Box::new(self.unary()?)))
+            },
+            Some(Token::Op("(")) => {
+                let node: Node = self.binary(0)?;
+                self.expect(")")?;
+                Ok(node)
+            },
+            Some(Token::Op("[")) => {
+                let node: Node = self.binary(0)?;
+                self.expect("]")?;
+                Ok(Node::Byte(Box::new(node)))
+            },
+            Some(Token::Number(value)) => Ok(Node::Number(value)),
+            Some(Token::Name(name)) => {
+                // Registers win over symbols of the same name
+                let lower: String = name.to_ascii_lowercase();
+                if let Some(&(_, source)) = SOURCES.iter().find(|&&(source, _)| source == lower) {
+                    Ok(Node::Source(source))
+                }
+                else if let Some(symbol) = self.symbols.by_name(&name) {
+                    Ok(Node::Number(symbol.addr as i64))
+                }
+                else {
+                    Err(format!("unknown name `{}`", name))
+                }
+            },
+            Some(token) => Err(format!("unexpected {:?}", token)),
+            None => Err("expression ends early".to_string()),
+        }
+    }
+}
+
+/// A parsed expression, evaluated against the machine each time it is
+/// needed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expr {
+    pub text: String,
+    root: Node,
+}
+
+impl Expr {
+    /// Parses `text`, resolving symbol names to their addresses.
+    pub fn parse(text: &str, symbols: &SymbolTable) -> Result<Expr, String> {
+        let mut parser = Parser { tokens: tokenize(text)?, position: 0, symbols };
+        let root: Node = parser.binary(0)?;
+        match parser.next() {
+            None => Ok(Expr { text: text.trim().to_string(), root }),
+            Some(token) => Err(format!("unexpected {:?}", token)),
+        }
+    }
+    pub fn evaluate<P>(&self, vm: &Vm<P>) -> i64 {
+        evaluate(&self.root, vm)
+    }
+}
+
+fn pair(high: u8, low: u8) -> i64 {
+    ((high as i64) << 8) | low as i64
+}
+
+fn evaluate<P>(node: &Node, vm: &Vm<P>) -> i64 {
+    match *node {
+        Node::Number(value) => value,
+        Node::Source(source) => match source {
+            Source::A => vm.a as i64,
+            Source::B => vm.b as i64,
+            Source::C => vm.c as i64,
+            Source::D => vm.d as i64,
+            Source::E => vm.e as i64,
+            Source::H => vm.h as i64,
+            Source::L => vm.l as i64,
+            Source::Bc => pair(vm.b, vm.c),
+            Source::De => pair(vm.d, vm.e),
+            Source::Hl => pair(vm.h, vm.l),
+            Source::Sp => vm.sp as i64,
+            Source::Pc => vm.pc as i64,
+            Source::Z => vm.condition_codes.z as i64,
+            Source::S => vm.condition_codes.s as i64,
+            Source::P => vm.condition_codes.p as i64,
+            Source::Cy => vm.condition_codes.cy as i64,
+            Source::Ac => vm.condition_codes.ac as i64,
+        },
+        // Peek so conditions never trip read watchpoints
+        Node::Byte(ref addr) => vm.memory.read_range(evaluate(addr, vm) as u16, 1)[0] as i64,
+        Node::Unary(op, ref operand) => {
+            let value: i64 = evaluate(operand, vm);
+            match op {
+                "-" => value.wrapping_neg(),
+                "~" => !value,
+                _ => (value == 0) as i64,
+            }
+        },
+        Node::Binary(op, ref lhs, ref rhs) => {
+            let lhs: i64 = evaluate(lhs, vm);
+            // && and || short-circuit like C
+            match op {
+                "&&" => return (lhs != 0 && evaluate(rhs, vm) != 0) as i64,
+                "||" => return (lhs != 0 || evaluate(rhs, vm) != 0) as i64,
+                _ => (),
+            }
+            let rhs: i64 = evaluate(rhs, vm);
+            match op {
+                "|" => lhs | rhs,
+                "^" => lhs ^ rhs,
+                "&" => lhs & rhs,
+                "==" => (lhs == rhs) as i64,
+                "!=" => (lhs != rhs) as i64,
+                "<" => (lhs < rhs) as i64,
+                "<=" => (lhs <= rhs) as i64,
+                ">" => (lhs > rhs) as i64,
+                ">=" => (lhs >= rhs) as i64,
+                "<<" => lhs.wrapping_shl(rhs as u32),
+                ">>" => lhs.wrapping_shr(rhs as u32),
+                "+" => lhs.wrapping_add(rhs),
+                "-" => lhs.wrapping_sub(rhs),
+                "*" => lhs.wrapping_mul(rhs),
+                // Division by zero gives 0 rather than stopping the machine
+                "/" => lhs.checked_div(rhs).unwrap_or(0),
+                _ => lhs.checked_rem(rhs).unwrap_or(0),
+            }
+        },
+    }
+}
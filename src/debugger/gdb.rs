@@ -0,0 +1,379 @@
+/*
+    GDB remote serial protocol stub, so GDB or any tool that speaks the
+    protocol can drive the emulator over TCP.  Registers go over the wire
+    in the order a, b, c, d, e, h, l, sp, pc, flags: bytes for the 8-bit
+    ones, little-endian words for sp and pc, and the PSW byte for the flags.
+    Supports register and memory access, software breakpoints, watchpoints,
+    single step, continue and ^C.
+*/
+use std::collections::HashMap;
+use std::io;
+use std::io::prelude::*;
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use debugger::{Debugger, Machine, Stop, WatchKind};
+use vm::{ConditionCodes, VmError};
+
+// Instructions run between checks for ^C while continuing
+const POLL_INSTRUCTIONS: u64 = 10_000;
+const INTERRUPT: u8 = 0x03;
+const REGISTERS: usize = 10;
+// Signals reported in stop replies
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.rust8080.cpu">
+    <reg name="a" bitsize="8" regnum="0"/>
+    <reg name="b" bitsize="8"/>
+    <reg name="c" bitsize="8"/>
+    <reg name="d" bitsize="8"/>
+    <reg name="e" bitsize="8"/>
+    <reg name="h" bitsize="8"/>
+    <reg name="l" bitsize="8"/>
+    <reg name="sp" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="flags" bitsize="8"/>
+  </feature>
+</target>
+"#;
+
+/// Listens for one debugger connection on localhost.  Port 0 picks a free
+/// port; ask the listener which.
+pub fn listen(port: u16) -> io::Result<TcpListener> {
+    TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|i| text.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok())).collect()
+}
+
+fn number(text: &str) -> Option<usize> {
+    usize::from_str_radix(text, 16).ok()
+}
+
+/// "addr,len" as used by m, M, Z and z.
+fn range(text: &str) -> Option<(u16, usize)> {
+    let mut parts = text.splitn(2, ',');
+    let addr: usize = parts.next().and_then(number)?;
+    let len: usize = parts.next().and_then(number)?;
+    if addr > 0xffff {
+        return None;
+    }
+    Some((addr as u16, len))
+}
+
+/// One connected client.
+struct Session<'a, M: Machine + 'a> {
+    debugger: &'a mut Debugger<M>,
+    stream: TcpStream,
+    pending: Vec<u8>,     // Bytes received but not yet parsed
+    no_ack: bool,         // Client asked for QStartNoAckMode
+    breakpoints: HashMap<(u8, u16), usize>,  // (Z type, addr) to debugger id
+}
+
+impl<'a, M: Machine> Session<'a, M> {
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        if self.pending.is_empty() {
+            let mut buffer = [0u8; 4096];
+            let count: usize = self.stream.read(&mut buffer)?;
+            if count == 0 {
+                return Ok(None);
+            }
+            self.pending.extend_from_slice(&buffer[..count]);
+        }
+        Ok(Some(self.pending.remove(0)))
+    }
+    /// The next packet's contents, or None when the client hangs up.  A
+    /// bare ^C outside a packet comes back as "\x03".
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(INTERRUPT) => return Ok(Some("\x03".to_string())),
+                Some(b'$') => (),
+                // Acks, naks and line noise
+                Some(_) => continue,
+            }
+            let mut data: Vec<u8> = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    None => return Ok(None),
+                    Some(b'#') => break,
+                    Some(byte) => data.push(byte),
+                }
+            }
+            let mut checksum: Vec<u8> = Vec::new();
+            for _ in 0..2 {
+                match self.read_byte()? {
+                    None => return Ok(None),
+                    Some(byte) => checksum.push(byte),
+                }
+            }
+            let sum: u8 = data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+            let valid: bool = unhex(&String::from_utf8_lossy(&checksum)).is_some_and(|check| check == [sum]);
+            if !self.no_ack {
+                self.stream.write_all(if valid { b"+" } else { b"-" })?;
+            }
+            if valid {
+                return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+            }
+        }
+    }
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let sum: u8 = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        self.stream.write_all(format!("${}#{:02x}", data, sum).as_bytes())
+    }
+
+    fn register_bytes(&self, index: usize) -> Vec<u8> {
+        let vm = self.debugger.vm();
+        match index {
+            0 => vec![vm.a],
+            1 => vec![vm.b],
+            2 => vec![vm.c],
+            3 => vec![vm.d],
+            4 => vec![vm.e],
+            5 => vec![vm.h],
+            6 => vec![vm.l],
+            7 => vec![vm.sp as u8, (vm.sp >> 8) as u8],
+            8 => vec![vm.pc as u8, (vm.pc >> 8) as u8],
+            _ => vec![vm.condition_codes.to_psw()],
+        }
+    }
+    fn register_size(index: usize) -> usize {
+        if index == 7 || index == 8 { 2 } else { 1 }
+    }
+    fn set_register(&mut self, index: usize, bytes: &[u8]) {
+        let vm = self.debugger.vm_mut();
+        let word: u16 = bytes[0] as u16 | (*bytes.get(1).unwrap_or(&0) as u16) << 8;
+        match index {
+            0 => vm.a = bytes[0],
+            1 => vm.b = bytes[0],
+            2 => vm.c = bytes[0],
+            3 => vm.d = bytes[0],
+            4 => vm.e = bytes[0],
+            5 => vm.h = bytes[0],
+            6 => vm.l = bytes[0],
+            7 => vm.sp = word,
+            8 => vm.pc = word,
+            _ => vm.condition_codes = ConditionCodes::from_psw(bytes[0]),
+        }
+    }
+
+    fn stop_reply(&self, stop: &Stop) -> String {
+        match *stop {
+            Stop::Watchpoint { access, id, .. } => {
+                let kind: WatchKind = self.debugger.watchpoints().iter()
+                    .find(|watchpoint| watchpoint.id == id)
+                    .map_or(WatchKind::Access, |watchpoint| watchpoint.kind);
+                let name: &str = match kind {
+                    WatchKind::Write => "watch",
+                    WatchKind::Read => "rwatch",
+                    WatchKind::Access => "awatch",
+                };
+                format!("T{:02x}{}:{:04x};", SIGTRAP, name, access.addr)
+            },
+            Stop::Breakpoint { .. } => format!("T{:02x}swbreak:;", SIGTRAP),
+            Stop::Error(VmError::Halted { .. }) => format!("S{:02x}", SIGTRAP),
+            Stop::Error(_) => format!("S{:02x}", SIGILL),
+            _ => format!("S{:02x}", SIGTRAP),
+        }
+    }
+    /// Continues in slices, checking between them whether the client sent
+    /// ^C.
+    fn cont(&mut self) -> io::Result<String> {
+        let limit: u64 = self.debugger.limit;
+        self.debugger.limit = POLL_INSTRUCTIONS;
+        let reply: io::Result<String> = loop {
+            let stop: Stop = self.debugger.cont();
+            if stop != Stop::Limit {
+                break Ok(self.stop_reply(&stop));
+            }
+            self.stream.set_nonblocking(true)?;
+            let mut buffer = [0u8; 64];
+            let received: io::Result<usize> = self.stream.read(&mut buffer);
+            self.stream.set_nonblocking(false)?;
+            match received {
+                Ok(0) => break Err(io::Error::new(io::ErrorKind::UnexpectedEof, "client hung up")),
+                Ok(count) => {
+                    // Whatever came with or after the ^C is parsed later
+                    let received: &[u8] = &buffer[..count];
+                    self.pending.extend(received.iter().filter(|&&byte| byte != INTERRUPT));
+                    if received.contains(&INTERRUPT) {
+                        break Ok(format!("S{:02x}", SIGINT));
+                    }
+                },
+                Err(ref error) if error.kind() == io::ErrorKind::WouldBlock => (),
+                Err(error) => break Err(error),
+            }
+        };
+        self.debugger.limit = limit;
+        reply
+    }
+    /// Z and z: software breakpoints (type 0) and watchpoints (2 write,
+    /// 3 read, 4 access).  Hardware breakpoints (1) are not offered.
+    fn breakpoint(&mut self, insert: bool, args: &str) -> String {
+        let kind: u8 = match args.as_bytes().first() {
+            Some(kind @ b'0') | Some(kind @ b'2'..=b'4') => kind - b'0',
+            _ => return String::new(),
+        };
+        let (addr, len): (u16, usize) = match args.get(2..).and_then(range) {
+            Some(range) => range,
+            None => return "E01".to_string(),
+        };
+        if !insert {
+            if let Some(id) = self.breakpoints.remove(&(kind, addr)) {
+                self.debugger.remove(id);
+            }
+            return "OK".to_string();
+        }
+        if self.breakpoints.contains_key(&(kind, addr)) {
+            return "OK".to_string();
+        }
+        let end: u16 = addr.saturating_add(len.clamp(1, 0x10000) as u16 - 1);
+        let id: usize = match kind {
+            0 => self.debugger.add_breakpoint(addr, None),
+            2 => self.debugger.add_watchpoint(addr, end, WatchKind::Write),
+            3 => self.debugger.add_watchpoint(addr, end, WatchKind::Read),
+            _ => self.debugger.add_watchpoint(addr, end, WatchKind::Access),
+        };
+        self.breakpoints.insert((kind, addr), id);
+        "OK".to_string()
+    }
+    /// The reply to one packet, or None to end the session.
+    fn handle(&mut self, packet: &str) -> io::Result<Option<String>> {
+        let (command, args): (&str, &str) = packet.split_at(packet.chars().next().map_or(0, |c| c.len_utf8()));
+        let reply: String = match command {
+            "?" => format!("S{:02x}", SIGTRAP),
+            "\x03" => format!("S{:02x}", SIGINT),
+            "g" => (0..REGISTERS).map(|index| hex(&self.register_bytes(index))).collect(),
+            "G" => match unhex(args) {
+                Some(ref bytes) if bytes.len() == (0..REGISTERS).map(Self::register_size).sum::<usize>() => {
+                    let mut offset: usize = 0;
+                    for index in 0..REGISTERS {
+                        let size: usize = Self::register_size(index);
+                        self.set_register(index, &bytes[offset..offset + size]);
+                        offset += size;
+                    }
+                    self.debugger.reset_history();
+                    "OK".to_string()
+                },
+                _ => "E01".to_string(),
+            },
+            "p" => match number(args) {
+                Some(index) if index < REGISTERS => hex(&self.register_bytes(index)),
+                _ => "E01".to_string(),
+            },
+            "P" => {
+                let mut parts = args.splitn(2, '=');
+                match (parts.next().and_then(number), parts.next().and_then(unhex)) {
+                    (Some(index), Some(ref bytes)) if index < REGISTERS && bytes.len() == Self::register_size(index) => {
+                        self.set_register(index, bytes);
+                        self.debugger.reset_history();
+                        "OK".to_string()
+                    },
+                    _ => "E01".to_string(),
+                }
+            },
+            "m" => match range(args) {
+                Some((addr, len)) => hex(&self.debugger.vm().memory.read_range(addr, len.min(0x10000))),
+                None => "E01".to_string(),
+            },
+            "M" => {
+                let mut parts = args.splitn(2, ':');
+                match (parts.next().and_then(range), parts.next().and_then(unhex)) {
+                    (Some((addr, len)), Some(ref bytes)) if bytes.len() == len => {
+                        // Like a debugger poke: ROM can be patched too
+                        self.debugger.vm_mut().memory.load(addr, bytes);
+                        self.debugger.reset_history();
+                        "OK".to_string()
+                    },
+                    _ => "E01".to_string(),
+                }
+            },
+            "s" | "c" => {
+                if let Some(addr) = number(args) {
+                    self.debugger.vm_mut().pc = addr as u16;
+                    self.debugger.reset_history();
+                }
+                if command == "s" {
+                    let stop: Stop = self.debugger.step();
+                    self.stop_reply(&stop)
+                }
+                else {
+                    self.cont()?
+                }
+            },
+            "Z" => self.breakpoint(true, args),
+            "z" => self.breakpoint(false, args),
+            "H" | "T" => "OK".to_string(),
+            "D" => {
+                self.send("OK")?;
+                return Ok(None);
+            },
+            "k" => return Ok(None),
+            "q" | "Q" => self.query(packet),
+            // Anything else, including vCont and X, is unsupported
+            _ => String::new(),
+        };
+        Ok(Some(reply))
+    }
+    fn query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            "PacketSize=4000;qXfer:features:read+;QStartNoAckMode+;swbreak+".to_string()
+        }
+        else if packet == "QStartNoAckMode" {
+            self.no_ack = true;
+            "OK".to_string()
+        }
+        else if packet == "qAttached" {
+            "1".to_string()
+        }
+        else if packet == "qC" {
+            "QC1".to_string()
+        }
+        else if packet == "qfThreadInfo" {
+            "m1".to_string()
+        }
+        else if packet == "qsThreadInfo" {
+            "l".to_string()
+        }
+        else if let Some(rest) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            match range(rest) {
+                Some((offset, len)) => {
+                    let offset: usize = (offset as usize).min(TARGET_XML.len());
+                    let end: usize = (offset + len).min(TARGET_XML.len());
+                    let more: &str = if end < TARGET_XML.len() { "m" } else { "l" };
+                    format!("{}{}", more, &TARGET_XML[offset..end])
+                },
+                None => "E01".to_string(),
+            }
+        }
+        else {
+            String::new()
+        }
+    }
+}
+
+/// Serves one client on `stream` until it detaches, kills the session or
+/// hangs up.  The machine stays stopped while the client is not running it.
+pub fn serve<M: Machine>(debugger: &mut Debugger<M>, stream: TcpStream) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    let mut session = Session { debugger, stream, pending: Vec::new(), no_ack: false, breakpoints: HashMap::new() };
+    while let Some(packet) = session.read_packet()? {
+        match session.handle(&packet)? {
+            Some(reply) => session.send(&reply)?,
+            None => break,
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,526 @@
+/*
+    An interactive debugger around the VM: PC breakpoints with optional
+    conditions, memory watchpoints, step, step over, step out, continue and
+    run until the next interrupt.  With recording on it can also step back,
+    run backwards to a breakpoint or write watchpoint, and say who last
+    wrote an address.  Step over and step out count CALL/RST and
+    interrupt entries against RETs, so code that juggles return addresses by
+    hand can confuse them.
+*/
+use std::cell::RefCell;
+use std::fmt;
+use std::mem;
+use std::rc::Rc;
+use disassemble::{decode, Flow, OPCODES};
+use savestate;
+use savestate::{SaveState, StateError, VmState};
+use symbols::SymbolTable;
+use vm::{Event, MemoryMap, PortIo, Vm, VmError};
+
+pub mod bus;
+pub mod expr;
+pub mod gdb;
+pub mod repl;
+pub mod rewind;
+
+use self::bus::{Access, AccessLog, WatchedBus};
+use self::expr::Expr;
+use self::rewind::{History, LastWrite, Record, RewindError};
+
+// Instructions a single run command executes before giving control back
+pub const DEFAULT_LIMIT: u64 = 10_000_000;
+
+/// Something the debugger can drive one event at a time.
+pub trait Machine {
+    type Io: PortIo;
+    fn vm(&self) -> &Vm<Self::Io>;
+    fn vm_mut(&mut self) -> &mut Vm<Self::Io>;
+    /// Runs one instruction, or whatever the machine does between them.
+    fn step(&mut self) -> Result<Event, VmError>;
+    /// Adds sections for device and timing state outside the CPU and
+    /// memory, for rewinding.  Machines whose devices keep no state can
+    /// leave this and `load_devices` alone.
+    fn save_devices(&self, _state: &mut SaveState) {}
+    fn load_devices(&mut self, _state: &SaveState) -> Result<(), StateError> {
+        Ok(())
+    }
+}
+
+/// A bare CPU with no interrupt sources.
+impl<P: PortIo> Machine for Vm<P> {
+    type Io = P;
+    fn vm(&self) -> &Vm<P> {
+        self
+    }
+    fn vm_mut(&mut self) -> &mut Vm<P> {
+        self
+    }
+    fn step(&mut self) -> Result<Event, VmError> {
+        self.run_current_opcode().map(Event::Instruction)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Breakpoint {
+    pub id: usize,
+    pub addr: u16,
+    pub condition: Option<Expr>,  // Only stop when this is non-zero
+    pub enabled: bool,
+    pub hits: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Access,               // Read or write
+}
+
+impl WatchKind {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            WatchKind::Read => "read",
+            WatchKind::Write => "write",
+            WatchKind::Access => "access",
+        }
+    }
+    fn matches(&self, access: &Access) -> bool {
+        match *self {
+            WatchKind::Read => !access.write,
+            WatchKind::Write => access.write,
+            WatchKind::Access => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub id: usize,
+    pub start: u16,
+    pub end: u16,         // Inclusive
+    pub kind: WatchKind,
+    pub enabled: bool,
+    pub hits: u64,
+}
+
+/// Why a run command gave control back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    Done,                 // The step finished
+    Breakpoint { id: usize, addr: u16 },
+    /// Reported after the instruction at `pc` made the access
+    Watchpoint { id: usize, pc: u16, access: Access },
+    Interrupt { vector: u8 },
+    Limit,                // Ran the instruction limit without stopping
+    HistoryStart,         // Ran backwards to the oldest recorded event
+    RewindFailed,         // A snapshot could not be restored, so the history was dropped
+    Error(VmError),
+}
+
+impl From<RewindError> for Stop {
+    fn from(error: RewindError) -> Stop {
+        match error {
+            RewindError::Snapshot(_) => Stop::RewindFailed,
+            RewindError::Replay(error) => Stop::Error(error),
+        }
+    }
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Stop::Done => write!(f, "stepped"),
+            Stop::Breakpoint { id, addr } => write!(f, "breakpoint {} at 0x{:04x}", id, addr),
+            Stop::Watchpoint { id, pc, access } if access.write => {
+                write!(f, "watchpoint {}: 0x{:04x} written by 0x{:04x}: 0x{:02x} -> 0x{:02x}",
+                       id, access.addr, pc, access.old, access.value)
+            },
+            Stop::Watchpoint { id, pc, access } => {
+                write!(f, "watchpoint {}: 0x{:04x} read by 0x{:04x}: 0x{:02x}", id, access.addr, pc, access.value)
+            },
+            Stop::Interrupt { vector } => write!(f, "interrupt RST {}", vector),
+            Stop::Limit => write!(f, "stopped after the instruction limit"),
+            Stop::HistoryStart => write!(f, "reached the start of the recorded history"),
+            Stop::RewindFailed => write!(f, "could not restore a rewind snapshot, the history was dropped"),
+            Stop::Error(ref error) => write!(f, "{}", error),
+        }
+    }
+}
+
+// What reverse_continue found in the history
+enum ReverseHit {
+    Watch { id: usize, pc: Option<u16>, access: Access },
+    Break,                // Some breakpoint is at the instruction; its condition is still to check
+}
+
+pub struct Debugger<M: Machine> {
+    pub machine: M,
+    pub symbols: SymbolTable,
+    pub depth: i64,       // Calls and interrupts entered minus returns taken
+    pub limit: u64,       // Instructions per run command
+    breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+    next_id: usize,       // Breakpoints and watchpoints share numbers
+    log: AccessLog,       // Memory accesses made by the current event
+    history: Option<History>,  // Recorded events, when recording
+}
+
+impl<M: Machine> Debugger<M> {
+    /// Takes over `machine`, routing its memory through an access log for
+    /// the watchpoints.
+    pub fn new(mut machine: M, symbols: SymbolTable) -> Debugger<M> {
+        let log: AccessLog = Rc::new(RefCell::new(Vec::new()));
+        let memory = mem::replace(&mut machine.vm_mut().memory, Box::new(MemoryMap::new()));
+        machine.vm_mut().memory = Box::new(WatchedBus::new(memory, log.clone()));
+        Debugger {
+            machine,
+            symbols,
+            depth: 0,
+            limit: DEFAULT_LIMIT,
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            next_id: 1,
+            log,
+            history: None,
+        }
+    }
+    pub fn vm(&self) -> &Vm<M::Io> {
+        self.machine.vm()
+    }
+    pub fn vm_mut(&mut self) -> &mut Vm<M::Io> {
+        self.machine.vm_mut()
+    }
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+    /// Adds a breakpoint at `addr` and returns its number.
+    pub fn add_breakpoint(&mut self, addr: u16, condition: Option<Expr>) -> usize {
+        let id: usize = self.next_id;
+        self.next_id += 1;
+        self.breakpoints.push(Breakpoint { id, addr, condition, enabled: true, hits: 0 });
+        id
+    }
+    /// Watches `start..=end` for `kind` accesses and returns the number.
+    pub fn add_watchpoint(&mut self, start: u16, end: u16, kind: WatchKind) -> usize {
+        let id: usize = self.next_id;
+        self.next_id += 1;
+        self.watchpoints.push(Watchpoint { id, start, end, kind, enabled: true, hits: 0 });
+        id
+    }
+    /// Deletes breakpoint or watchpoint `id`.  Returns false if there is none.
+    pub fn remove(&mut self, id: usize) -> bool {
+        let count: usize = self.breakpoints.len() + self.watchpoints.len();
+        self.breakpoints.retain(|breakpoint| breakpoint.id != id);
+        self.watchpoints.retain(|watchpoint| watchpoint.id != id);
+        count != self.breakpoints.len() + self.watchpoints.len()
+    }
+    /// Enables or disables breakpoint or watchpoint `id`.
+    pub fn set_enabled(&mut self, id: usize, enabled: bool) -> bool {
+        if let Some(breakpoint) = self.breakpoints.iter_mut().find(|breakpoint| breakpoint.id == id) {
+            breakpoint.enabled = enabled;
+            return true;
+        }
+        if let Some(watchpoint) = self.watchpoints.iter_mut().find(|watchpoint| watchpoint.id == id) {
+            watchpoint.enabled = enabled;
+            return true;
+        }
+        false
+    }
+    /// Name of `addr` from the symbol table, as "name" or "name+N".
+    pub fn name_for(&self, addr: u16) -> Option<String> {
+        self.symbols.name_for(addr)
+    }
+    /// The instruction at `addr`, with symbol names for its operands.
+    pub fn instruction_at(&self, addr: u16) -> String {
+        let instruction = decode(&self.vm().memory.read_range(addr, 3), addr);
+        instruction.format_with(|target| self.symbols.name_for(target))
+    }
+
+    /// Starts or stops recording history.  Stopping forgets what was
+    /// recorded.
+    pub fn set_recording(&mut self, on: bool) {
+        if !on {
+            self.history = None;
+        }
+        else if self.history.is_none() {
+            self.history = Some(History::new(rewind::SNAPSHOT_INTERVAL, rewind::DEFAULT_CAPACITY));
+        }
+    }
+    /// Starts the history afresh, for when the machine has been changed
+    /// behind its back (registers set, a state loaded).
+    pub fn reset_history(&mut self) {
+        if let Some(ref mut history) = self.history {
+            *history = History::new(history.interval, history.capacity);
+        }
+    }
+    pub fn history(&self) -> Option<&History> {
+        self.history.as_ref()
+    }
+    fn snapshot(&self) -> SaveState {
+        let mut state = SaveState::new("rewind");
+        savestate::save_vm(self.vm(), &mut state);
+        self.machine.save_devices(&mut state);
+        state
+    }
+
+    /// Runs one machine event, keeping the call depth and history up to
+    /// date, and returns it with the watchpoint it tripped, if any.
+    fn advance(&mut self) -> Result<(Event, Option<Stop>), VmError> {
+        self.log.borrow_mut().clear();
+        if self.history.as_ref().is_some_and(History::wants_snapshot) {
+            let snapshot: SaveState = self.snapshot();
+            if let Some(ref mut history) = self.history {
+                history.add_snapshot(snapshot);
+            }
+        }
+        let sp: u16 = self.vm().sp;
+        let depth: i64 = self.depth;
+        let event: Event = self.machine.step()?;
+        // Opcode fetches are not data accesses
+        let mut fetch: (u16, u16) = (0, 0);
+        match event {
+            Event::Instruction(outcome) => {
+                let info = OPCODES[outcome.opcode as usize];
+                fetch = (outcome.pc, info.length as u16);
+                let after: u16 = self.vm().sp;
+                match info.mnemonic.flow() {
+                    Flow::Call | Flow::Restart => self.depth += 1,
+                    Flow::ConditionalCall if after == sp.wrapping_sub(2) => self.depth += 1,
+                    Flow::Return => self.depth -= 1,
+                    Flow::ConditionalReturn if after == sp.wrapping_add(2) => self.depth -= 1,
+                    _ => (),
+                }
+            },
+            Event::Interrupt { accepted: true, .. } => self.depth += 1,
+            _ => (),
+        }
+        let pc: u16 = match event {
+            Event::Instruction(outcome) => outcome.pc,
+            _ => self.vm().pc,
+        };
+        let log: Vec<Access> = self.log.borrow_mut().drain(..).collect();
+        if let Some(ref mut history) = self.history {
+            let pc: Option<u16> = match event {
+                Event::Instruction(outcome) => Some(outcome.pc),
+                _ => None,
+            };
+            let writes: Vec<Access> = log.iter().filter(|access| access.write).cloned().collect();
+            history.push(Record { pc, depth, writes });
+        }
+        for access in log {
+            if !access.write && access.addr.wrapping_sub(fetch.0) < fetch.1 {
+                continue;
+            }
+            let hit = self.watchpoints.iter_mut().find(|watchpoint| {
+                watchpoint.enabled && watchpoint.kind.matches(&access) &&
+                    watchpoint.start <= access.addr && access.addr <= watchpoint.end
+            });
+            if let Some(watchpoint) = hit {
+                watchpoint.hits += 1;
+                return Ok((event, Some(Stop::Watchpoint { id: watchpoint.id, pc, access })));
+            }
+        }
+        Ok((event, None))
+    }
+    /// Puts the machine back as it was before recorded event `index` and
+    /// forgets the history after it.  Indexes outside the history are
+    /// ignored.  Should restoring a snapshot or replaying fail, the machine
+    /// is left where it stopped and the history starts again from there.
+    pub fn rewind_to(&mut self, index: u64) -> Result<(), RewindError> {
+        if self.history.as_ref().is_none_or(|history| index >= history.position()) {
+            return Ok(());
+        }
+        let mut history: History = match self.history.take() {
+            Some(history) => history,
+            None => return Ok(()),
+        };
+        if let Some(&(at, ref snapshot)) = history.snapshot_before(index) {
+            let replayed: Result<(), RewindError> = self.replay(snapshot, index - at);
+            self.log.borrow_mut().clear();
+            if let Err(error) = replayed {
+                self.history = Some(History::new(history.interval, history.capacity));
+                return Err(error);
+            }
+            if let Some(record) = history.record(index) {
+                self.depth = record.depth;
+            }
+            history.truncate(index);
+        }
+        self.history = Some(history);
+        Ok(())
+    }
+    /// Restores `snapshot` and runs `events` events on from it.
+    fn replay(&mut self, snapshot: &SaveState, events: u64) -> Result<(), RewindError> {
+        let state: VmState = VmState::read(snapshot)?;
+        state.apply(self.machine.vm_mut());
+        self.machine.load_devices(snapshot)?;
+        for _ in 0..events {
+            self.machine.step()?;
+        }
+        Ok(())
+    }
+    /// Goes back to just before the last instruction run.
+    pub fn step_back(&mut self) -> Stop {
+        let target: Option<u64> = self.history.as_ref().and_then(|history| {
+            history.back_from(history.position()).find(|(_, record)| record.pc.is_some()).map(|(index, _)| index)
+        });
+        match target {
+            Some(index) => match self.rewind_to(index) {
+                Ok(()) => Stop::Done,
+                Err(error) => error.into(),
+            },
+            None => Stop::HistoryStart,
+        }
+    }
+    /// What running backwards from before event `position` meets first: a
+    /// write watchpoint tripped by event N (stopping after it, at N + 1) or
+    /// a breakpoint at event N's instruction (stopping before it, at N).
+    /// Read watchpoints only work forwards.
+    fn reverse_hit(&self, position: u64) -> Option<(u64, ReverseHit)> {
+        let history: &History = self.history.as_ref()?;
+        for (index, record) in history.back_from(position) {
+            if index + 1 < position {
+                for access in record.writes.iter().rev() {
+                    let hit = self.watchpoints.iter().find(|watchpoint| {
+                        watchpoint.enabled && watchpoint.kind != WatchKind::Read &&
+                            watchpoint.start <= access.addr && access.addr <= watchpoint.end
+                    });
+                    if let Some(watchpoint) = hit {
+                        return Some((index + 1, ReverseHit::Watch { id: watchpoint.id, pc: record.pc, access: *access }));
+                    }
+                }
+            }
+            if let Some(pc) = record.pc {
+                if self.breakpoints.iter().any(|breakpoint| breakpoint.enabled && breakpoint.addr == pc) {
+                    return Some((index, ReverseHit::Break));
+                }
+            }
+        }
+        None
+    }
+    /// Runs backwards to the most recent breakpoint or write watchpoint, or
+    /// to the start of the history.
+    pub fn reverse_continue(&mut self) -> Stop {
+        let mut position: u64 = match self.history {
+            Some(ref history) => history.position(),
+            None => return Stop::HistoryStart,
+        };
+        while let Some((index, hit)) = self.reverse_hit(position) {
+            if let Err(error) = self.rewind_to(index) {
+                return error.into();
+            }
+            match hit {
+                ReverseHit::Watch { id, pc, access } => {
+                    if let Some(watchpoint) = self.watchpoints.iter_mut().find(|watchpoint| watchpoint.id == id) {
+                        watchpoint.hits += 1;
+                    }
+                    // Writes without an instruction are an interrupt's push
+                    let pc: u16 = pc.unwrap_or(self.vm().pc);
+                    return Stop::Watchpoint { id, pc, access };
+                },
+                // Conditions can only be checked once the machine is back there
+                ReverseHit::Break => match self.breakpoint_hit() {
+                    Some(stop) => return stop,
+                    None => position = index,
+                },
+            }
+        }
+        let start: u64 = self.history.as_ref().map_or(0, History::start);
+        match self.rewind_to(start) {
+            Ok(()) => Stop::HistoryStart,
+            Err(error) => error.into(),
+        }
+    }
+    /// The most recent recorded write to `addr`.
+    pub fn last_write(&self, addr: u16) -> Option<LastWrite> {
+        self.history.as_ref().and_then(|history| history.last_write(addr))
+    }
+
+    /// The breakpoint at the current PC whose condition holds, if any.
+    fn breakpoint_hit(&mut self) -> Option<Stop> {
+        let vm = self.machine.vm();
+        let hit = self.breakpoints.iter_mut().find(|breakpoint| {
+            breakpoint.enabled && breakpoint.addr == vm.pc &&
+                breakpoint.condition.as_ref().is_none_or(|condition| condition.evaluate(vm) != 0)
+        });
+        hit.map(|breakpoint| {
+            breakpoint.hits += 1;
+            Stop::Breakpoint { id: breakpoint.id, addr: breakpoint.addr }
+        })
+    }
+    /// Runs until `done` says to stop after an event, a breakpoint or
+    /// watchpoint hits, or the instruction limit runs out.  The instruction
+    /// at the current PC always runs, so a run can leave a breakpoint.
+    fn run<F: FnMut(&Self, &Event) -> Option<Stop>>(&mut self, mut done: F) -> Stop {
+        let mut executed: u64 = 0;
+        loop {
+            let (event, watched) = match self.advance() {
+                Ok(result) => result,
+                Err(error) => return Stop::Error(error),
+            };
+            if let Some(stop) = watched {
+                return stop;
+            }
+            if let Some(stop) = done(self, &event) {
+                return stop;
+            }
+            match event {
+                Event::Instruction(_) => executed += 1,
+                Event::Interrupt { accepted: true, .. } => (),
+                _ => continue,
+            }
+            if let Some(stop) = self.breakpoint_hit() {
+                return stop;
+            }
+            if executed >= self.limit {
+                return Stop::Limit;
+            }
+        }
+    }
+    /// Executes one instruction, entering any interrupt that is due first.
+    pub fn step(&mut self) -> Stop {
+        self.run(|_, event| match *event {
+            Event::Instruction(_) => Some(Stop::Done),
+            _ => None,
+        })
+    }
+    /// Executes one instruction, running any call or restart it makes to
+    /// its return.
+    pub fn step_over(&mut self) -> Stop {
+        let depth: i64 = self.depth;
+        self.run(|debugger, event| match *event {
+            Event::Instruction(_) if debugger.depth <= depth => Some(Stop::Done),
+            _ => None,
+        })
+    }
+    /// Runs until the current subroutine returns.
+    pub fn step_out(&mut self) -> Stop {
+        let depth: i64 = self.depth;
+        self.run(|debugger, event| match *event {
+            Event::Instruction(_) if debugger.depth < depth => Some(Stop::Done),
+            _ => None,
+        })
+    }
+    /// Runs until a breakpoint, a watchpoint, an error or the limit.
+    pub fn cont(&mut self) -> Stop {
+        self.run(|_, _| None)
+    }
+    /// Runs to the end of the machine's current video frame.  Machines
+    /// without frames run to the instruction limit.
+    pub fn run_frame(&mut self) -> Stop {
+        self.run(|_, event| match *event {
+            Event::Frame => Some(Stop::Done),
+            _ => None,
+        })
+    }
+    /// Runs until the CPU accepts an interrupt, stopping at its vector.
+    pub fn run_until_interrupt(&mut self) -> Stop {
+        self.run(|_, event| match *event {
+            Event::Interrupt { vector, accepted: true } => Some(Stop::Interrupt { vector }),
+            _ => None,
+        })
+    }
+}
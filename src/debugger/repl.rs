@@ -0,0 +1,376 @@
+/*
+    Line-oriented debugger commands, gdb flavoured.  Arguments that take an
+    address or value accept debugger expressions, so `b DrawSprite`,
+    `x hl 16` and `b $0a5f if a == 3` all work.  An empty line repeats the
+    previous command.
+*/
+use std::io;
+use std::io::prelude::*;
+use debugger::{Debugger, Machine, Stop, WatchKind};
+use debugger::expr::Expr;
+use disassemble::OPCODES;
+
+const PROMPT: &str = "(8080) ";
+const HEX_PER_LINE: usize = 16;
+// Default lengths for x and dis
+const DUMP_BYTES: usize = 64;
+const LIST_INSTRUCTIONS: usize = 10;
+// Longest x or dis: the whole address space
+const MAX_LEN: u64 = 0x10000;
+
+pub const HELP: &str = "\
+step|s [N]             execute N instructions (default 1)
+next|n                 step over calls and restarts
+finish|out             run until the current subroutine returns
+continue|c [N]         run until a breakpoint or watchpoint, at most N instructions
+interrupt|int          run until the next interrupt is taken
+record [off]           start (or stop) recording history for running backwards
+reverse-step|rs [N]    go back N instructions (default 1)
+reverse-continue|rc    run backwards to a breakpoint or write watchpoint
+whowrote|ww ADDR       show the last recorded write to ADDR
+break|b ADDR [if EXPR] stop at ADDR, optionally only when EXPR is non-zero
+watch|rwatch|awatch ADDR [LEN]
+                       stop on writes, reads or any access to ADDR..ADDR+LEN-1
+delete|d ID            delete a breakpoint or watchpoint
+enable ID, disable ID
+info|i                 list breakpoints and watchpoints
+regs|r                 show registers and flags
+x ADDR [LEN]           dump memory
+dis|l [ADDR] [N]       disassemble N instructions (default at PC)
+print|p EXPR           evaluate an expression
+set REG EXPR           set a register, register pair or flag
+quit|q
+";
+
+fn parse_value<M: Machine>(debugger: &Debugger<M>, text: &str) -> Result<i64, String> {
+    Ok(Expr::parse(text, &debugger.symbols)?.evaluate(debugger.vm()))
+}
+
+fn parse_addr<M: Machine>(debugger: &Debugger<M>, text: &str) -> Result<u16, String> {
+    let value: i64 = parse_value(debugger, text)?;
+    if !(0..=0xffff).contains(&value) {
+        return Err(format!("address out of range: {}", value));
+    }
+    Ok(value as u16)
+}
+
+fn parse_count(text: Option<&&str>, default: u64) -> Result<u64, String> {
+    match text {
+        Some(text) => text.parse().map_err(|_| format!("bad count `{}`", text)),
+        None => Ok(default),
+    }
+}
+
+fn parse_id(text: Option<&&str>) -> Result<usize, String> {
+    let text: &str = text.ok_or("missing breakpoint number")?;
+    text.parse().map_err(|_| format!("bad breakpoint number `{}`", text))
+}
+
+/// "0x1a32 <name+2>", or just the address when no symbol covers it.
+fn location<M: Machine>(debugger: &Debugger<M>, addr: u16) -> String {
+    match debugger.name_for(addr) {
+        Some(name) => format!("0x{:04x} <{}>", addr, name),
+        None => format!("0x{:04x}", addr),
+    }
+}
+
+/// The instruction about to run, as shown after every stop.
+pub fn current<M: Machine>(debugger: &Debugger<M>) -> String {
+    let pc: u16 = debugger.vm().pc;
+    format!("=> {}  {}", location(debugger, pc), debugger.instruction_at(pc))
+}
+
+/// How a run command ended, followed by the next instruction.
+pub fn describe<M: Machine>(debugger: &Debugger<M>, stop: &Stop) -> String {
+    let reason: String = match *stop {
+        Stop::Done => String::new(),
+        Stop::Breakpoint { id, addr } => format!("breakpoint {} at {}\n", id, location(debugger, addr)),
+        Stop::Watchpoint { access, .. } => {
+            let name: String = debugger.name_for(access.addr).map(|name| format!(" <{}>", name)).unwrap_or_default();
+            format!("{}{}\n", stop, name)
+        },
+        _ => format!("{}\n", stop),
+    };
+    format!("{}{}", reason, current(debugger))
+}
+
+pub fn registers<M: Machine>(debugger: &Debugger<M>) -> String {
+    let vm = debugger.vm();
+    let flags = &vm.condition_codes;
+    format!("pc 0x{:04x}  sp 0x{:04x}  a 0x{:02x}  bc 0x{:02x}{:02x}  de 0x{:02x}{:02x}  hl 0x{:02x}{:02x}\n\
+             z {}  s {}  p {}  cy {}  ac {}  int {}  cycles {}  depth {}",
+            vm.pc, vm.sp, vm.a, vm.b, vm.c, vm.d, vm.e, vm.h, vm.l,
+            flags.z, flags.s, flags.p, flags.cy, flags.ac,
+            if vm.int_enable != 0 { "on" } else { "off" }, vm.cycles, debugger.depth)
+}
+
+fn dump<M: Machine>(debugger: &Debugger<M>, addr: u16, len: usize) -> String {
+    let bytes: Vec<u8> = debugger.vm().memory.read_range(addr, len);
+    let mut text = String::new();
+    for (line, chunk) in bytes.chunks(HEX_PER_LINE).enumerate() {
+        let start: u16 = addr.wrapping_add((line * HEX_PER_LINE) as u16);
+        let hex: Vec<String> = chunk.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let ascii: String = chunk.iter()
+            .map(|&byte| if (0x20..0x7f).contains(&byte) { byte as char } else { '.' })
+            .collect();
+        text.push_str(&format!("0x{:04x}  {:<48}  {}", start, hex.join(" "), ascii));
+        if let Some(name) = debugger.symbols.get(start).map(|symbol| symbol.name.clone()) {
+            text.push_str(&format!("  <{}>", name));
+        }
+        text.push('\n');
+    }
+    text
+}
+
+fn list<M: Machine>(debugger: &Debugger<M>, mut addr: u16, count: usize) -> String {
+    let mut text = String::new();
+    for _ in 0..count {
+        let marker: &str = if addr == debugger.vm().pc { "=>" } else { "  " };
+        if let Some(symbol) = debugger.symbols.get(addr) {
+            text.push_str(&format!("{}:\n", symbol.name));
+        }
+        text.push_str(&format!("{} 0x{:04x}  {}\n", marker, addr, debugger.instruction_at(addr)));
+        addr = addr.wrapping_add(decode_length(debugger, addr));
+    }
+    text
+}
+
+fn decode_length<M: Machine>(debugger: &Debugger<M>, addr: u16) -> u16 {
+    OPCODES[debugger.vm().memory.read_range(addr, 1)[0] as usize].length as u16
+}
+
+fn info<M: Machine>(debugger: &Debugger<M>) -> String {
+    let mut text = String::new();
+    for breakpoint in debugger.breakpoints() {
+        text.push_str(&format!("{:<3} break  {:<8} {}  hits {}", breakpoint.id,
+                               if breakpoint.enabled { "on" } else { "off" },
+                               location(debugger, breakpoint.addr), breakpoint.hits));
+        if let Some(ref condition) = breakpoint.condition {
+            text.push_str(&format!("  if {}", condition.text));
+        }
+        text.push('\n');
+    }
+    for watchpoint in debugger.watchpoints() {
+        text.push_str(&format!("{:<3} {:<6} {:<8} {}..0x{:04x}  hits {}\n", watchpoint.id,
+                               watchpoint.kind.name(), if watchpoint.enabled { "on" } else { "off" },
+                               location(debugger, watchpoint.start), watchpoint.end, watchpoint.hits));
+    }
+    if text.is_empty() {
+        text.push_str("no breakpoints or watchpoints\n");
+    }
+    text
+}
+
+fn set_register<M: Machine>(debugger: &mut Debugger<M>, register: &str, value: i64) -> Result<(), String> {
+    let vm = debugger.vm_mut();
+    let (byte, word): (u8, u16) = (value as u8, value as u16);
+    match register.to_ascii_lowercase().as_str() {
+        "a" => vm.a = byte,
+        "b" => vm.b = byte,
+        "c" => vm.c = byte,
+        "d" => vm.d = byte,
+        "e" => vm.e = byte,
+        "h" => vm.h = byte,
+        "l" => vm.l = byte,
+        "bc" => { vm.b = (word >> 8) as u8; vm.c = byte; },
+        "de" => { vm.d = (word >> 8) as u8; vm.e = byte; },
+        "hl" => { vm.h = (word >> 8) as u8; vm.l = byte; },
+        "sp" => vm.sp = word,
+        "pc" => vm.pc = word,
+        "z" => vm.condition_codes.z = (value != 0) as u8,
+        "s" => vm.condition_codes.s = (value != 0) as u8,
+        "p" => vm.condition_codes.p = (value != 0) as u8,
+        "cy" => vm.condition_codes.cy = (value != 0) as u8,
+        "ac" => vm.condition_codes.ac = (value != 0) as u8,
+        _ => return Err(format!("unknown register `{}`", register)),
+    }
+    Ok(())
+}
+
+/// Runs one command line and returns what it prints.
+pub fn execute<M: Machine>(debugger: &mut Debugger<M>, line: &str) -> Result<String, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let command: &str = match words.first() {
+        Some(command) => command,
+        None => return Ok(String::new()),
+    };
+    let args: &[&str] = &words[1..];
+    match command {
+        "help" | "h" | "?" => Ok(HELP.to_string()),
+        "step" | "s" => {
+            let mut stop: Stop = Stop::Done;
+            for _ in 0..parse_count(args.first(), 1)? {
+                stop = debugger.step();
+                if stop != Stop::Done {
+                    break;
+                }
+            }
+            Ok(describe(debugger, &stop))
+        },
+        "next" | "n" => {
+            let stop: Stop = debugger.step_over();
+            Ok(describe(debugger, &stop))
+        },
+        "finish" | "out" => {
+            let stop: Stop = debugger.step_out();
+            Ok(describe(debugger, &stop))
+        },
+        "continue" | "c" => {
+            let limit: u64 = debugger.limit;
+            debugger.limit = parse_count(args.first(), limit)?;
+            let stop: Stop = debugger.cont();
+            debugger.limit = limit;
+            Ok(describe(debugger, &stop))
+        },
+        "interrupt" | "int" => {
+            let stop: Stop = debugger.run_until_interrupt();
+            Ok(describe(debugger, &stop))
+        },
+        "record" => {
+            let on: bool = match args.first() {
+                None | Some(&"on") => true,
+                Some(&"off") | Some(&"stop") => false,
+                Some(text) => return Err(format!("record takes on or off, not `{}`", text)),
+            };
+            debugger.set_recording(on);
+            Ok(if on { "recording\n" } else { "not recording\n" }.to_string())
+        },
+        "reverse-step" | "rs" => {
+            if debugger.history().is_none() {
+                return Err("not recording, try record".to_string());
+            }
+            let mut stop: Stop = Stop::Done;
+            for _ in 0..parse_count(args.first(), 1)? {
+                stop = debugger.step_back();
+                if stop != Stop::Done {
+                    break;
+                }
+            }
+            Ok(describe(debugger, &stop))
+        },
+        "reverse-continue" | "rc" => {
+            if debugger.history().is_none() {
+                return Err("not recording, try record".to_string());
+            }
+            let stop: Stop = debugger.reverse_continue();
+            Ok(describe(debugger, &stop))
+        },
+        "whowrote" | "ww" => {
+            let addr: u16 = parse_addr(debugger, args.first().ok_or("whowrote needs an address")?)?;
+            if debugger.history().is_none() {
+                return Err("not recording, try record".to_string());
+            }
+            Ok(match debugger.last_write(addr) {
+                Some(write) => {
+                    let by: String = match write.pc {
+                        Some(pc) => format!("{}  {}", location(debugger, pc), debugger.instruction_at(pc)),
+                        None => "an interrupt".to_string(),
+                    };
+                    format!("0x{:02x} -> 0x{:02x} at event {} by {}\n", write.old, write.value, write.index, by)
+                },
+                None => format!("no recorded write to {}\n", location(debugger, addr)),
+            })
+        },
+        "break" | "b" => {
+            // The condition is the rest of the line, spaces and all
+            let rest: &str = line.trim_start()[command.len()..].trim();
+            let (addr, condition): (&str, Option<&str>) = match rest.find(" if ") {
+                Some(split) => (&rest[..split], Some(&rest[split + 4..])),
+                None => (rest, None),
+            };
+            if addr.is_empty() {
+                return Err("break needs an address".to_string());
+            }
+            let addr: u16 = parse_addr(debugger, addr)?;
+            let condition: Option<Expr> = match condition {
+                Some(text) => Some(Expr::parse(text, &debugger.symbols)?),
+                None => None,
+            };
+            let id: usize = debugger.add_breakpoint(addr, condition);
+            Ok(format!("breakpoint {} at {}\n", id, location(debugger, addr)))
+        },
+        "watch" | "rwatch" | "awatch" | "w" => {
+            let kind: WatchKind = match command {
+                "rwatch" => WatchKind::Read,
+                "awatch" => WatchKind::Access,
+                _ => WatchKind::Write,
+            };
+            let start: u16 = parse_addr(debugger, args.first().ok_or("watch needs an address")?)?;
+            let len: u64 = parse_count(args.get(1), 1)?.max(1);
+            let end: u16 = start.saturating_add((len - 1).min(0xffff) as u16);
+            let id: usize = debugger.add_watchpoint(start, end, kind);
+            Ok(format!("{} watchpoint {} on {}..0x{:04x}\n", kind.name(), id, location(debugger, start), end))
+        },
+        "delete" | "d" => {
+            let id: usize = parse_id(args.first())?;
+            if debugger.remove(id) { Ok(String::new()) } else { Err(format!("no breakpoint {}", id)) }
+        },
+        "enable" | "disable" => {
+            let id: usize = parse_id(args.first())?;
+            if debugger.set_enabled(id, command == "enable") { Ok(String::new()) } else { Err(format!("no breakpoint {}", id)) }
+        },
+        "info" | "i" => Ok(info(debugger)),
+        "regs" | "r" => Ok(format!("{}\n{}\n", registers(debugger), current(debugger))),
+        "x" => {
+            let addr: u16 = parse_addr(debugger, args.first().ok_or("x needs an address")?)?;
+            let len: u64 = parse_count(args.get(1), DUMP_BYTES as u64)?;
+            if len > MAX_LEN {
+                return Err(format!("length {} is more than the 0x{:x} byte address space", len, MAX_LEN));
+            }
+            Ok(dump(debugger, addr, len as usize))
+        },
+        "dis" | "l" => {
+            let addr: u16 = match args.first() {
+                Some(text) => parse_addr(debugger, text)?,
+                None => debugger.vm().pc,
+            };
+            let count: u64 = parse_count(args.get(1), LIST_INSTRUCTIONS as u64)?;
+            if count > MAX_LEN {
+                return Err(format!("count {} is more than the 0x{:x} byte address space", count, MAX_LEN));
+            }
+            Ok(list(debugger, addr, count as usize))
+        },
+        "print" | "p" => {
+            let value: i64 = parse_value(debugger, line.trim_start()[command.len()..].trim())?;
+            Ok(format!("0x{:x} ({})\n", value, value))
+        },
+        "set" => {
+            let register: &str = args.first().ok_or("set needs a register")?;
+            let value: i64 = parse_value(debugger, &args[1..].join(" "))?;
+            set_register(debugger, register, value)?;
+            // The recorded past no longer leads here
+            debugger.reset_history();
+            Ok(String::new())
+        },
+        _ => Err(format!("unknown command `{}`, try help", command)),
+    }
+}
+
+/// Reads commands from `input` until it ends or says quit.
+pub fn run<M: Machine, R: BufRead, W: Write>(debugger: &mut Debugger<M>, input: &mut R, output: &mut W)
+                                             -> io::Result<()> {
+    writeln!(output, "{}", current(debugger))?;
+    let mut previous: String = String::new();
+    loop {
+        write!(output, "{}", PROMPT)?;
+        output.flush()?;
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        let line: String = if line.trim().is_empty() { previous.clone() } else { line.trim().to_string() };
+        if line == "quit" || line == "q" {
+            return Ok(());
+        }
+        match execute(debugger, &line) {
+            Ok(text) => {
+                write!(output, "{}", text)?;
+                if !text.is_empty() && !text.ends_with('\n') {
+                    writeln!(output)?;
+                }
+            },
+            Err(message) => writeln!(output, "error: {}", message)?,
+        }
+        previous = line;
+    }
+}
@@ -0,0 +1,142 @@
+/*
+    Execution history for stepping backwards.  Every event the debugger
+    runs leaves a record of where it ran and which bytes it wrote; every
+    `interval` events a full snapshot of the machine is kept as well.  Going
+    back to an earlier event restores the nearest snapshot before it and
+    replays forward, which is exact because the machine is deterministic
+    while the debugger holds it.
+*/
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use debugger::bus::Access;
+use savestate::{SaveState, StateError};
+use vm::VmError;
+
+// Events between snapshots: bounds the replay needed to go back one step
+pub const SNAPSHOT_INTERVAL: u64 = 10_000;
+// Events kept before the oldest are dropped
+pub const DEFAULT_CAPACITY: usize = 1_000_000;
+
+/// What one event did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub pc: Option<u16>,      // Address of the instruction, None for interrupts and the like
+    pub depth: i64,           // Call depth before the event
+    pub writes: Vec<Access>,
+}
+
+/// The most recent write to an address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LastWrite {
+    pub index: u64,           // Event that made it
+    pub pc: Option<u16>,      // Instruction that made it, None for an interrupt's push
+    pub old: u8,
+    pub value: u8,
+}
+
+/// Why going back failed.  Either way the history is lost and starts
+/// again from wherever the machine was left.
+#[derive(Debug)]
+pub enum RewindError {
+    Snapshot(StateError),     // A snapshot could not be restored
+    Replay(VmError),          // Running forward from the snapshot failed
+}
+
+impl fmt::Display for RewindError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RewindError::Snapshot(ref error) => write!(f, "rewind snapshot: {}", error),
+            RewindError::Replay(ref error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl Error for RewindError {}
+
+impl From<StateError> for RewindError {
+    fn from(error: StateError) -> RewindError {
+        RewindError::Snapshot(error)
+    }
+}
+
+impl From<VmError> for RewindError {
+    fn from(error: VmError) -> RewindError {
+        RewindError::Replay(error)
+    }
+}
+
+pub struct History {
+    pub interval: u64,
+    pub capacity: usize,
+    first: u64,                           // Index of records[0]
+    records: VecDeque<Record>,
+    snapshots: VecDeque<(u64, SaveState)>,  // State before event N, oldest first
+}
+
+impl History {
+    pub fn new(interval: u64, capacity: usize) -> History {
+        History { interval: interval.max(1), capacity, first: 0, records: VecDeque::new(), snapshots: VecDeque::new() }
+    }
+    /// Index of the next event to run.
+    pub fn position(&self) -> u64 {
+        self.first + self.records.len() as u64
+    }
+    /// Index of the earliest event that can be gone back to.
+    pub fn start(&self) -> u64 {
+        self.first
+    }
+    pub fn record(&self, index: u64) -> Option<&Record> {
+        index.checked_sub(self.first).and_then(|offset| self.records.get(offset as usize))
+    }
+    /// Whether a snapshot is due before the next event.
+    pub fn wants_snapshot(&self) -> bool {
+        let position: u64 = self.position();
+        self.snapshots.back().is_none_or(|&(index, _)| position >= index + self.interval)
+    }
+    pub fn add_snapshot(&mut self, state: SaveState) {
+        let position: u64 = self.position();
+        if self.snapshots.is_empty() {
+            self.first = position;
+        }
+        self.snapshots.push_back((position, state));
+    }
+    /// Adds the record of the event just run, dropping the oldest stretch
+    /// of history when over capacity.
+    pub fn push(&mut self, record: Record) {
+        self.records.push_back(record);
+        while self.records.len() > self.capacity && self.snapshots.len() > 1 {
+            self.snapshots.pop_front();
+            let start: u64 = self.snapshots[0].0;
+            let dropped: usize = (start - self.first) as usize;
+            self.records.drain(..dropped);
+            self.first = start;
+        }
+    }
+    /// The latest snapshot at or before event `index`.
+    pub fn snapshot_before(&self, index: u64) -> Option<&(u64, SaveState)> {
+        self.snapshots.iter().rev().find(|&&(at, _)| at <= index)
+    }
+    /// Forgets everything from event `index` on, ready to record again
+    /// from there.
+    pub fn truncate(&mut self, index: u64) {
+        self.records.truncate(index.saturating_sub(self.first) as usize);
+        while self.snapshots.back().is_some_and(|&(at, _)| at > index) {
+            self.snapshots.pop_back();
+        }
+    }
+    /// Events before `before`, newest first, with their indexes.
+    pub fn back_from(&self, before: u64) -> impl Iterator<Item = (u64, &Record)> {
+        let end: usize = before.saturating_sub(self.first).min(self.records.len() as u64) as usize;
+        let first: u64 = self.first;
+        self.records.range(..end).enumerate().rev().map(move |(offset, record)| (first + offset as u64, record))
+    }
+    /// Who last wrote `addr`, as far back as the history goes.
+    pub fn last_write(&self, addr: u16) -> Option<LastWrite> {
+        self.back_from(self.position()).find_map(|(index, record)| {
+            record.writes.iter().rev().find(|access| access.addr == addr).map(|access| {
+                LastWrite { index, pc: record.pc, old: access.old, value: access.value }
+            })
+        })
+    }
+}
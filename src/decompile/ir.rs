@@ -0,0 +1,353 @@
+/*
+    The intermediate form: each basic block becomes assignments and calls on
+    C-like variables, ending in a terminator.  8-bit registers are a..l,
+    register pairs used as a whole are the 16-bit variables bc, de, hl and sp,
+    and the flags are carry, zero, sign and parity.
+
+    When a branch tests flags whose meaning is still known (nothing since
+    the flag setter has changed the values involved), the test is folded
+    into a comparison: CPI 5 / JZ becomes a == 0x05 and DCR B / JNZ becomes
+    b != 0.  Otherwise the condition names the flag.
+*/
+use disassemble::{Flow, Instruction, Mnemonic, Operand, Register, RegisterPair};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Var(&'static str),
+    Const(u16),
+    Addr(u16),                                    // An immediate used as an address
+    Mem(Box<Expr>),                               // Byte at an address
+    MemWord(Box<Expr>),                           // Little-endian word at an address
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    Intrinsic(&'static str, Vec<Expr>),           // in(), rlc(), daa(), pop(), ...
+}
+
+impl Expr {
+    fn binary(op: &'static str, lhs: Expr, rhs: Expr) -> Expr {
+        Expr::Binary(op, Box::new(lhs), Box::new(rhs))
+    }
+    /// The condition with its sense reversed.
+    pub fn negate(self) -> Expr {
+        match self {
+            Expr::Binary(op, lhs, rhs) => {
+                let inverse: &'static str = match op {
+                    "==" => "!=", "!=" => "==", "<" => ">=", ">=" => "<", ">" => "<=", "<=" => ">",
+                    _ => return Expr::Unary("!", Box::new(Expr::Binary(op, lhs, rhs))),
+                };
+                Expr::Binary(inverse, lhs, rhs)
+            },
+            Expr::Unary("!", inner) => *inner,
+            other => Expr::Unary("!", Box::new(other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stmt {
+    Assign(Expr, Expr),
+    Exchange(Expr, Expr),                         // XCHG, XTHL
+    Eval(Expr),                                   // An intrinsic for its side effects
+    Call(u16),
+    CallIf(Expr, u16),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Terminator {
+    Next,                                         // Falls into the following block
+    Jump(u16),
+    Branch(Expr, u16),                            // Jumps when the condition holds
+    Return,
+    ReturnIf(Expr),
+    Indirect(Expr),                               // PCHL
+}
+
+#[derive(Debug, Clone)]
+pub struct Block {
+    pub start: u16,
+    pub stmts: Vec<Stmt>,
+    pub terminator: Terminator,
+}
+
+/// What the flags describe after a flag-setting instruction.
+#[derive(Debug, Clone)]
+enum FlagSource {
+    Compare(Expr, Expr),          // CMP/CPI: the flags of lhs - rhs
+    Result(Expr),                 // An 8-bit result left in a register
+}
+
+fn reg(r: Register) -> Expr {
+    match r {
+        Register::B => Expr::Var("b"),
+        Register::C => Expr::Var("c"),
+        Register::D => Expr::Var("d"),
+        Register::E => Expr::Var("e"),
+        Register::H => Expr::Var("h"),
+        Register::L => Expr::Var("l"),
+        Register::M => Expr::Mem(Box::new(Expr::Var("hl"))),
+        Register::A => Expr::Var("a"),
+    }
+}
+
+fn pair(rp: RegisterPair) -> Expr {
+    Expr::Var(match rp {
+        RegisterPair::B => "bc",
+        RegisterPair::D => "de",
+        RegisterPair::H => "hl",
+        RegisterPair::SP => "sp",
+        RegisterPair::PSW => "psw",
+    })
+}
+
+/// The value of an operand other than a register.
+fn value(operand: &Operand) -> Expr {
+    match *operand {
+        Operand::Reg(r) => reg(r),
+        Operand::Pair(rp) => pair(rp),
+        Operand::Imm8(v) | Operand::Port(v) | Operand::Vector(v) => Expr::Const(v as u16),
+        Operand::Imm16(v) => Expr::Addr(v),
+        Operand::Addr(v) => Expr::Addr(v),
+    }
+}
+
+/// Flag and sense tested by a condition code, numbered as in opcodes.
+fn condition(opcode: u8) -> (&'static str, bool) {
+    match (opcode >> 3) & 0x07 {
+        0 => ("zero", false),
+        1 => ("zero", true),
+        2 => ("carry", false),
+        3 => ("carry", true),
+        4 => ("parity", false),
+        5 => ("parity", true),
+        6 => ("sign", false),
+        _ => ("sign", true),
+    }
+}
+
+/// The condition for `opcode`'s condition code, folded into a comparison
+/// when `source` says what set the flags.
+fn test(opcode: u8, source: Option<&FlagSource>) -> Expr {
+    let (flag, set) = condition(opcode);
+    let folded: Option<Expr> = match (source, flag) {
+        (Some(FlagSource::Compare(lhs, rhs)), "zero") => Some(Expr::binary("==", lhs.clone(), rhs.clone())),
+        (Some(FlagSource::Compare(lhs, rhs)), "carry") => Some(Expr::binary("<", lhs.clone(), rhs.clone())),
+        (Some(FlagSource::Result(result)), "zero") => Some(Expr::binary("==", result.clone(), Expr::Const(0))),
+        (Some(FlagSource::Result(result)), "sign") => Some(Expr::binary(">=", result.clone(), Expr::Const(0x80))),
+        _ => None,
+    };
+    let expr: Expr = folded.unwrap_or(Expr::Var(flag));
+    if set { expr } else { expr.negate() }
+}
+
+/// Lifts one instruction.  Returns the flag source it leaves behind, if it
+/// sets flags in a way a branch can be folded into.
+fn lift(instruction: &Instruction, stmts: &mut Vec<Stmt>) -> Option<FlagSource> {
+    let ops: &[Operand] = &instruction.operands;
+    let a = || Expr::Var("a");
+    let assign = |stmts: &mut Vec<Stmt>, lhs: Expr, rhs: Expr| stmts.push(Stmt::Assign(lhs, rhs));
+    let intrinsic = |name: &'static str, args: Vec<Expr>| Expr::Intrinsic(name, args);
+    match instruction.mnemonic {
+        Mnemonic::Nop => {},
+        Mnemonic::Lxi | Mnemonic::Mvi | Mnemonic::Mov => assign(stmts, value(&ops[0]), value(&ops[1])),
+        Mnemonic::Stax => assign(stmts, Expr::Mem(Box::new(value(&ops[0]))), a()),
+        Mnemonic::Ldax => assign(stmts, a(), Expr::Mem(Box::new(value(&ops[0])))),
+        Mnemonic::Inx | Mnemonic::Inr | Mnemonic::Dcx | Mnemonic::Dcr => {
+            let op: &'static str = match instruction.mnemonic {
+                Mnemonic::Inx | Mnemonic::Inr => "+",
+                _ => "-",
+            };
+            let target: Expr = value(&ops[0]);
+            assign(stmts, target.clone(), Expr::binary(op, target.clone(), Expr::Const(1)));
+            if let Mnemonic::Inr | Mnemonic::Dcr = instruction.mnemonic {
+                return Some(FlagSource::Result(target));
+            }
+        },
+        Mnemonic::Dad => assign(stmts, Expr::Var("hl"), Expr::binary("+", Expr::Var("hl"), value(&ops[0]))),
+        Mnemonic::Rlc | Mnemonic::Rrc | Mnemonic::Ral | Mnemonic::Rar | Mnemonic::Daa => {
+            let name: &'static str = match instruction.mnemonic {
+                Mnemonic::Rlc => "rlc", Mnemonic::Rrc => "rrc", Mnemonic::Ral => "ral",
+                Mnemonic::Rar => "rar", _ => "daa",
+            };
+            assign(stmts, a(), intrinsic(name, vec![a()]));
+        },
+        Mnemonic::Shld => assign(stmts, Expr::MemWord(Box::new(value(&ops[0]))), Expr::Var("hl")),
+        Mnemonic::Lhld => assign(stmts, Expr::Var("hl"), Expr::MemWord(Box::new(value(&ops[0])))),
+        Mnemonic::Sta => assign(stmts, Expr::Mem(Box::new(value(&ops[0]))), a()),
+        Mnemonic::Lda => assign(stmts, a(), Expr::Mem(Box::new(value(&ops[0])))),
+        Mnemonic::Cma => assign(stmts, a(), Expr::Unary("~", Box::new(a()))),
+        Mnemonic::Stc => assign(stmts, Expr::Var("carry"), Expr::Const(1)),
+        Mnemonic::Cmc => assign(stmts, Expr::Var("carry"), Expr::Unary("!", Box::new(Expr::Var("carry")))),
+        Mnemonic::Hlt => stmts.push(Stmt::Eval(intrinsic("halt", vec![]))),
+        Mnemonic::Add | Mnemonic::Adi | Mnemonic::Sub | Mnemonic::Sui |
+        Mnemonic::Ana | Mnemonic::Ani | Mnemonic::Xra | Mnemonic::Xri | Mnemonic::Ora | Mnemonic::Ori => {
+            let op: &'static str = match instruction.mnemonic {
+                Mnemonic::Add | Mnemonic::Adi => "+",
+                Mnemonic::Sub | Mnemonic::Sui => "-",
+                Mnemonic::Ana | Mnemonic::Ani => "&",
+                Mnemonic::Xra | Mnemonic::Xri => "^",
+                _ => "|",
+            };
+            let operand: Expr = value(&ops[0]);
+            match (op, &operand) {
+                // ANA A and ORA A only set the flags
+                ("&", &Expr::Var("a")) | ("|", &Expr::Var("a")) => {},
+                ("^", &Expr::Var("a")) | ("-", &Expr::Var("a")) => assign(stmts, a(), Expr::Const(0)),
+                _ => assign(stmts, a(), Expr::binary(op, a(), operand)),
+            }
+            return Some(FlagSource::Result(a()));
+        },
+        Mnemonic::Adc | Mnemonic::Aci | Mnemonic::Sbb | Mnemonic::Sbi => {
+            let op: &'static str = match instruction.mnemonic {
+                Mnemonic::Adc | Mnemonic::Aci => "+",
+                _ => "-",
+            };
+            let sum: Expr = Expr::binary(op, a(), value(&ops[0]));
+            assign(stmts, a(), Expr::binary(op, sum, Expr::Var("carry")));
+            return Some(FlagSource::Result(a()));
+        },
+        Mnemonic::Cmp | Mnemonic::Cpi => {
+            stmts.push(Stmt::Eval(intrinsic("compare", vec![a(), value(&ops[0])])));
+            return Some(FlagSource::Compare(a(), value(&ops[0])));
+        },
+        Mnemonic::Push => stmts.push(Stmt::Eval(intrinsic("push", vec![value(&ops[0])]))),
+        Mnemonic::Pop => assign(stmts, value(&ops[0]), intrinsic("pop", vec![])),
+        Mnemonic::Out => stmts.push(Stmt::Eval(intrinsic("out", vec![value(&ops[0]), a()]))),
+        Mnemonic::In => assign(stmts, a(), intrinsic("in", vec![value(&ops[0])])),
+        Mnemonic::Xthl => stmts.push(Stmt::Exchange(Expr::Var("hl"), Expr::MemWord(Box::new(Expr::Var("sp"))))),
+        Mnemonic::Xchg => stmts.push(Stmt::Exchange(Expr::Var("hl"), Expr::Var("de"))),
+        Mnemonic::Sphl => assign(stmts, Expr::Var("sp"), Expr::Var("hl")),
+        Mnemonic::Di => stmts.push(Stmt::Eval(intrinsic("di", vec![]))),
+        Mnemonic::Ei => stmts.push(Stmt::Eval(intrinsic("ei", vec![]))),
+        Mnemonic::Rst | Mnemonic::Call => {
+            if let Some(target) = instruction.branch_target() {
+                stmts.push(Stmt::Call(target));
+            }
+        },
+        // Control flow is handled by lift_block
+        _ => {},
+    }
+    None
+}
+
+/// Adds the registers `expr` reads to `names`, pairs split into their
+/// halves and any memory access as "mem".
+fn reads(expr: &Expr, names: &mut Vec<&'static str>) {
+    match *expr {
+        Expr::Var("bc") => names.extend(&["b", "c"]),
+        Expr::Var("de") => names.extend(&["d", "e"]),
+        Expr::Var("hl") => names.extend(&["h", "l"]),
+        Expr::Var("psw") => names.push("a"),
+        Expr::Var(name) => names.push(name),
+        Expr::Const(_) | Expr::Addr(_) => {},
+        Expr::Mem(ref at) | Expr::MemWord(ref at) => {
+            names.push("mem");
+            reads(at, names);
+        },
+        Expr::Unary(_, ref inner) => reads(inner, names),
+        Expr::Binary(_, ref lhs, ref rhs) => {
+            reads(lhs, names);
+            reads(rhs, names);
+        },
+        Expr::Intrinsic(_, ref args) => for arg in args {
+            reads(arg, names);
+        },
+    }
+}
+
+/// Adds what assigning to `target` changes to `names`.
+fn writes(target: &Expr, names: &mut Vec<&'static str>) {
+    match *target {
+        Expr::Mem(_) | Expr::MemWord(_) => names.push("mem"),
+        _ => reads(target, names),
+    }
+}
+
+/// Whether `stmts` change anything `source` depends on.
+fn clobbers(stmts: &[Stmt], source: &FlagSource) -> bool {
+    let mut read: Vec<&'static str> = Vec::new();
+    match *source {
+        FlagSource::Compare(ref lhs, ref rhs) => {
+            reads(lhs, &mut read);
+            reads(rhs, &mut read);
+        },
+        FlagSource::Result(ref result) => reads(result, &mut read),
+    }
+    let mut written: Vec<&'static str> = Vec::new();
+    for stmt in stmts {
+        match *stmt {
+            Stmt::Assign(ref target, _) => writes(target, &mut written),
+            Stmt::Exchange(ref x, ref y) => {
+                writes(x, &mut written);
+                writes(y, &mut written);
+            },
+            _ => {},
+        }
+    }
+    written.iter().any(|name| read.contains(name))
+}
+
+/// Lifts the instructions of one block, in order.
+pub fn lift_block(start: u16, instructions: &[&Instruction]) -> Block {
+    let mut stmts: Vec<Stmt> = Vec::new();
+    let mut terminator = Terminator::Next;
+    // What the flags describe while a branch could still fold them
+    let mut source: Option<FlagSource> = None;
+    // The compare statement that set the flags, and which compares were
+    // folded into every test of them and can go
+    let mut setter: Option<usize> = None;
+    let mut folded: Vec<usize> = Vec::new();
+    let mut needed: Vec<usize> = Vec::new();
+    for instruction in instructions {
+        let flow: Flow = instruction.mnemonic.flow();
+        let cond: Option<Expr> = match flow {
+            Flow::Branch | Flow::ConditionalCall | Flow::ConditionalReturn => {
+                if let Some(index) = setter {
+                    if source.is_some() { folded.push(index) } else { needed.push(index) }
+                }
+                Some(test(instruction.opcode, source.as_ref()))
+            },
+            _ => None,
+        };
+        match flow {
+            Flow::Jump => terminator = Terminator::Jump(instruction.target().unwrap_or(0)),
+            Flow::Branch => terminator = Terminator::Branch(cond.unwrap(), instruction.target().unwrap_or(0)),
+            Flow::Return => terminator = Terminator::Return,
+            Flow::ConditionalReturn => terminator = Terminator::ReturnIf(cond.unwrap()),
+            Flow::Indirect => terminator = Terminator::Indirect(Expr::Var("hl")),
+            Flow::ConditionalCall => stmts.push(Stmt::CallIf(cond.unwrap(), instruction.target().unwrap_or(0))),
+            _ => {},
+        }
+        let before: usize = stmts.len();
+        match lift(instruction, &mut stmts) {
+            Some(flags) => {
+                setter = match flags {
+                    FlagSource::Compare(..) => Some(before),
+                    _ => None,
+                };
+                source = Some(flags);
+            },
+            None => match instruction.mnemonic {
+                // Neither flags nor registers change
+                Mnemonic::Nop | Mnemonic::Ei | Mnemonic::Di | Mnemonic::Out => {},
+                _ if flow == Flow::ConditionalReturn => {},
+                // Flags are left as they were, but maybe not what they describe
+                _ if instruction.flags == 0 && flow == Flow::Next => {
+                    if source.as_ref().is_some_and(|source| clobbers(&stmts[before..], source)) {
+                        source = None;
+                    }
+                },
+                _ => {
+                    source = None;
+                    setter = None;
+                },
+            },
+        }
+    }
+    folded.retain(|index| !needed.contains(index));
+    folded.sort();
+    folded.dedup();
+    for index in folded.into_iter().rev() {
+        stmts.remove(index);
+    }
+    Block { start, stmts, terminator }
+}
@@ -0,0 +1,385 @@
+/*
+    Pseudo-C output, one function per subroutine of the call graph.
+
+    Blocks are lifted to the intermediate form in `ir`, then laid out in
+    address order and structured: a branch back to an earlier block becomes
+    a do/while, while or for (;;) loop, a forward branch over a run of
+    blocks becomes an if (with an else when the run ends by jumping over
+    the next one), and anything else stays a goto.  Memory at addresses
+    with symbols reads as named globals.
+*/
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use disassemble::Instruction;
+use disassemble::cfg::{Cfg, EdgeKind};
+use symbols::DataType;
+
+pub mod ir;
+
+use self::ir::{Block, Expr, Stmt, Terminator};
+
+const INDENT: &str = "    ";
+
+pub struct Decompiler<'a> {
+    pub cfg: &'a Cfg<'a>,
+    pub blocks: BTreeMap<u16, Block>,     // Lifted blocks, keyed by start
+}
+
+enum Line {
+    Code(usize, String),                  // Depth and text
+    Label(usize, u16),                    // Printed only if something jumps to it
+}
+
+/// Lays out one function.
+struct Emitter<'a, 'b: 'a> {
+    decompiler: &'a Decompiler<'b>,
+    entry: u16,
+    order: Vec<u16>,                      // The function's blocks in address order
+    position: HashMap<u16, usize>,
+    lines: Vec<Line>,
+    labelled: BTreeSet<u16>,
+    gotos: BTreeSet<u16>,
+    loops: Vec<(u16, Option<u16>, bool)>, // Header, exit and whether continue reaches the header
+}
+
+impl<'a> Decompiler<'a> {
+    pub fn new(cfg: &'a Cfg<'a>) -> Decompiler<'a> {
+        let instructions = &cfg.disassembly.instructions;
+        let blocks: BTreeMap<u16, Block> = cfg.blocks.values().map(|block| {
+            let body: Vec<&Instruction> = block.instructions.iter().map(|addr| &instructions[addr]).collect();
+            (block.start, ir::lift_block(block.start, &body))
+        }).collect();
+        Decompiler { cfg, blocks }
+    }
+    /// The value of an address: a data symbol as &name, or hex.
+    fn address(&self, addr: u16) -> String {
+        match self.cfg.disassembly.symbols.containing(addr) {
+            Some((symbol, 0)) if symbol.is_data() => format!("&{}", symbol.name),
+            Some((symbol, offset)) if symbol.is_data() => format!("&{}[{}]", symbol.name, offset),
+            _ => format!("0x{:04x}", addr),
+        }
+    }
+    /// The byte at a fixed address: a named global where there is a symbol.
+    fn global(&self, addr: u16) -> String {
+        match self.cfg.disassembly.symbols.containing(addr) {
+            Some((symbol, 0)) => symbol.name.clone(),
+            Some((symbol, offset)) => format!("{}[{}]", symbol.name, offset),
+            None => format!("mem[0x{:04x}]", addr),
+        }
+    }
+    pub fn expr(&self, expr: &Expr) -> String {
+        let operand = |expr: &Expr| match *expr {
+            Expr::Binary(..) => format!("({})", self.expr(expr)),
+            _ => self.expr(expr),
+        };
+        match *expr {
+            Expr::Var(name) => name.to_string(),
+            Expr::Const(value) if value < 10 => value.to_string(),
+            Expr::Const(value) if value < 0x100 => format!("0x{:02x}", value),
+            Expr::Const(value) => format!("0x{:04x}", value),
+            Expr::Addr(addr) => self.address(addr),
+            Expr::Mem(ref at) => match **at {
+                Expr::Addr(addr) => self.global(addr),
+                ref at => format!("mem[{}]", self.expr(at)),
+            },
+            Expr::MemWord(ref at) => match **at {
+                Expr::Addr(addr) => match self.cfg.disassembly.symbols.get(addr) {
+                    Some(symbol) if symbol.data.is_some_and(|(t, _)| t == DataType::Word) => symbol.name.clone(),
+                    Some(symbol) => format!("word(&{})", symbol.name),
+                    None => format!("word(0x{:04x})", addr),
+                },
+                ref at => format!("word({})", self.expr(at)),
+            },
+            Expr::Unary(op, ref inner) => format!("{}{}", op, operand(inner)),
+            Expr::Binary(op, ref lhs, ref rhs) => format!("{} {} {}", operand(lhs), op, operand(rhs)),
+            Expr::Intrinsic(name, ref args) => {
+                let args: Vec<String> = args.iter().map(|arg| self.expr(arg)).collect();
+                format!("{}({})", name, args.join(", "))
+            },
+        }
+    }
+    pub fn stmt(&self, stmt: &Stmt) -> String {
+        match *stmt {
+            Stmt::Assign(ref lhs, Expr::Binary(op, ref left, ref right)) if **left == *lhs &&
+                    ["+", "-", "&", "|", "^"].contains(&op) => {
+                match (op, &**right) {
+                    ("+", &Expr::Const(1)) => format!("{}++;", self.expr(lhs)),
+                    ("-", &Expr::Const(1)) => format!("{}--;", self.expr(lhs)),
+                    _ => format!("{} {}= {};", self.expr(lhs), op, self.expr(right)),
+                }
+            },
+            Stmt::Assign(ref lhs, ref rhs) => format!("{} = {};", self.expr(lhs), self.expr(rhs)),
+            Stmt::Exchange(ref x, ref y) => format!("swap({}, {});", self.expr(x), self.expr(y)),
+            Stmt::Eval(ref expr) => format!("{};", self.expr(expr)),
+            Stmt::Call(target) => format!("{}();", self.cfg.name(target)),
+            Stmt::CallIf(ref cond, target) => format!("if ({}) {}();", self.expr(cond), self.cfg.name(target)),
+        }
+    }
+    /// One subroutine as pseudo-C.
+    pub fn function(&self, entry: u16) -> String {
+        let function = match self.cfg.functions.get(&entry) {
+            Some(function) => function,
+            None => return String::new(),
+        };
+        let mut order: Vec<u16> = function.blocks.clone();
+        order.sort();
+        let mut emitter = Emitter {
+            decompiler: self,
+            entry,
+            position: order.iter().enumerate().map(|(i, &addr)| (addr, i)).collect(),
+            order,
+            lines: Vec::new(),
+            labelled: BTreeSet::new(),
+            gotos: BTreeSet::new(),
+            loops: Vec::new(),
+        };
+        if emitter.order[0] != entry {
+            emitter.gotos.insert(entry);
+            emitter.lines.push(Line::Code(1, format!("goto {};", self.cfg.name(entry))));
+        }
+        let end: usize = emitter.order.len();
+        emitter.range(0, end, None, 1);
+
+        let mut notes: Vec<String> = Vec::new();
+        if function.entry_point {
+            notes.push("entry point".to_string());
+        }
+        if let Some(n) = function.restart {
+            notes.push(format!("RST {} vector", n));
+        }
+        if let Some(comment) = self.cfg.disassembly.symbols.get(entry).and_then(|symbol| symbol.comment.clone()) {
+            notes.push(comment);
+        }
+        let mut text: String = String::new();
+        if !notes.is_empty() {
+            text.push_str(&format!("/* {} */\n", notes.join(", ")));
+        }
+        text.push_str(&format!("void {}(void)\n{{\n", self.cfg.name(entry)));
+        for line in &emitter.lines {
+            match *line {
+                Line::Code(depth, ref code) => text.push_str(&format!("{}{}\n", INDENT.repeat(depth), code)),
+                Line::Label(depth, addr) if emitter.gotos.contains(&addr) =>
+                    text.push_str(&format!("{}{}:\n", INDENT.repeat(depth - 1), self.cfg.name(addr))),
+                Line::Label(..) => {},
+            }
+        }
+        text.push_str("}\n");
+        text
+    }
+    /// Every function, in address order.
+    pub fn program(&self) -> String {
+        let functions: Vec<String> = self.cfg.functions.keys().map(|&entry| self.function(entry)).collect();
+        functions.join("\n")
+    }
+}
+
+impl<'a, 'b> Emitter<'a, 'b> {
+    fn push(&mut self, depth: usize, text: String) {
+        self.lines.push(Line::Code(depth, text));
+    }
+    /// Index of `addr` among the blocks before `hi`, or `hi` itself when
+    /// `addr` is where the range falls out to.
+    fn find(&self, addr: u16, hi: usize, follow: Option<u16>) -> Option<usize> {
+        match self.position.get(&addr) {
+            Some(&index) if index < hi => Some(index),
+            _ if follow == Some(addr) => Some(hi),
+            _ => None,
+        }
+    }
+    /// Where a block falls through to, if it can.
+    fn fallthrough(&self, start: u16) -> Option<u16> {
+        self.decompiler.cfg.blocks[&start].successors.iter()
+            .find(|edge| edge.kind == EdgeKind::Fallthrough)
+            .map(|edge| edge.to)
+    }
+    /// The statements that pass control to `target`, none when control gets
+    /// there anyway by falling out to `follow`.
+    fn jump(&mut self, target: u16, follow: Option<u16>) -> Vec<String> {
+        if follow == Some(target) {
+            return Vec::new();
+        }
+        if let Some(&(header, exit, continues)) = self.loops.last() {
+            // In a do/while, continue would test the condition first
+            if target == header && continues {
+                return vec!["continue;".to_string()];
+            }
+            if exit == Some(target) {
+                return vec!["break;".to_string()];
+            }
+        }
+        let name: String = self.decompiler.cfg.name(target);
+        if target != self.entry && self.decompiler.cfg.functions.contains_key(&target) {
+            // A tail call: its return is this function's
+            return vec![format!("{}();", name), "return;".to_string()];
+        }
+        self.gotos.insert(target);
+        vec![format!("goto {};", name)]
+    }
+    /// Emits the jump to `target` on its own.
+    fn push_jump(&mut self, target: u16, follow: Option<u16>, depth: usize) {
+        for text in self.jump(target, follow) {
+            self.push(depth, text);
+        }
+    }
+    fn statements(&mut self, start: u16, depth: usize) {
+        let decompiler: &Decompiler = self.decompiler;
+        for stmt in &decompiler.blocks[&start].stmts {
+            self.push(depth, decompiler.stmt(stmt));
+        }
+    }
+    fn label(&mut self, start: u16, depth: usize) {
+        if self.labelled.insert(start) {
+            self.lines.push(Line::Label(depth, start));
+        }
+    }
+    /// Whether order[j] jumps back to order[i] and can be reached from it
+    /// through order[i..=j], making a loop.
+    fn back_edge(&self, i: usize, j: usize) -> bool {
+        let header: u16 = self.order[i];
+        match self.decompiler.blocks[&self.order[j]].terminator {
+            Terminator::Jump(target) | Terminator::Branch(_, target) if target == header => {},
+            _ => return false,
+        }
+        let mut seen: BTreeSet<u16> = BTreeSet::new();
+        let mut pending: Vec<u16> = vec![header];
+        while let Some(start) = pending.pop() {
+            if start == self.order[j] {
+                return true;
+            }
+            if !seen.insert(start) {
+                continue;
+            }
+            for edge in &self.decompiler.cfg.blocks[&start].successors {
+                if self.position.get(&edge.to).is_some_and(|&k| k >= i && k <= j) {
+                    pending.push(edge.to);
+                }
+            }
+        }
+        false
+    }
+    /// Emits the blocks order[lo..hi]; falling out of the last one reaches
+    /// `follow`.
+    fn range(&mut self, lo: usize, hi: usize, follow: Option<u16>, depth: usize) {
+        let mut i: usize = lo;
+        while i < hi {
+            let start: u16 = self.order[i];
+            self.label(start, depth);
+            let in_loop: bool = self.loops.iter().any(|&(header, _, _)| header == start);
+            let tail: Option<usize> = if in_loop { None } else {
+                (i..hi).rev().find(|&j| self.back_edge(i, j))
+            };
+            if let Some(j) = tail {
+                self.emit_loop(i, j, hi, follow, depth);
+                i = j + 1;
+                continue;
+            }
+            let next: Option<u16> = if i + 1 < hi { Some(self.order[i + 1]) } else { follow };
+            self.statements(start, depth);
+            let decompiler: &Decompiler = self.decompiler;
+            match decompiler.blocks[&start].terminator {
+                Terminator::Next => {
+                    if let Some(to) = self.fallthrough(start) {
+                        self.push_jump(to, next, depth);
+                    }
+                },
+                Terminator::Jump(target) => self.push_jump(target, next, depth),
+                Terminator::Return => self.push(depth, "return;".to_string()),
+                Terminator::Indirect(ref expr) => self.push(depth, format!("goto *{};", decompiler.expr(expr))),
+                Terminator::ReturnIf(ref cond) => {
+                    self.push(depth, format!("if ({}) return;", decompiler.expr(cond)));
+                    if let Some(to) = self.fallthrough(start) {
+                        self.push_jump(to, next, depth);
+                    }
+                },
+                Terminator::Branch(ref cond, target) => {
+                    let fall: Option<u16> = self.fallthrough(start);
+                    let then_start: Option<u16> = if i + 1 < hi { Some(self.order[i + 1]) } else { None };
+                    let k: Option<usize> = self.find(target, hi, follow).filter(|&k| k > i + 1);
+                    if let (Some(k), true) = (k, fall.is_some() && fall == then_start) {
+                        let not: Expr = cond.clone().negate();
+                        // An else when the then part ends by jumping over it
+                        let over: Option<(u16, usize)> = match decompiler.blocks[&self.order[k - 1]].terminator {
+                            Terminator::Jump(over) if k < hi => self.find(over, hi, follow)
+                                .filter(|&m| m > k)
+                                .map(|m| (over, m)),
+                            _ => None,
+                        };
+                        self.push(depth, format!("if ({}) {{", decompiler.expr(&not)));
+                        match over {
+                            Some((over, m)) => {
+                                self.range(i + 1, k, Some(over), depth + 1);
+                                self.push(depth, "} else {".to_string());
+                                self.range(k, m, Some(over), depth + 1);
+                                self.push(depth, "}".to_string());
+                                i = m;
+                            },
+                            None => {
+                                self.range(i + 1, k, Some(target), depth + 1);
+                                self.push(depth, "}".to_string());
+                                i = k;
+                            },
+                        }
+                        continue;
+                    }
+                    let mut taken: Vec<String> = self.jump(target, next);
+                    if taken.len() == 1 {
+                        self.push(depth, format!("if ({}) {}", decompiler.expr(cond), taken.remove(0)));
+                    }
+                    else if !taken.is_empty() {
+                        self.push(depth, format!("if ({}) {{", decompiler.expr(cond)));
+                        for text in taken {
+                            self.push(depth + 1, text);
+                        }
+                        self.push(depth, "}".to_string());
+                    }
+                    if let Some(to) = fall {
+                        self.push_jump(to, next, depth);
+                    }
+                },
+            }
+            i += 1;
+        }
+    }
+    /// Emits the loop from order[i] back from order[j].
+    fn emit_loop(&mut self, i: usize, j: usize, hi: usize, follow: Option<u16>, depth: usize) {
+        let decompiler: &Decompiler = self.decompiler;
+        let header: u16 = self.order[i];
+        let tail: u16 = self.order[j];
+        let exit: Option<u16> = if j + 1 < hi { Some(self.order[j + 1]) } else { follow };
+        match decompiler.blocks[&tail].terminator {
+            Terminator::Branch(ref cond, _) => {
+                self.loops.push((header, exit, false));
+                self.push(depth, "do {".to_string());
+                self.range(i, j, Some(tail), depth + 1);
+                self.label(tail, depth + 1);
+                self.statements(tail, depth + 1);
+                self.loops.pop();
+                self.push(depth, format!("}} while ({});", decompiler.expr(cond)));
+                if let Some(to) = self.fallthrough(tail) {
+                    self.push_jump(to, exit, depth);
+                }
+            },
+            _ => {
+                self.loops.push((header, exit, true));
+                let header_block: &Block = &decompiler.blocks[&header];
+                let test: Option<&Expr> = match header_block.terminator {
+                    Terminator::Branch(ref cond, out) if i < j && exit == Some(out) &&
+                            header_block.stmts.is_empty() && self.fallthrough(header) == Some(self.order[i + 1]) =>
+                        Some(cond),
+                    _ => None,
+                };
+                match test {
+                    Some(cond) => {
+                        self.push(depth, format!("while ({}) {{", decompiler.expr(&cond.clone().negate())));
+                        self.range(i + 1, j + 1, Some(header), depth + 1);
+                    },
+                    None => {
+                        self.push(depth, "for (;;) {".to_string());
+                        self.range(i, j + 1, Some(header), depth + 1);
+                    },
+                }
+                self.loops.pop();
+                self.push(depth, "}".to_string());
+            },
+        }
+    }
+}
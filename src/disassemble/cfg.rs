@@ -0,0 +1,378 @@
+/*
+    Basic blocks and a call graph built from a flow disassembly, with export
+    to Graphviz DOT and JSON.
+
+    Blocks end at jumps, branches, returns and PCHL; calls stay inside a
+    block and are recorded as call sites instead.  A function is an entry
+    point, a CALL target, an RST vector or a `code` symbol, and owns the
+    blocks reachable from it without passing through another function's
+    entry.
+*/
+use std::collections::{BTreeMap, BTreeSet};
+use super::{Flow, Instruction};
+use super::flow::Disassembly;
+use symbols::DataType;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeKind {
+    Fallthrough,          // Into the next block, or a Jcc/Rcc not taken
+    Jump,                 // JMP
+    Branch,               // Jcc taken
+}
+
+impl EdgeKind {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            EdgeKind::Fallthrough => "fallthrough",
+            EdgeKind::Jump => "jump",
+            EdgeKind::Branch => "branch",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Edge {
+    pub to: u16,
+    pub kind: EdgeKind,
+}
+
+#[derive(Debug, Clone)]
+pub struct BasicBlock {
+    pub start: u16,
+    pub instructions: Vec<u16>,       // Addresses, in order
+    pub successors: Vec<Edge>,
+    pub returns: bool,                // Ends in RET, or an Rcc that can return
+    pub indirect: bool,               // Ends in PCHL, successors unknown
+}
+
+impl BasicBlock {
+    /// Address of the last instruction.
+    pub fn end(&self) -> u16 {
+        *self.instructions.last().unwrap_or(&self.start)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallKind {
+    Call,                 // CALL
+    ConditionalCall,      // Ccc
+    Restart,              // RST n
+    TailJump,             // JMP or Jcc into another function
+}
+
+impl CallKind {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            CallKind::Call => "call",
+            CallKind::ConditionalCall => "conditional call",
+            CallKind::Restart => "rst",
+            CallKind::TailJump => "tail jump",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CallSite {
+    pub site: u16,                    // Address of the calling instruction
+    pub target: u16,
+    pub kind: CallKind,
+}
+
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub entry: u16,
+    pub blocks: Vec<u16>,             // Block starts, entry first
+    pub calls: Vec<CallSite>,
+    pub entry_point: bool,            // One of the analysis entry points
+    pub restart: Option<u8>,          // RST vector number, for 8 * n entries
+    pub indirect: bool,               // Contains a PCHL
+}
+
+pub struct Cfg<'a> {
+    pub disassembly: &'a Disassembly,
+    pub blocks: BTreeMap<u16, BasicBlock>,
+    pub functions: BTreeMap<u16, Function>,
+}
+
+fn ends_block(flow: Flow) -> bool {
+    matches!(flow, Flow::Jump | Flow::Branch | Flow::Return | Flow::ConditionalReturn | Flow::Indirect)
+}
+
+/// Escapes text for a double-quoted DOT or JSON string.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+impl<'a> Cfg<'a> {
+    pub fn build(disassembly: &'a Disassembly) -> Cfg<'a> {
+        let instructions: &BTreeMap<u16, Instruction> = &disassembly.instructions;
+        // Block leaders: entries, branch and call targets, and anything not
+        // reached by falling through
+        let mut leaders: BTreeSet<u16> = BTreeSet::new();
+        let mut fall_into: BTreeSet<u16> = BTreeSet::new();
+        let mut function_entries: BTreeSet<u16> = BTreeSet::new();
+        let mut restarts: BTreeSet<u16> = BTreeSet::new();
+        for instruction in instructions.values() {
+            let flow: Flow = instruction.mnemonic.flow();
+            if let Some(target) = instruction.branch_target() {
+                if disassembly.is_code(target) {
+                    leaders.insert(target);
+                    match flow {
+                        Flow::Call | Flow::ConditionalCall => { function_entries.insert(target); },
+                        Flow::Restart => { function_entries.insert(target); restarts.insert(target); },
+                        _ => {},
+                    }
+                }
+            }
+            if !ends_block(flow) {
+                fall_into.insert(instruction.next_addr());
+            }
+            else if disassembly.is_code(instruction.next_addr()) {
+                leaders.insert(instruction.next_addr());
+            }
+        }
+        let entries = disassembly.entries.iter().cloned()
+            .chain(disassembly.symbols.iter()
+                   .filter(|symbol| symbol.data.is_some_and(|(data_type, _)| data_type == DataType::Code))
+                   .map(|symbol| symbol.addr))
+            .filter(|&addr| disassembly.is_code(addr));
+        function_entries.extend(entries);
+        leaders.extend(function_entries.iter().cloned());
+        leaders.extend(instructions.keys().filter(|addr| !fall_into.contains(addr)));
+
+        let mut blocks: BTreeMap<u16, BasicBlock> = BTreeMap::new();
+        for &start in &leaders {
+            let mut block = BasicBlock {
+                start,
+                instructions: Vec::new(),
+                successors: Vec::new(),
+                returns: false,
+                indirect: false,
+            };
+            let mut addr: u16 = start;
+            loop {
+                let instruction: &Instruction = &instructions[&addr];
+                block.instructions.push(addr);
+                let next: u16 = instruction.next_addr();
+                let target: Option<u16> = instruction.target();
+                match instruction.mnemonic.flow() {
+                    Flow::Jump => {
+                        block.successors.extend(target.map(|to| Edge { to, kind: EdgeKind::Jump }));
+                        break;
+                    },
+                    Flow::Branch => {
+                        block.successors.extend(target.map(|to| Edge { to, kind: EdgeKind::Branch }));
+                        block.successors.push(Edge { to: next, kind: EdgeKind::Fallthrough });
+                        break;
+                    },
+                    Flow::ConditionalReturn => {
+                        block.returns = true;
+                        block.successors.push(Edge { to: next, kind: EdgeKind::Fallthrough });
+                        break;
+                    },
+                    Flow::Return => {
+                        block.returns = true;
+                        break;
+                    },
+                    Flow::Indirect => {
+                        block.indirect = true;
+                        break;
+                    },
+                    _ => {},
+                }
+                if next < addr || !disassembly.is_code(next) {
+                    // Falls off the code, usually after a call that never returns
+                    break;
+                }
+                if leaders.contains(&next) {
+                    block.successors.push(Edge { to: next, kind: EdgeKind::Fallthrough });
+                    break;
+                }
+                addr = next;
+            }
+            blocks.insert(start, block);
+        }
+
+        let mut functions: BTreeMap<u16, Function> = BTreeMap::new();
+        for &entry in &function_entries {
+            let mut function = Function {
+                entry,
+                blocks: Vec::new(),
+                calls: Vec::new(),
+                entry_point: disassembly.entries.contains(&entry),
+                restart: if entry & 0x07 == 0 && entry < 0x40 &&
+                    (restarts.contains(&entry) || disassembly.entries.contains(&entry)) {
+                    Some((entry >> 3) as u8)
+                } else {
+                    None
+                },
+                indirect: false,
+            };
+            let mut seen: BTreeSet<u16> = BTreeSet::new();
+            let mut pending: Vec<u16> = vec![entry];
+            while let Some(start) = pending.pop() {
+                if !seen.insert(start) {
+                    continue;
+                }
+                let block: &BasicBlock = match blocks.get(&start) {
+                    Some(block) => block,
+                    None => continue,
+                };
+                function.blocks.push(start);
+                function.indirect |= block.indirect;
+                for &addr in &block.instructions {
+                    let instruction: &Instruction = &instructions[&addr];
+                    let kind: CallKind = match instruction.mnemonic.flow() {
+                        Flow::Call => CallKind::Call,
+                        Flow::ConditionalCall => CallKind::ConditionalCall,
+                        Flow::Restart => CallKind::Restart,
+                        _ => continue,
+                    };
+                    if let Some(target) = instruction.branch_target() {
+                        function.calls.push(CallSite { site: addr, target, kind });
+                    }
+                }
+                for edge in block.successors.iter().rev() {
+                    if edge.to != entry && function_entries.contains(&edge.to) {
+                        function.calls.push(CallSite { site: block.end(), target: edge.to, kind: CallKind::TailJump });
+                    }
+                    else {
+                        pending.push(edge.to);
+                    }
+                }
+            }
+            // Entry first, then in address order
+            function.blocks[1..].sort();
+            functions.insert(entry, function);
+        }
+        Cfg { disassembly, blocks, functions }
+    }
+    /// Name for an address: its label, or a generated block name.
+    pub fn name(&self, addr: u16) -> String {
+        self.disassembly.label(addr).unwrap_or_else(|| format!("L_{:04X}", addr))
+    }
+    /// Blocks ending in PCHL.
+    pub fn indirect_jumps(&self) -> Vec<u16> {
+        self.blocks.values().filter(|block| block.indirect).map(|block| block.end()).collect()
+    }
+    fn block_text(&self, block: &BasicBlock) -> String {
+        let mut text: String = format!("{}:\\l", escape(&self.name(block.start)));
+        for addr in &block.instructions {
+            let instruction: &Instruction = &self.disassembly.instructions[addr];
+            let line: String = instruction.format_with(|target| self.disassembly.name_for(target));
+            text.push_str(&format!("{:04x}  {}\\l", addr, escape(&line)));
+        }
+        text
+    }
+    /// The control-flow graph as DOT: one node per block with its
+    /// instructions, and a cluster per function.  PCHL blocks are red, RST
+    /// vectors are double octagons, and calls are dashed edges.
+    pub fn to_dot(&self) -> String {
+        let mut dot: String = String::from("digraph cfg {\n    node [shape=box fontname=monospace];\n");
+        for block in self.blocks.values() {
+            let mut style: String = String::new();
+            if block.indirect {
+                style.push_str(" color=red xlabel=\"PCHL\"");
+            }
+            if let Some(function) = self.functions.get(&block.start) {
+                if let Some(n) = function.restart {
+                    style.push_str(&format!(" shape=doubleoctagon xlabel=\"RST {}\"", n));
+                }
+            }
+            dot.push_str(&format!("    b{:04x} [label=\"{}\"{}];\n", block.start, self.block_text(block), style));
+        }
+        for block in self.blocks.values() {
+            for edge in &block.successors {
+                let style: &str = match edge.kind {
+                    EdgeKind::Fallthrough => "",
+                    EdgeKind::Jump => " [style=bold]",
+                    EdgeKind::Branch => " [color=blue]",
+                };
+                dot.push_str(&format!("    b{:04x} -> b{:04x}{};\n", block.start, edge.to, style));
+            }
+        }
+        for function in self.functions.values() {
+            for call in &function.calls {
+                let block: u16 = self.block_of(call.site);
+                let label: String = match call.kind {
+                    CallKind::Restart => format!(" label=\"RST {}\"", call.target >> 3),
+                    _ => String::new(),
+                };
+                dot.push_str(&format!("    b{:04x} -> b{:04x} [style=dashed{}];\n", block, call.target, label));
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+    /// The call graph as DOT: one node per function.
+    pub fn call_graph_dot(&self) -> String {
+        let mut dot: String = String::from("digraph calls {\n    node [shape=box fontname=monospace];\n");
+        for function in self.functions.values() {
+            let mut style: String = String::new();
+            if function.entry_point {
+                style.push_str(" penwidth=2");
+            }
+            if let Some(n) = function.restart {
+                style.push_str(&format!(" shape=doubleoctagon xlabel=\"RST {}\"", n));
+            }
+            if function.indirect {
+                style.push_str(" color=red");
+            }
+            dot.push_str(&format!("    f{:04x} [label=\"{}\"{}];\n", function.entry,
+                                  escape(&self.name(function.entry)), style));
+        }
+        for function in self.functions.values() {
+            let mut targets: BTreeSet<(u16, &'static str)> = BTreeSet::new();
+            for call in &function.calls {
+                let style: &'static str = match call.kind {
+                    CallKind::Call => "",
+                    CallKind::ConditionalCall => " [style=dashed]",
+                    CallKind::Restart => " [color=purple]",
+                    CallKind::TailJump => " [style=dotted]",
+                };
+                targets.insert((call.target, style));
+            }
+            for (target, style) in targets {
+                dot.push_str(&format!("    f{:04x} -> f{:04x}{};\n", function.entry, target, style));
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+    /// Start of the block holding the instruction at `addr`.
+    pub fn block_of(&self, addr: u16) -> u16 {
+        self.blocks.range(..=addr).next_back().map_or(addr, |(&start, _)| start)
+    }
+    /// Blocks, functions and PCHL sites as JSON, addresses as numbers.
+    pub fn to_json(&self) -> String {
+        let mut json: String = String::from("{\n  \"blocks\": [\n");
+        let blocks: Vec<String> = self.blocks.values().map(|block| {
+            let successors: Vec<String> = block.successors.iter()
+                .map(|edge| format!("{{\"to\": {}, \"kind\": \"{}\"}}", edge.to, edge.kind.name()))
+                .collect();
+            let instructions: Vec<String> = block.instructions.iter().map(|addr| addr.to_string()).collect();
+            format!("    {{\"start\": {}, \"name\": \"{}\", \"instructions\": [{}], \"successors\": [{}], \
+                     \"returns\": {}, \"indirect\": {}}}",
+                    block.start, escape(&self.name(block.start)), instructions.join(", "),
+                    successors.join(", "), block.returns, block.indirect)
+        }).collect();
+        json.push_str(&blocks.join(",\n"));
+        json.push_str("\n  ],\n  \"functions\": [\n");
+        let functions: Vec<String> = self.functions.values().map(|function| {
+            let blocks: Vec<String> = function.blocks.iter().map(|addr| addr.to_string()).collect();
+            let calls: Vec<String> = function.calls.iter()
+                .map(|call| format!("{{\"site\": {}, \"target\": {}, \"kind\": \"{}\"}}",
+                                    call.site, call.target, call.kind.name()))
+                .collect();
+            let restart: String = function.restart.map_or("null".to_string(), |n| n.to_string());
+            format!("    {{\"entry\": {}, \"name\": \"{}\", \"entry_point\": {}, \"restart\": {}, \
+                     \"indirect\": {}, \"blocks\": [{}], \"calls\": [{}]}}",
+                    function.entry, escape(&self.name(function.entry)), function.entry_point, restart,
+                    function.indirect, blocks.join(", "), calls.join(", "))
+        }).collect();
+        json.push_str(&functions.join(",\n"));
+        let indirect: Vec<String> = self.indirect_jumps().iter().map(|addr| addr.to_string()).collect();
+        json.push_str(&format!("\n  ],\n  \"indirect_jumps\": [{}]\n}}\n", indirect.join(", ")));
+        json
+    }
+}
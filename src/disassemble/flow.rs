@@ -0,0 +1,261 @@
+/*
+    Recursive-descent disassembly.  Decoding starts at the entry points and
+    follows jumps, calls and restarts, so only bytes that are reachable as
+    code are shown as instructions; everything else is listed as DB data.
+    A symbol file can add names, comments, entry points and typed data.
+*/
+use std::collections::{BTreeMap, BTreeSet};
+use super::{decode, Flow, Instruction, Operand};
+use symbols::{DataType, Symbol, SymbolTable};
+
+/// Reset plus the RST 1 and RST 2 vectors, the entry points of Space Invaders.
+pub const DEFAULT_ENTRIES: [u16; 3] = [0x0000, 0x0008, 0x0010];
+// Data bytes per DB line, words per DW line and characters per text line
+const DATA_PER_LINE: usize = 8;
+const WORDS_PER_LINE: usize = 4;
+const TEXT_PER_LINE: usize = 32;
+// Column for comments after a label
+const COMMENT_COLUMN: usize = 24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LabelKind {
+    Jump,                 // Target of a JMP or Jcc, named L_XXXX
+    Subroutine,           // Target of a CALL, Ccc or RST, named SUB_XXXX
+}
+
+impl LabelKind {
+    pub fn name(&self, addr: u16) -> String {
+        match *self {
+            LabelKind::Jump => format!("L_{:04X}", addr),
+            LabelKind::Subroutine => format!("SUB_{:04X}", addr),
+        }
+    }
+}
+
+pub struct Disassembly {
+    pub origin: u16,
+    pub bytes: Vec<u8>,
+    pub entries: Vec<u16>,
+    pub instructions: BTreeMap<u16, Instruction>,     // Keyed by address
+    pub labels: BTreeMap<u16, LabelKind>,             // Branch targets that were decoded
+    pub symbols: SymbolTable,
+}
+
+impl Disassembly {
+    /// Follows the code reachable from `entries` in `bytes`, which were
+    /// loaded at `origin`.  Decoding along a path stops at a RET, JMP or
+    /// PCHL, at the end of the buffer, at an undocumented opcode (which is
+    /// almost certainly data) or where it would overlap an instruction
+    /// already found.
+    pub fn analyze(bytes: &[u8], origin: u16, entries: &[u16]) -> Disassembly {
+        Disassembly::analyze_with_symbols(bytes, origin, entries, &SymbolTable::new())
+    }
+    /// As analyze, but also decoding from every `code` symbol and never
+    /// decoding through bytes that a symbol declares as data.
+    pub fn analyze_with_symbols(bytes: &[u8], origin: u16, entries: &[u16],
+                                symbols: &SymbolTable) -> Disassembly {
+        let mut disassembly = Disassembly {
+            origin,
+            bytes: bytes.to_vec(),
+            entries: entries.to_vec(),
+            instructions: BTreeMap::new(),
+            labels: BTreeMap::new(),
+            symbols: symbols.clone(),
+        };
+        // Instruction start owning each byte
+        let mut owner: Vec<Option<u16>> = vec![None; bytes.len()];
+        let mut declared_data: Vec<bool> = vec![false; bytes.len()];
+        let mut pending: Vec<u16> = entries.to_vec();
+        for symbol in symbols.iter() {
+            match symbol.data {
+                Some((DataType::Code, _)) => pending.push(symbol.addr),
+                Some(_) => for i in 0..symbol.size() {
+                    if let Some(offset) = disassembly.offset(symbol.addr.wrapping_add(i as u16)) {
+                        declared_data[offset] = true;
+                    }
+                },
+                None => {},
+            }
+        }
+        let mut targets: BTreeMap<u16, LabelKind> = BTreeMap::new();
+        while let Some(start) = pending.pop() {
+            let mut addr: u16 = start;
+            while let Some(offset) = disassembly.offset(addr) {
+                if owner[offset].is_some() {
+                    break;
+                }
+                let instruction: Instruction = decode(&bytes[offset..], addr);
+                let end: usize = offset + instruction.length as usize;
+                if end > bytes.len() || instruction.undocumented ||
+                    owner[offset..end].iter().any(|o| o.is_some()) ||
+                    declared_data[offset..end].iter().any(|&data| data) {
+                    break;
+                }
+                for byte in &mut owner[offset..end] {
+                    *byte = Some(addr);
+                }
+                let flow: Flow = instruction.mnemonic.flow();
+                if let Some(target) = instruction.branch_target() {
+                    let kind: LabelKind = match flow {
+                        Flow::Jump | Flow::Branch => LabelKind::Jump,
+                        _ => LabelKind::Subroutine,
+                    };
+                    let entry = targets.entry(target).or_insert(kind);
+                    *entry = (*entry).max(kind);
+                    pending.push(target);
+                }
+                let next: u16 = instruction.next_addr();
+                disassembly.instructions.insert(addr, instruction);
+                match flow {
+                    Flow::Jump | Flow::Return | Flow::Indirect => break,
+                    _ => {},
+                }
+                if next < addr {
+                    // Ran off the top of the address space
+                    break;
+                }
+                addr = next;
+            }
+        }
+        // Targets inside other instructions or outside the buffer stay numeric
+        for (addr, kind) in targets {
+            if disassembly.instructions.contains_key(&addr) {
+                disassembly.labels.insert(addr, kind);
+            }
+        }
+        disassembly
+    }
+    /// Offset of `addr` into the buffer, if it lies inside it.
+    pub fn offset(&self, addr: u16) -> Option<usize> {
+        let offset: usize = addr.wrapping_sub(self.origin) as usize;
+        if addr >= self.origin && offset < self.bytes.len() {
+            Some(offset)
+        }
+        else {
+            None
+        }
+    }
+    /// Name of the label at `addr`: its symbol, or the generated label.
+    pub fn label(&self, addr: u16) -> Option<String> {
+        match self.symbols.get(addr) {
+            Some(symbol) => Some(symbol.name.clone()),
+            None => self.labels.get(&addr).map(|kind| kind.name(addr)),
+        }
+    }
+    /// Name to show for `addr` in an operand: a label, or a symbol name
+    /// with an offset for addresses inside typed data.
+    pub fn name_for(&self, addr: u16) -> Option<String> {
+        self.label(addr).or_else(|| self.symbols.name_for(addr))
+    }
+    /// The symbol table extended with a `code` symbol for every generated
+    /// label, to be saved and annotated by hand.
+    pub fn export_symbols(&self) -> SymbolTable {
+        let mut symbols: SymbolTable = self.symbols.clone();
+        for (&addr, kind) in &self.labels {
+            if symbols.get(addr).is_none() {
+                let mut symbol = Symbol::new(addr, &kind.name(addr));
+                symbol.data = Some((DataType::Code, 1));
+                symbols.insert(symbol);
+            }
+        }
+        symbols
+    }
+    /// Whether `addr` is the first byte of a decoded instruction.
+    pub fn is_code(&self, addr: u16) -> bool {
+        self.instructions.contains_key(&addr)
+    }
+    /// Bytes decoded as instructions, out of the whole buffer.
+    pub fn code_bytes(&self) -> usize {
+        self.instructions.values().map(|i| i.length as usize).sum()
+    }
+    /// Whether a data line should end before `addr`.
+    fn breaks_data(&self, addr: u16) -> bool {
+        self.is_code(addr) || self.label(addr).is_some() ||
+            self.symbols.containing(addr.wrapping_sub(1)).is_some_and(|(symbol, offset)| {
+                symbol.is_data() && offset + 1 == symbol.size()
+            })
+    }
+    /// One line of data at `offset`, shaped by any typed symbol covering it,
+    /// and the number of bytes it takes.
+    fn data_line(&self, offset: usize) -> (String, usize) {
+        let addr: u16 = self.origin.wrapping_add(offset as u16);
+        let data_type: DataType = match self.symbols.containing(addr) {
+            Some((symbol, _)) if symbol.is_data() => symbol.data.map_or(DataType::Byte, |(t, _)| t),
+            _ => DataType::Byte,
+        };
+        let mut len: usize = 0;
+        while offset + len < self.bytes.len() {
+            if len > 0 && self.breaks_data(addr.wrapping_add(len as u16)) {
+                break;
+            }
+            len += 1;
+        }
+        let run: &[u8] = &self.bytes[offset..offset + len];
+        match data_type {
+            DataType::Word if run.len() >= 2 => {
+                let words: Vec<String> = run.chunks(2).take(WORDS_PER_LINE)
+                    .take_while(|pair| pair.len() == 2)
+                    .map(|pair| format!("${:04x}", pair[0] as u16 | (pair[1] as u16) << 8))
+                    .collect();
+                let len: usize = words.len() * 2;
+                (format!("{:<8}{}", "DW", words.join(",")), len)
+            },
+            DataType::Text if run.iter().take(TEXT_PER_LINE).all(|&b| (0x20..0x7f).contains(&b) && b != b'"') => {
+                let text: &[u8] = &run[..run.len().min(TEXT_PER_LINE)];
+                (format!("{:<8}\"{}\"", "DB", String::from_utf8_lossy(text)), text.len())
+            },
+            _ => {
+                let data: Vec<String> = run.iter().take(DATA_PER_LINE).map(|b| format!("${:02x}", b)).collect();
+                let len: usize = data.len();
+                (format!("{:<8}{}", "DB", data.join(",")), len)
+            },
+        }
+    }
+    /// The listing as assembler source: an ORG line, EQUs for symbols that
+    /// are not labels in the listing, then "0xADDR text" lines with labels on
+    /// lines of their own and unreached bytes as DB (or DW or text, where a
+    /// symbol gives the type).
+    pub fn listing(&self) -> String {
+        let mut body: String = String::new();
+        let mut defined: BTreeSet<u16> = BTreeSet::new();
+        let mut offset: usize = 0;
+        while offset < self.bytes.len() {
+            let addr: u16 = self.origin.wrapping_add(offset as u16);
+            if let Some(label) = self.label(addr) {
+                let comment = self.symbols.get(addr).and_then(|symbol| symbol.comment.as_ref());
+                match comment {
+                    Some(comment) => body.push_str(&format!("{:<w$}; {}\n", format!("{}:", label), comment,
+                                                            w = COMMENT_COLUMN)),
+                    None => body.push_str(&format!("{}:\n", label)),
+                }
+                defined.insert(addr);
+            }
+            if let Some(instruction) = self.instructions.get(&addr) {
+                let text: String = instruction.format_operands(|operand| match *operand {
+                    Operand::Addr(target) => self.name_for(target),
+                    // Immediates only name data, as they are often plain numbers
+                    Operand::Imm16(value) => self.symbols.containing(value)
+                        .filter(|&(symbol, _)| symbol.is_data())
+                        .and_then(|_| self.symbols.name_for(value)),
+                    _ => None,
+                });
+                body.push_str(&format!("0x{:04x} {}\n", addr, text));
+                offset += instruction.length as usize;
+                continue;
+            }
+            let (text, len) = self.data_line(offset);
+            body.push_str(&format!("0x{:04x} {}\n", addr, text));
+            offset += len;
+        }
+        let mut listing: String = format!("        ORG     ${:04x}\n", self.origin);
+        for symbol in self.symbols.iter().filter(|symbol| !defined.contains(&symbol.addr)) {
+            let equ: String = format!("{:<16}{:<8}${:04x}", symbol.name, "EQU", symbol.addr);
+            match symbol.comment {
+                Some(ref comment) => listing.push_str(&format!("{:<32}; {}\n", equ, comment)),
+                None => listing.push_str(&format!("{}\n", equ)),
+            }
+        }
+        listing.push_str(&body);
+        listing
+    }
+}
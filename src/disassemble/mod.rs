@@ -0,0 +1,458 @@
+/*
+    The 8080 opcode table and a decoder built on it.  The CPU core takes its
+    cycle counts from the same table, so there is one description of every
+    opcode shared by the disassembler, debugger and tracer.
+*/
+use std::fmt;
+
+pub mod cfg;
+pub mod flow;
+
+// Flags an instruction can change, for OpcodeInfo::flags
+pub const FLAG_Z: u8 = 0x01;
+pub const FLAG_S: u8 = 0x02;
+pub const FLAG_P: u8 = 0x04;
+pub const FLAG_CY: u8 = 0x08;
+pub const FLAG_AC: u8 = 0x10;
+const ALL_FLAGS: u8 = FLAG_Z | FLAG_S | FLAG_P | FLAG_CY | FLAG_AC;
+const ZSPA: u8 = FLAG_Z | FLAG_S | FLAG_P | FLAG_AC;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Mnemonic {
+    Nop, Lxi, Stax, Inx, Inr, Dcr, Mvi, Rlc, Dad, Ldax, Dcx, Rrc, Ral, Rar,
+    Shld, Daa, Lhld, Cma, Sta, Stc, Lda, Cmc, Mov, Hlt,
+    Add, Adc, Sub, Sbb, Ana, Xra, Ora, Cmp,
+    Rnz, Rz, Rnc, Rc, Rpo, Rpe, Rp, Rm, Ret,
+    Jnz, Jz, Jnc, Jc, Jpo, Jpe, Jp, Jm, Jmp,
+    Cnz, Cz, Cnc, Cc, Cpo, Cpe, Cp, Cm, Call,
+    Pop, Push, Rst,
+    Adi, Aci, Sui, Sbi, Ani, Xri, Ori, Cpi,
+    Out, In, Xthl, Pchl, Xchg, Sphl, Di, Ei,
+}
+
+impl Mnemonic {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Mnemonic::Nop => "NOP", Mnemonic::Lxi => "LXI", Mnemonic::Stax => "STAX",
+            Mnemonic::Inx => "INX", Mnemonic::Inr => "INR", Mnemonic::Dcr => "DCR",
+            Mnemonic::Mvi => "MVI", Mnemonic::Rlc => "RLC", Mnemonic::Dad => "DAD",
+            Mnemonic::Ldax => "LDAX", Mnemonic::Dcx => "DCX", Mnemonic::Rrc => "RRC",
+            Mnemonic::Ral => "RAL", Mnemonic::Rar => "RAR", Mnemonic::Shld => "SHLD",
+            Mnemonic::Daa => "DAA", Mnemonic::Lhld => "LHLD", Mnemonic::Cma => "CMA",
+            Mnemonic::Sta => "STA", Mnemonic::Stc => "STC", Mnemonic::Lda => "LDA",
+            Mnemonic::Cmc => "CMC", Mnemonic::Mov => "MOV", Mnemonic::Hlt => "HLT",
+            Mnemonic::Add => "ADD", Mnemonic::Adc => "ADC", Mnemonic::Sub => "SUB",
+            Mnemonic::Sbb => "SBB", Mnemonic::Ana => "ANA", Mnemonic::Xra => "XRA",
+            Mnemonic::Ora => "ORA", Mnemonic::Cmp => "CMP",
+            Mnemonic::Rnz => "RNZ", Mnemonic::Rz => "RZ", Mnemonic::Rnc => "RNC",
+            Mnemonic::Rc => "RC", Mnemonic::Rpo => "RPO", Mnemonic::Rpe => "RPE",
+            Mnemonic::Rp => "RP", Mnemonic::Rm => "RM", Mnemonic::Ret => "RET",
+            Mnemonic::Jnz => "JNZ", Mnemonic::Jz => "JZ", Mnemonic::Jnc => "JNC",
+            Mnemonic::Jc => "JC", Mnemonic::Jpo => "JPO", Mnemonic::Jpe => "JPE",
+            Mnemonic::Jp => "JP", Mnemonic::Jm => "JM", Mnemonic::Jmp => "JMP",
+            Mnemonic::Cnz => "CNZ", Mnemonic::Cz => "CZ", Mnemonic::Cnc => "CNC",
+            Mnemonic::Cc => "CC", Mnemonic::Cpo => "CPO", Mnemonic::Cpe => "CPE",
+            Mnemonic::Cp => "CP", Mnemonic::Cm => "CM", Mnemonic::Call => "CALL",
+            Mnemonic::Pop => "POP", Mnemonic::Push => "PUSH", Mnemonic::Rst => "RST",
+            Mnemonic::Adi => "ADI", Mnemonic::Aci => "ACI", Mnemonic::Sui => "SUI",
+            Mnemonic::Sbi => "SBI", Mnemonic::Ani => "ANI", Mnemonic::Xri => "XRI",
+            Mnemonic::Ori => "ORI", Mnemonic::Cpi => "CPI",
+            Mnemonic::Out => "OUT", Mnemonic::In => "IN", Mnemonic::Xthl => "XTHL",
+            Mnemonic::Pchl => "PCHL", Mnemonic::Xchg => "XCHG", Mnemonic::Sphl => "SPHL",
+            Mnemonic::Di => "DI", Mnemonic::Ei => "EI",
+        }
+    }
+}
+
+/// How an instruction passes control on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    Next,                 // Falls through to the following instruction
+    Jump,                 // JMP to its address operand
+    Branch,               // Jcc: to its address operand or falls through
+    Call,                 // CALL
+    ConditionalCall,      // Ccc
+    Restart,              // RST n: a one byte call to 8 * n
+    Return,               // RET
+    ConditionalReturn,    // Rcc
+    Indirect,             // PCHL: jumps to HL, unknown statically
+    Halt,                 // HLT: resumes after the next interrupt
+}
+
+impl Mnemonic {
+    pub fn flow(&self) -> Flow {
+        match *self {
+            Mnemonic::Jmp => Flow::Jump,
+            Mnemonic::Jnz | Mnemonic::Jz | Mnemonic::Jnc | Mnemonic::Jc |
+            Mnemonic::Jpo | Mnemonic::Jpe | Mnemonic::Jp | Mnemonic::Jm => Flow::Branch,
+            Mnemonic::Call => Flow::Call,
+            Mnemonic::Cnz | Mnemonic::Cz | Mnemonic::Cnc | Mnemonic::Cc |
+            Mnemonic::Cpo | Mnemonic::Cpe | Mnemonic::Cp | Mnemonic::Cm => Flow::ConditionalCall,
+            Mnemonic::Rst => Flow::Restart,
+            Mnemonic::Ret => Flow::Return,
+            Mnemonic::Rnz | Mnemonic::Rz | Mnemonic::Rnc | Mnemonic::Rc |
+            Mnemonic::Rpo | Mnemonic::Rpe | Mnemonic::Rp | Mnemonic::Rm => Flow::ConditionalReturn,
+            Mnemonic::Pchl => Flow::Indirect,
+            Mnemonic::Hlt => Flow::Halt,
+            _ => Flow::Next,
+        }
+    }
+}
+
+impl fmt::Display for Mnemonic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(self.name())
+    }
+}
+
+/// 8-bit registers in opcode encoding order.  M is the byte at HL.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Register { B, C, D, E, H, L, M, A }
+
+const REGISTERS: [Register; 8] = [Register::B, Register::C, Register::D, Register::E,
+                                  Register::H, Register::L, Register::M, Register::A];
+
+impl Register {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Register::B => "B", Register::C => "C", Register::D => "D", Register::E => "E",
+            Register::H => "H", Register::L => "L", Register::M => "M", Register::A => "A",
+        }
+    }
+}
+
+/// Register pairs as they are written in operands: PUSH/POP use PSW where
+/// the other instructions use SP.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RegisterPair { B, D, H, SP, PSW }
+
+impl RegisterPair {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            RegisterPair::B => "B", RegisterPair::D => "D", RegisterPair::H => "H",
+            RegisterPair::SP => "SP", RegisterPair::PSW => "PSW",
+        }
+    }
+}
+
+/// The shape of an operand in the opcode table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperandKind {
+    Reg(Register),
+    Pair(RegisterPair),
+    Imm8,                 // D8
+    Imm16,                // D16
+    Addr,                 // 16-bit address
+    Port,                 // 8-bit port number
+    Vector(u8),           // RST number
+}
+
+/// An operand with its value filled in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Reg(Register),
+    Pair(RegisterPair),
+    Imm8(u8),
+    Imm16(u16),
+    Addr(u16),
+    Port(u8),
+    Vector(u8),
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Operand::Reg(r) => write!(f, "{}", r.name()),
+            Operand::Pair(rp) => write!(f, "{}", rp.name()),
+            Operand::Imm8(value) | Operand::Port(value) => write!(f, "${:02x}", value),
+            Operand::Imm16(value) | Operand::Addr(value) => write!(f, "${:04x}", value),
+            Operand::Vector(n) => write!(f, "{}", n),
+        }
+    }
+}
+
+/// Static description of one opcode.
+#[derive(Debug, Clone, Copy)]
+pub struct OpcodeInfo {
+    pub mnemonic: Mnemonic,
+    pub operands: [Option<OperandKind>; 2],
+    pub length: u8,               // Bytes including the opcode
+    pub cycles: u8,               // T-states, not-taken cost for conditional CALL/RET
+    pub cycles_taken: u8,         // T-states when a conditional CALL/RET is taken
+    pub flags: u8,                // FLAG_* bits the instruction can change
+    pub undocumented: bool,       // Alias of NOP, JMP, RET or CALL
+}
+
+const fn op(mnemonic: Mnemonic, operands: [Option<OperandKind>; 2], cycles: u8, flags: u8) -> OpcodeInfo {
+    let mut length: u8 = 1;
+    let mut index: usize = 0;
+    while index < 2 {
+        length += match operands[index] {
+            Some(OperandKind::Imm8) | Some(OperandKind::Port) => 1,
+            Some(OperandKind::Imm16) | Some(OperandKind::Addr) => 2,
+            _ => 0,
+        };
+        index += 1;
+    }
+    OpcodeInfo { mnemonic, operands, length, cycles, cycles_taken: cycles, flags, undocumented: false }
+}
+
+const fn alias(info: OpcodeInfo) -> OpcodeInfo {
+    OpcodeInfo { undocumented: true, ..info }
+}
+
+const fn taken(info: OpcodeInfo, cycles_taken: u8) -> OpcodeInfo {
+    OpcodeInfo { cycles_taken, ..info }
+}
+
+const NONE: [Option<OperandKind>; 2] = [None, None];
+
+const fn one(kind: OperandKind) -> [Option<OperandKind>; 2] {
+    [Some(kind), None]
+}
+
+const fn two(first: OperandKind, second: OperandKind) -> [Option<OperandKind>; 2] {
+    [Some(first), Some(second)]
+}
+
+const fn reg(r: u8) -> OperandKind {
+    OperandKind::Reg(REGISTERS[(r & 0x07) as usize])
+}
+
+/// Register pair field of LXI/INX/DCX/DAD (`psw` false) or PUSH/POP (`psw` true).
+const fn pair(opcode: u8, psw: bool) -> OperandKind {
+    OperandKind::Pair(match (opcode >> 4) & 0x03 {
+        0 => RegisterPair::B,
+        1 => RegisterPair::D,
+        2 => RegisterPair::H,
+        _ => if psw { RegisterPair::PSW } else { RegisterPair::SP },
+    })
+}
+
+const fn describe(opcode: u8) -> OpcodeInfo {
+    use self::Mnemonic::*;
+    use self::OperandKind::{Addr, Imm16, Imm8, Port, Vector};
+    let dst: u8 = (opcode >> 3) & 0x07;
+    let src: u8 = opcode & 0x07;
+    // Cycles for forms that touch M
+    let m_dst: bool = dst == 6;
+    let m_src: bool = src == 6;
+    match opcode {
+        0x00 => op(Nop, NONE, 4, 0),
+        0x08 | 0x10 | 0x18 | 0x20 | 0x28 | 0x30 | 0x38 => alias(op(Nop, NONE, 4, 0)),
+        0x01 | 0x11 | 0x21 | 0x31 => op(Lxi, two(pair(opcode, false), Imm16), 10, 0),
+        0x02 | 0x12 => op(Stax, one(pair(opcode, false)), 7, 0),
+        0x0a | 0x1a => op(Ldax, one(pair(opcode, false)), 7, 0),
+        0x03 | 0x13 | 0x23 | 0x33 => op(Inx, one(pair(opcode, false)), 5, 0),
+        0x0b | 0x1b | 0x2b | 0x3b => op(Dcx, one(pair(opcode, false)), 5, 0),
+        0x09 | 0x19 | 0x29 | 0x39 => op(Dad, one(pair(opcode, false)), 10, FLAG_CY),
+        0x04 | 0x0c | 0x14 | 0x1c | 0x24 | 0x2c | 0x34 | 0x3c =>
+            op(Inr, one(reg(dst)), if m_dst { 10 } else { 5 }, ZSPA),
+        0x05 | 0x0d | 0x15 | 0x1d | 0x25 | 0x2d | 0x35 | 0x3d =>
+            op(Dcr, one(reg(dst)), if m_dst { 10 } else { 5 }, ZSPA),
+        0x06 | 0x0e | 0x16 | 0x1e | 0x26 | 0x2e | 0x36 | 0x3e =>
+            op(Mvi, two(reg(dst), Imm8), if m_dst { 10 } else { 7 }, 0),
+        0x07 => op(Rlc, NONE, 4, FLAG_CY),
+        0x0f => op(Rrc, NONE, 4, FLAG_CY),
+        0x17 => op(Ral, NONE, 4, FLAG_CY),
+        0x1f => op(Rar, NONE, 4, FLAG_CY),
+        0x22 => op(Shld, one(Addr), 16, 0),
+        0x2a => op(Lhld, one(Addr), 16, 0),
+        0x27 => op(Daa, NONE, 4, ALL_FLAGS),
+        0x2f => op(Cma, NONE, 4, 0),
+        0x32 => op(Sta, one(Addr), 13, 0),
+        0x3a => op(Lda, one(Addr), 13, 0),
+        0x37 => op(Stc, NONE, 4, FLAG_CY),
+        0x3f => op(Cmc, NONE, 4, FLAG_CY),
+        0x76 => op(Hlt, NONE, 7, 0),
+        0x40..=0x7f => op(Mov, two(reg(dst), reg(src)), if m_dst || m_src { 7 } else { 5 }, 0),
+        0x80..=0xbf => {
+            let mnemonic: Mnemonic = match dst {
+                0 => Add, 1 => Adc, 2 => Sub, 3 => Sbb, 4 => Ana, 5 => Xra, 6 => Ora, _ => Cmp,
+            };
+            op(mnemonic, one(reg(src)), if m_src { 7 } else { 4 }, ALL_FLAGS)
+        },
+        0xc0 | 0xc8 | 0xd0 | 0xd8 | 0xe0 | 0xe8 | 0xf0 | 0xf8 => {
+            let mnemonic: Mnemonic = match dst {
+                0 => Rnz, 1 => Rz, 2 => Rnc, 3 => Rc, 4 => Rpo, 5 => Rpe, 6 => Rp, _ => Rm,
+            };
+            taken(op(mnemonic, NONE, 5, 0), 11)
+        },
+        0xc2 | 0xca | 0xd2 | 0xda | 0xe2 | 0xea | 0xf2 | 0xfa => {
+            let mnemonic: Mnemonic = match dst {
+                0 => Jnz, 1 => Jz, 2 => Jnc, 3 => Jc, 4 => Jpo, 5 => Jpe, 6 => Jp, _ => Jm,
+            };
+            op(mnemonic, one(Addr), 10, 0)
+        },
+        0xc4 | 0xcc | 0xd4 | 0xdc | 0xe4 | 0xec | 0xf4 | 0xfc => {
+            let mnemonic: Mnemonic = match dst {
+                0 => Cnz, 1 => Cz, 2 => Cnc, 3 => Cc, 4 => Cpo, 5 => Cpe, 6 => Cp, _ => Cm,
+            };
+            taken(op(mnemonic, one(Addr), 11, 0), 17)
+        },
+        0xc9 => op(Ret, NONE, 10, 0),
+        0xd9 => alias(op(Ret, NONE, 10, 0)),
+        0xc3 => op(Jmp, one(Addr), 10, 0),
+        0xcb => alias(op(Jmp, one(Addr), 10, 0)),
+        0xcd => op(Call, one(Addr), 17, 0),
+        0xdd | 0xed | 0xfd => alias(op(Call, one(Addr), 17, 0)),
+        0xc1 | 0xd1 | 0xe1 => op(Pop, one(pair(opcode, true)), 10, 0),
+        0xf1 => op(Pop, one(pair(opcode, true)), 10, ALL_FLAGS),
+        0xc5 | 0xd5 | 0xe5 | 0xf5 => op(Push, one(pair(opcode, true)), 11, 0),
+        0xc6 => op(Adi, one(Imm8), 7, ALL_FLAGS),
+        0xce => op(Aci, one(Imm8), 7, ALL_FLAGS),
+        0xd6 => op(Sui, one(Imm8), 7, ALL_FLAGS),
+        0xde => op(Sbi, one(Imm8), 7, ALL_FLAGS),
+        0xe6 => op(Ani, one(Imm8), 7, ALL_FLAGS),
+        0xee => op(Xri, one(Imm8), 7, ALL_FLAGS),
+        0xf6 => op(Ori, one(Imm8), 7, ALL_FLAGS),
+        0xfe => op(Cpi, one(Imm8), 7, ALL_FLAGS),
+        0xc7 | 0xcf | 0xd7 | 0xdf | 0xe7 | 0xef | 0xf7 | 0xff => op(Rst, one(Vector(dst)), 11, 0),
+        0xd3 => op(Out, one(Port), 10, 0),
+        0xdb => op(In, one(Port), 10, 0),
+        0xe3 => op(Xthl, NONE, 18, 0),
+        0xe9 => op(Pchl, NONE, 5, 0),
+        0xeb => op(Xchg, NONE, 4, 0),
+        0xf9 => op(Sphl, NONE, 5, 0),
+        0xf3 => op(Di, NONE, 4, 0),
+        0xfb => op(Ei, NONE, 4, 0),
+    }
+}
+
+const fn build_table() -> [OpcodeInfo; 256] {
+    let mut table: [OpcodeInfo; 256] = [op(Mnemonic::Nop, NONE, 4, 0); 256];
+    let mut opcode: usize = 0;
+    while opcode < 256 {
+        table[opcode] = describe(opcode as u8);
+        opcode += 1;
+    }
+    table
+}
+
+/// Every opcode, indexed by its value.
+pub const OPCODES: [OpcodeInfo; 256] = build_table();
+
+/// A decoded instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    pub addr: u16,
+    pub opcode: u8,
+    pub mnemonic: Mnemonic,
+    pub operands: Vec<Operand>,
+    pub length: u8,
+    pub cycles: u8,
+    pub cycles_taken: u8,
+    pub flags: u8,
+    pub undocumented: bool,
+}
+
+impl Instruction {
+    /// The immediate address of a jump, call or memory access, if any.
+    pub fn target(&self) -> Option<u16> {
+        self.operands.iter().filter_map(|operand| match *operand {
+            Operand::Addr(addr) => Some(addr),
+            _ => None,
+        }).next()
+    }
+    /// Where control can go after this instruction, other than falling
+    /// through: the address operand of jumps and calls, or the RST vector.
+    pub fn branch_target(&self) -> Option<u16> {
+        match self.mnemonic.flow() {
+            Flow::Jump | Flow::Branch | Flow::Call | Flow::ConditionalCall => self.target(),
+            Flow::Restart => Some((self.opcode & 0x38) as u16),
+            _ => None,
+        }
+    }
+    /// Address of the following instruction.
+    pub fn next_addr(&self) -> u16 {
+        self.addr.wrapping_add(self.length as u16)
+    }
+    /// Formats the instruction with address operands replaced by whatever
+    /// `name` returns for them.
+    pub fn format_with<F: Fn(u16) -> Option<String>>(&self, name: F) -> String {
+        self.format_operands(|operand| match *operand {
+            Operand::Addr(addr) => name(addr),
+            _ => None,
+        })
+    }
+    /// Formats the instruction with any operand replaced by whatever `name`
+    /// returns for it.
+    pub fn format_operands<F: Fn(&Operand) -> Option<String>>(&self, name: F) -> String {
+        if self.operands.is_empty() {
+            return self.mnemonic.to_string();
+        }
+        let operands: Vec<String> = self.operands.iter()
+            .map(|operand| name(operand).unwrap_or_else(|| operand.to_string()))
+            .collect();
+        format!("{:<8}{}", self.mnemonic, operands.join(","))
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.format_with(|_| None))
+    }
+}
+
+/// Decodes the instruction at the start of `bytes`, which were loaded at
+/// `addr`.  Operand bytes past the end of `bytes` read as 0.
+pub fn decode(bytes: &[u8], addr: u16) -> Instruction {
+    let byte = |index: usize| -> u8 { bytes.get(index).cloned().unwrap_or(0) };
+    let opcode: u8 = byte(0);
+    let info: OpcodeInfo = OPCODES[opcode as usize];
+    let word: u16 = ((byte(2) as u16) << 8) | byte(1) as u16;
+    let operands: Vec<Operand> = info.operands.iter().filter_map(|kind| kind.map(|kind| match kind {
+        OperandKind::Reg(r) => Operand::Reg(r),
+        OperandKind::Pair(rp) => Operand::Pair(rp),
+        OperandKind::Imm8 => Operand::Imm8(byte(1)),
+        OperandKind::Port => Operand::Port(byte(1)),
+        OperandKind::Imm16 => Operand::Imm16(word),
+        OperandKind::Addr => Operand::Addr(word),
+        OperandKind::Vector(n) => Operand::Vector(n),
+    })).collect();
+    Instruction {
+        addr,
+        opcode,
+        mnemonic: info.mnemonic,
+        operands,
+        length: info.length,
+        cycles: info.cycles,
+        cycles_taken: info.cycles_taken,
+        flags: info.flags,
+        undocumented: info.undocumented,
+    }
+}
+
+/// Linear sweep over a buffer, decoding one instruction after another.
+pub struct Instructions<'a> {
+    bytes: &'a [u8],
+    origin: u16,
+    offset: usize,
+}
+
+impl<'a> Iterator for Instructions<'a> {
+    type Item = Instruction;
+    fn next(&mut self) -> Option<Instruction> {
+        if self.offset >= self.bytes.len() {
+            return None;
+        }
+        let instruction = decode(&self.bytes[self.offset..],
+                                 self.origin.wrapping_add(self.offset as u16));
+        self.offset += instruction.length as usize;
+        Some(instruction)
+    }
+}
+
+/// Iterates over the instructions in `bytes`, which were loaded at `origin`.
+pub fn instructions(bytes: &[u8], origin: u16) -> Instructions<'_> {
+    Instructions { bytes, origin, offset: 0 }
+}
+
+/// Linear listing of `buffer`, one "0xADDR INSTRUCTION" line per instruction.
+pub fn disassemble(buffer: &[u8], origin: u16) -> String {
+    let mut listing = String::new();
+    for instruction in instructions(buffer, origin) {
+        listing.push_str(&format!("0x{:04x} {}\n", instruction.addr, instruction));
+    }
+    listing
+}
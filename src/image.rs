@@ -0,0 +1,110 @@
+/*
+    Minimal PNG and BMP writers for RGBA pixel buffers, so frames can be
+    inspected without pulling in an image library.
+*/
+use std::io;
+use std::io::prelude::*;
+
+/// CRC-32 (ISO 3309), as used by PNG chunks.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc: u32 = 0xffff_ffff;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask: u32 = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let mut a: u32 = 1;
+    let mut b: u32 = 0;
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+fn write_chunk(out: &mut dyn Write, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    let mut body: Vec<u8> = Vec::with_capacity(data.len() + 4);
+    body.extend_from_slice(kind);
+    body.extend_from_slice(data);
+    out.write_all(&body)?;
+    out.write_all(&crc32(&body).to_be_bytes())
+}
+
+/// Wraps `data` in a zlib stream made of uncompressed deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out: Vec<u8> = vec![0x78, 0x01];
+    let mut blocks = data.chunks(0xffff).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[0x01, 0x00, 0x00, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let last: u8 = blocks.peek().is_none() as u8;
+        let len: u16 = block.len() as u16;
+        out.push(last);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+/// Writes `rgba` (`width * height` pixels, 4 bytes each, top row first) as an
+/// 8-bit RGBA PNG.
+pub fn write_png(out: &mut dyn Write, width: usize, height: usize, rgba: &[u8]) -> io::Result<()> {
+    out.write_all(&[0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a])?;
+    let mut header: Vec<u8> = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // 8 bits per channel, RGBA, deflate, adaptive filtering, no interlace
+    header.extend_from_slice(&[8, 6, 0, 0, 0]);
+    write_chunk(out, b"IHDR", &header)?;
+    let mut scanlines: Vec<u8> = Vec::with_capacity(height * (width * 4 + 1));
+    for row in rgba.chunks(width * 4) {
+        // Filter type 0 (none) for every scanline
+        scanlines.push(0);
+        scanlines.extend_from_slice(row);
+    }
+    write_chunk(out, b"IDAT", &zlib_stored(&scanlines))?;
+    write_chunk(out, b"IEND", &[])
+}
+
+/// Writes `rgba` as a 24-bit uncompressed BMP.
+pub fn write_bmp(out: &mut dyn Write, width: usize, height: usize, rgba: &[u8]) -> io::Result<()> {
+    // Rows are padded to a multiple of 4 bytes and stored bottom row first
+    let row_size: usize = (width * 3 + 3) & !3;
+    let image_size: usize = row_size * height;
+    let mut data: Vec<u8> = Vec::with_capacity(54 + image_size);
+    data.extend_from_slice(b"BM");
+    data.extend_from_slice(&((54 + image_size) as u32).to_le_bytes());
+    data.extend_from_slice(&[0, 0, 0, 0]);
+    data.extend_from_slice(&54u32.to_le_bytes());
+    // BITMAPINFOHEADER
+    data.extend_from_slice(&40u32.to_le_bytes());
+    data.extend_from_slice(&(width as i32).to_le_bytes());
+    data.extend_from_slice(&(height as i32).to_le_bytes());
+    data.extend_from_slice(&1u16.to_le_bytes());
+    data.extend_from_slice(&24u16.to_le_bytes());
+    data.extend_from_slice(&0u32.to_le_bytes());
+    data.extend_from_slice(&(image_size as u32).to_le_bytes());
+    // 72 DPI
+    data.extend_from_slice(&2835i32.to_le_bytes());
+    data.extend_from_slice(&2835i32.to_le_bytes());
+    data.extend_from_slice(&0u32.to_le_bytes());
+    data.extend_from_slice(&0u32.to_le_bytes());
+    for row in rgba.chunks(width * 4).rev() {
+        let start: usize = data.len();
+        for pixel in row.chunks(4) {
+            data.extend_from_slice(&[pixel[2], pixel[1], pixel[0]]);
+        }
+        data.resize(start + row_size, 0);
+    }
+    out.write_all(&data)
+}
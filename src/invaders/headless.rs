@@ -0,0 +1,256 @@
+/*
+    Running the machine without a terminal: scripted inputs, a fixed number
+    of frames and PNG dumps plus hashes of chosen frames, so screens can be
+    compared against known good runs.  Runs can also be recorded to a movie,
+    or driven by one in place of the script.
+*/
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use image;
+use invaders::movie::{Movie, MovieError};
+use invaders::video;
+use invaders::*;
+use trace::{TraceFilter, Tracer};
+use vm::VmError;
+
+// Bits of port 2 that are DIP switches rather than controls
+const DIP_SWITCHES: u8 = 0x8b;
+
+/// Controls held down, as the bits of ports 1 and 2.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Buttons {
+    pub port1: u8,
+    pub port2: u8,
+}
+
+impl Buttons {
+    /// Parses a control name as used in input scripts.
+    pub fn from_name(name: &str) -> Option<Buttons> {
+        let (port1, port2) = match name {
+            "coin" => (COIN, 0),
+            "p1start" => (P1_START, 0),
+            "p2start" => (P2_START, 0),
+            "p1fire" => (P1_FIRE, 0),
+            "p1left" => (P1_LEFT, 0),
+            "p1right" => (P1_RIGHT, 0),
+            "p2fire" => (0, P2_FIRE),
+            "p2left" => (0, P2_LEFT),
+            "p2right" => (0, P2_RIGHT),
+            "tilt" => (0, TILT),
+            _ => return None,
+        };
+        Some(Buttons { port1, port2 })
+    }
+    /// Presents these controls on the machine's input ports, keeping the
+    /// DIP switches and the always-high bit of port 1.
+    pub fn apply(&self, io: &mut InvadersIo) {
+        io.port1 = 0x08 | self.port1;
+        io.port2 = (io.port2 & DIP_SWITCHES) | self.port2;
+    }
+}
+
+/// Which controls are held from which frame on.  Frames are numbered by how
+/// many have completed, so an entry for frame 0 applies from power on.
+///
+/// The text form has one entry per line: a frame number followed by the
+/// names of every control held from that frame until the next entry, e.g.
+///
+/// ```text
+/// # insert a coin and start a one player game
+/// 200 coin
+/// 205
+/// 260 p1start
+/// 265
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct InputScript {
+    changes: Vec<(u64, Buttons)>,
+}
+
+impl InputScript {
+    pub fn new() -> InputScript {
+        InputScript { changes: Vec::new() }
+    }
+    /// Holds `buttons` from `frame` until the next change.
+    pub fn hold(&mut self, frame: u64, buttons: Buttons) {
+        self.changes.retain(|&(f, _)| f != frame);
+        self.changes.push((frame, buttons));
+        self.changes.sort_by_key(|&(f, _)| f);
+    }
+    pub fn parse(text: &str) -> Result<InputScript, HeadlessError> {
+        let mut script = InputScript::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("");
+            let mut words = line.split_whitespace();
+            let frame: u64 = match words.next() {
+                Some(word) => word.parse().map_err(|_| HeadlessError::Script {
+                    line: index + 1,
+                    message: format!("bad frame number `{}`", word),
+                })?,
+                None => continue,
+            };
+            let mut buttons = Buttons::default();
+            for word in words {
+                let pressed = Buttons::from_name(word).ok_or_else(|| HeadlessError::Script {
+                    line: index + 1,
+                    message: format!("unknown control `{}`", word),
+                })?;
+                buttons.port1 |= pressed.port1;
+                buttons.port2 |= pressed.port2;
+            }
+            script.hold(frame, buttons);
+        }
+        Ok(script)
+    }
+    pub fn load(path: &Path) -> Result<InputScript, HeadlessError> {
+        let mut text = String::new();
+        File::open(path)?.read_to_string(&mut text)?;
+        InputScript::parse(&text)
+    }
+    /// Controls held during `frame`.
+    pub fn buttons_at(&self, frame: u64) -> Buttons {
+        self.changes.iter()
+            .take_while(|&&(f, _)| f <= frame)
+            .last()
+            .map(|&(_, buttons)| buttons)
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HeadlessConfig {
+    pub frames: u64,              // Frames to emulate
+    pub dump_frames: Vec<u64>,    // Frames to hash, and to save if output_dir is set
+    pub output_dir: Option<PathBuf>,
+    pub overlay: bool,            // Render with the colour overlay
+    pub script: InputScript,
+    pub trace: Option<PathBuf>,   // Execution trace file
+    pub trace_filter: TraceFilter,
+    pub play: Option<Movie>,      // Movie to replay instead of the script and frame count
+    pub record: Option<PathBuf>,  // Where to save a movie of the run
+}
+
+/// A dumped frame.  Frame numbers count from 1: frame N is the picture after
+/// N calls to run_frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameDump {
+    pub frame: u64,
+    pub hash: u64,
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Debug)]
+pub enum HeadlessError {
+    Io(io::Error),
+    Vm { frame: u64, error: VmError },
+    Script { line: usize, message: String },
+    Movie(MovieError),
+}
+
+impl fmt::Display for HeadlessError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HeadlessError::Io(ref error) => write!(f, "{}", error),
+            HeadlessError::Vm { frame, ref error } => write!(f, "frame {}: {}", frame, error),
+            HeadlessError::Script { line, ref message } =>
+                write!(f, "input script line {}: {}", line, message),
+            HeadlessError::Movie(ref error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl Error for HeadlessError {}
+
+impl From<io::Error> for HeadlessError {
+    fn from(error: io::Error) -> HeadlessError {
+        HeadlessError::Io(error)
+    }
+}
+
+impl From<MovieError> for HeadlessError {
+    fn from(error: MovieError) -> HeadlessError {
+        HeadlessError::Movie(error)
+    }
+}
+
+/// 64-bit FNV-1a, stable across platforms and releases.
+pub fn hash_pixels(pixels: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in pixels {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+
+/// Runs `machine` for `config.frames` frames with the scripted inputs, or
+/// through the whole of `config.play` from its start, and returns the hash
+/// of every requested frame, writing frame_NNNNN.png files into the output
+/// directory when one is given.  A played movie must end in the state it
+/// was recorded with.
+pub fn run(machine: &mut Invaders, config: &HeadlessConfig) -> Result<Vec<FrameDump>, HeadlessError> {
+    let mut dumps: Vec<FrameDump> = Vec::new();
+    let frames: u64 = match config.play {
+        Some(ref movie) => {
+            movie.rewind(machine)?;
+            movie.inputs.len() as u64
+        },
+        None => config.frames,
+    };
+    let mut recording: Option<Movie> = config.record.as_ref().map(|_| Movie::new(machine));
+    let mut tracer: Option<Tracer> = match config.trace {
+        Some(ref path) => Some(Tracer::new(Box::new(BufWriter::new(File::create(path)?)),
+                                           config.trace_filter.clone())),
+        None => None,
+    };
+    for index in 0..frames {
+        match config.play {
+            Some(ref movie) => movie.apply(index as usize, &mut machine.vm.io),
+            None => config.script.buttons_at(machine.frame).apply(&mut machine.vm.io),
+        }
+        if let Some(ref mut movie) = recording {
+            movie.record(&machine.vm.io);
+        }
+        let ran: Result<(), VmError> = match tracer {
+            Some(ref mut tracer) => tracer.run_frame(machine),
+            None => machine.run_frame(),
+        };
+        if let Err(error) = ran {
+            // The end of the trace is what explains the error
+            if let Some(tracer) = tracer {
+                tracer.finish()?;
+            }
+            return Err(HeadlessError::Vm { frame: machine.frame, error });
+        }
+        if !config.dump_frames.contains(&machine.frame) {
+            continue;
+        }
+        let pixels: Vec<u8> = video::frame(&machine.vm, config.overlay);
+        let path: Option<PathBuf> = match config.output_dir {
+            Some(ref dir) => {
+                let path = dir.join(format!("frame_{:05}.png", machine.frame));
+                let mut file = File::create(&path)?;
+                image::write_png(&mut file, video::WIDTH, video::HEIGHT, &pixels)?;
+                Some(path)
+            },
+            None => None,
+        };
+        dumps.push(FrameDump { frame: machine.frame, hash: hash_pixels(&pixels), path });
+    }
+    if let Some(tracer) = tracer {
+        tracer.finish()?;
+    }
+    if let (Some(mut movie), Some(path)) = (recording, config.record.as_ref()) {
+        movie.finish(machine);
+        movie.save(path)?;
+    }
+    if let Some(ref movie) = config.play {
+        movie.verify(machine)?;
+    }
+    Ok(dumps)
+}
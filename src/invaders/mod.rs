@@ -0,0 +1,274 @@
+/*
+    Space Invaders (Taito/Midway 8080) machine: memory map, input ports and
+    the external shift register used to draw sprites at any x position.
+*/
+use debugger::Machine;
+use savestate::{SaveState, StateError};
+use vm::{Event, MemoryMap, PortIo, RegionKind, Vm, VmError};
+
+pub mod headless;
+pub mod movie;
+pub mod state;
+pub mod video;
+
+pub const CLOCK_HZ: u32 = 2_000_000;
+pub const FRAMES_PER_SECOND: u32 = 60;
+pub const CYCLES_PER_FRAME: u32 = CLOCK_HZ / FRAMES_PER_SECOND;
+// 224 visible lines plus vertical blanking
+pub const SCANLINES: u32 = 256;
+// The video hardware raises RST 1 when the beam reaches line 96 and RST 2
+// when it enters vertical blanking at line 224.
+pub const MID_SCREEN_LINE: u32 = 96;
+pub const VBLANK_LINE: u32 = 224;
+pub const MID_SCREEN_CYCLE: u32 = CYCLES_PER_FRAME * MID_SCREEN_LINE / SCANLINES;
+pub const VBLANK_CYCLE: u32 = CYCLES_PER_FRAME * VBLANK_LINE / SCANLINES;
+// What happens when during each frame: the two interrupts, then the end
+const SCHEDULE: [(u32, Option<i32>); 3] = [
+    (MID_SCREEN_CYCLE, Some(1)),
+    (VBLANK_CYCLE, Some(2)),
+    (CYCLES_PER_FRAME, None),
+];
+
+// Port 1 input bits
+pub const COIN: u8 = 0x01;
+pub const P2_START: u8 = 0x02;
+pub const P1_START: u8 = 0x04;
+pub const P1_FIRE: u8 = 0x10;
+pub const P1_LEFT: u8 = 0x20;
+pub const P1_RIGHT: u8 = 0x40;
+// Port 2 input bits (the rest are DIP switches)
+pub const TILT: u8 = 0x04;
+pub const P2_FIRE: u8 = 0x10;
+pub const P2_LEFT: u8 = 0x20;
+pub const P2_RIGHT: u8 = 0x40;
+
+/// The 16-bit shift register behind ports 2, 3 and 4.  Each OUT 4 shifts a new
+/// byte into the high half, OUT 2 picks the bit offset and IN 3 returns the
+/// byte `offset` bits from the top.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ShiftRegister {
+    pub value: u16,
+    pub offset: u8,
+}
+
+impl ShiftRegister {
+    pub fn write_data(&mut self, data: u8) {
+        self.value = ((data as u16) << 8) | (self.value >> 8);
+    }
+    pub fn set_offset(&mut self, offset: u8) {
+        self.offset = offset & 0x07;
+    }
+    pub fn read(&self) -> u8 {
+        (self.value >> (8 - self.offset)) as u8
+    }
+}
+
+/// The cabinet's I/O ports.
+#[derive(Debug, Clone)]
+pub struct InvadersIo {
+    pub shifter: ShiftRegister,
+    pub port1: u8,        // Coin, start buttons and player 1 controls
+    pub port2: u8,        // DIP switches and player 2 controls
+    pub sound1: u8,       // Last value written to port 3
+    pub sound2: u8,       // Last value written to port 5
+    pub watchdog: u8,     // Last value written to port 6
+}
+
+impl Default for InvadersIo {
+    fn default() -> InvadersIo {
+        InvadersIo {
+            shifter: ShiftRegister::default(),
+            // Bit 3 of port 1 is wired high
+            port1: 0x08,
+            port2: 0x00,
+            sound1: 0,
+            sound2: 0,
+            watchdog: 0,
+        }
+    }
+}
+
+impl PortIo for InvadersIo {
+    fn input(&mut self, port: u8) -> Option<u8> {
+        match port {
+            // Port 0 is only read by the self test, bits 1-3 are wired high
+            0 => Some(0x0e),
+            1 => Some(self.port1),
+            2 => Some(self.port2),
+            3 => Some(self.shifter.read()),
+            _ => None,
+        }
+    }
+    fn output(&mut self, port: u8, value: u8) -> bool {
+        match port {
+            2 => self.shifter.set_offset(value),
+            3 => self.sound1 = value,
+            4 => self.shifter.write_data(value),
+            5 => self.sound2 = value,
+            6 => self.watchdog = value,
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// ROM at 0x0000-0x1fff, RAM (including video RAM from 0x2400) at
+/// 0x2000-0x3fff, and the RAM mirrored through the rest of the address space.
+pub fn memory_map() -> MemoryMap {
+    let mut map = MemoryMap::new();
+    map.map(0x0000, 0x1fff, RegionKind::Rom);
+    map.map(0x2000, 0x3fff, RegionKind::Ram);
+    map.map(0x4000, 0xffff, RegionKind::Mirror { target: 0x2000, len: 0x2000 });
+    map
+}
+
+pub struct Invaders {
+    pub vm: Vm<InvadersIo>,
+    pub frame: u64,           // Frames completed since reset
+    frame_start: u64,         // Value of vm.cycles when the current frame began
+    next_event: usize,        // Index into SCHEDULE
+}
+
+impl Invaders {
+    /// Builds the machine with `rom` (the 8 KiB invaders.h/g/f/e image) loaded
+    /// at 0x0000.
+    pub fn new(rom: &[u8]) -> Invaders {
+        let mut vm = Vm::with_io(InvadersIo::default());
+        vm.memory = Box::new(memory_map());
+        vm.memory.load(0x0000, rom);
+        Invaders { vm, frame: 0, frame_start: 0, next_event: 0 }
+    }
+    /// T-states executed since the current frame began.
+    pub fn frame_cycle(&self) -> u32 {
+        (self.vm.cycles - self.frame_start) as u32
+    }
+    /// Advances the machine by one event: an interrupt or the end of the
+    /// frame if one is due, otherwise the next instruction.  Instructions are
+    /// never split, so events can come a few T-states late.  A halted CPU
+    /// idles until the next event.
+    pub fn step(&mut self) -> Result<Event, VmError> {
+        let (due, vector) = SCHEDULE[self.next_event];
+        let position: u32 = self.frame_cycle();
+        if position >= due {
+            self.next_event = (self.next_event + 1) % SCHEDULE.len();
+            return Ok(match vector {
+                Some(n) => Event::Interrupt { vector: n as u8, accepted: self.vm.generate_interrupt(n) },
+                None => {
+                    // Any overshoot is carried into the next frame
+                    self.frame_start += CYCLES_PER_FRAME as u64;
+                    self.frame += 1;
+                    Event::Frame
+                },
+            });
+        }
+        match self.vm.run_current_opcode() {
+            Ok(outcome) => Ok(Event::Instruction(outcome)),
+            Err(VmError::Halted { .. }) => {
+                self.vm.cycles += (due - position) as u64;
+                Ok(Event::Idle { cycles: due - position })
+            },
+            Err(error) => Err(error),
+        }
+    }
+    /// Emulates one video frame, delivering the mid-screen and vblank
+    /// interrupts at their scanlines.  An interrupt that arrives while the
+    /// game has interrupts disabled is lost, as on the real board.
+    pub fn run_frame(&mut self) -> Result<(), VmError> {
+        loop {
+            if let Event::Frame = self.step()? {
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl Machine for Invaders {
+    type Io = InvadersIo;
+    fn vm(&self) -> &Vm<InvadersIo> {
+        &self.vm
+    }
+    fn vm_mut(&mut self) -> &mut Vm<InvadersIo> {
+        &mut self.vm
+    }
+    fn step(&mut self) -> Result<Event, VmError> {
+        Invaders::step(self)
+    }
+    fn save_devices(&self, state: &mut SaveState) {
+        Invaders::save_devices(self, state)
+    }
+    fn load_devices(&mut self, state: &SaveState) -> Result<(), StateError> {
+        Invaders::load_devices(self, state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shift_register_ports() {
+        let mut io = InvadersIo::default();
+        io.output(4, 0xaa);
+        io.output(4, 0xff);
+        // (OUT 2 value, IN 3 result) with 0xffaa in the register
+        let reads: [(u8, u8); 5] = [
+            (0x00, 0xff),
+            (0x03, 0xfd),
+            (0x07, 0xd5),
+            (0xfb, 0xfd),             // Only the low 3 bits count
+            (0x08, 0xff),
+        ];
+        for &(offset, value) in reads.iter() {
+            assert!(io.output(2, offset));
+            assert_eq!(io.input(3), Some(value), "offset {:02x}", offset);
+        }
+        // A new byte goes in the top and pushes the old top down
+        io.output(4, 0x12);
+        assert_eq!(io.shifter.value, 0x12ff);
+        io.output(2, 0x00);
+        assert_eq!(io.input(3), Some(0x12));
+        io.output(2, 0x02);
+        assert_eq!(io.input(3), Some(0x4b));
+    }
+
+    #[test]
+    fn shift_register_from_the_cpu() {
+        // MVI A,0AAh; OUT 4; MVI A,0FFh; OUT 4; MVI A,0FBh; OUT 2; IN 3
+        let mut machine = Invaders::new(&[0x3e, 0xaa, 0xd3, 0x04, 0x3e, 0xff, 0xd3, 0x04,
+                                          0x3e, 0xfb, 0xd3, 0x02, 0xdb, 0x03]);
+        for _ in 0..7 {
+            machine.vm.run_current_opcode().unwrap();
+        }
+        assert_eq!(machine.vm.a, 0xfd);
+    }
+
+    #[test]
+    fn interrupts_fire_at_their_scanlines() {
+        let rom: [u8; 0x12] = [
+            0x31, 0x00, 0x24,         // LXI SP,2400h
+            0xfb,                     // EI
+            0x76,                     // HLT
+            0xc3, 0x04, 0x00,         // JMP 0004h
+            0xfb, 0xc9,               // RST 1: EI; RET
+            0, 0, 0, 0, 0, 0,
+            0xfb, 0xc9,               // RST 2: EI; RET
+        ];
+        let mut machine = Invaders::new(&rom);
+        for frame in 0..2 {
+            let mut interrupts: Vec<(u32, u8, bool)> = Vec::new();
+            loop {
+                let at: u32 = machine.frame_cycle();
+                match machine.step().unwrap() {
+                    Event::Interrupt { vector, accepted } => interrupts.push((at, vector, accepted)),
+                    Event::Frame => break,
+                    _ => {},
+                }
+            }
+            // Halted until each is due, so they come exactly on time
+            assert_eq!(interrupts, vec![(12499, 1, true), (29166, 2, true)], "frame {}", frame);
+            assert_eq!(machine.vm.cycles, (frame + 1) * 33333);
+            assert_eq!(machine.frame, frame + 1);
+            assert_eq!(machine.frame_cycle(), 0);
+        }
+    }
+}
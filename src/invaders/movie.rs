@@ -0,0 +1,161 @@
+/*
+    Movies: the input ports of every frame of a run, replayed exactly to
+    reproduce it.  A movie starts from a save state, so it can begin
+    anywhere, and ends with a checksum of the whole machine so a replay that
+    went a different way is caught.
+
+    File layout, all integers little-endian:
+        "R8080MV" 0x1a          magic
+        u16                     format version
+        u64                     frame count N
+        N x (u8, u8)            ports 1 and 2 during each frame
+        u64                     checksum of the machine after the last frame
+        the rest                save state to start from
+*/
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+use invaders::{Invaders, InvadersIo};
+use invaders::headless::hash_pixels;
+use savestate::{put_u16, put_u64, SaveState, StateError};
+
+pub const MAGIC: &[u8; 8] = b"R8080MV\x1a";
+pub const VERSION: u16 = 1;
+// Magic, version and frame count
+const HEADER_LEN: usize = 18;
+
+#[derive(Debug)]
+pub enum MovieError {
+    Io(io::Error),
+    NotAMovie,
+    Version(u16),             // Written by a newer or older format
+    Corrupt(String),
+    State(StateError),        // The start state is bad or for another ROM
+    Desync { frames: u64, expected: u64, actual: u64 },
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MovieError::Io(ref error) => write!(f, "{}", error),
+            MovieError::NotAMovie => write!(f, "not a movie"),
+            MovieError::Version(version) => {
+                write!(f, "movie version {} is not supported (expected {})", version, VERSION)
+            },
+            MovieError::Corrupt(ref message) => write!(f, "corrupt movie: {}", message),
+            MovieError::State(ref error) => write!(f, "movie start: {}", error),
+            MovieError::Desync { frames, expected, actual } => {
+                write!(f, "desync after {} frames: checksum {:016x}, recorded {:016x}", frames, actual, expected)
+            },
+        }
+    }
+}
+
+impl Error for MovieError {}
+
+impl From<io::Error> for MovieError {
+    fn from(error: io::Error) -> MovieError {
+        MovieError::Io(error)
+    }
+}
+
+impl From<StateError> for MovieError {
+    fn from(error: StateError) -> MovieError {
+        MovieError::State(error)
+    }
+}
+
+/// Hash of everything a save state holds: CPU, memory, ports and frame
+/// timing.  Two machines with the same checksum run on identically.
+pub fn checksum(machine: &Invaders) -> u64 {
+    // FNV-1a, the same as for screens
+    hash_pixels(&machine.save_state().to_bytes())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub start: SaveState,
+    pub inputs: Vec<(u8, u8)>,    // Ports 1 and 2 during each frame
+    pub checksum: u64,            // Of the machine after the last frame
+}
+
+impl Movie {
+    /// An empty movie starting from where `machine` is now.
+    pub fn new(machine: &Invaders) -> Movie {
+        Movie { start: machine.save_state(), inputs: Vec::new(), checksum: checksum(machine) }
+    }
+    /// Records the ports as they are for the frame about to run.
+    pub fn record(&mut self, io: &InvadersIo) {
+        self.inputs.push((io.port1, io.port2));
+    }
+    /// Notes how the machine ended up, once the last frame has run.
+    pub fn finish(&mut self, machine: &Invaders) {
+        self.checksum = checksum(machine);
+    }
+    /// Puts `machine` where the movie starts.
+    pub fn rewind(&self, machine: &mut Invaders) -> Result<(), MovieError> {
+        Ok(machine.load_state(&self.start)?)
+    }
+    /// Sets the ports for frame `index` of the movie.
+    pub fn apply(&self, index: usize, io: &mut InvadersIo) {
+        if let Some(&(port1, port2)) = self.inputs.get(index) {
+            io.port1 = port1;
+            io.port2 = port2;
+        }
+    }
+    /// Fails unless `machine`, having played the whole movie, ended up as
+    /// it did when the movie was recorded.
+    pub fn verify(&self, machine: &Invaders) -> Result<(), MovieError> {
+        let actual: u64 = checksum(machine);
+        if actual != self.checksum {
+            return Err(MovieError::Desync { frames: self.inputs.len() as u64, expected: self.checksum, actual });
+        }
+        Ok(())
+    }
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out: Vec<u8> = MAGIC.to_vec();
+        put_u16(&mut out, VERSION);
+        put_u64(&mut out, self.inputs.len() as u64);
+        for &(port1, port2) in &self.inputs {
+            out.extend_from_slice(&[port1, port2]);
+        }
+        put_u64(&mut out, self.checksum);
+        out.extend_from_slice(&self.start.to_bytes());
+        out
+    }
+    pub fn parse(bytes: &[u8]) -> Result<Movie, MovieError> {
+        if bytes.len() < MAGIC.len() + 2 || &bytes[..MAGIC.len()] != MAGIC {
+            return Err(MovieError::NotAMovie);
+        }
+        let version: u16 = bytes[8] as u16 | (bytes[9] as u16) << 8;
+        if version != VERSION {
+            return Err(MovieError::Version(version));
+        }
+        let u64_at = |at: usize| bytes[at..at + 8].iter().rev().fold(0u64, |value, &byte| value << 8 | byte as u64);
+        if bytes.len() < HEADER_LEN {
+            return Err(MovieError::Corrupt("truncated header".to_string()));
+        }
+        let frames: u64 = u64_at(10);
+        // Input plus checksum, without trusting the count not to overflow
+        let needed: Option<u64> = frames.checked_mul(2).and_then(|len| len.checked_add(HEADER_LEN as u64 + 8));
+        if needed.is_none_or(|needed| needed > bytes.len() as u64) {
+            return Err(MovieError::Corrupt(format!("{} frames of input are truncated", frames)));
+        }
+        let end: usize = HEADER_LEN + 2 * frames as usize;
+        let inputs: Vec<(u8, u8)> = bytes[HEADER_LEN..end].chunks(2).map(|ports| (ports[0], ports[1])).collect();
+        let checksum: u64 = u64_at(end);
+        let start: SaveState = SaveState::parse(&bytes[end + 8..])?;
+        Ok(Movie { start, inputs, checksum })
+    }
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        File::create(path)?.write_all(&self.to_bytes())
+    }
+    pub fn load(path: &Path) -> Result<Movie, MovieError> {
+        let mut bytes: Vec<u8> = Vec::new();
+        File::open(path)?.read_to_end(&mut bytes)?;
+        Movie::parse(&bytes)
+    }
+}
@@ -0,0 +1,104 @@
+/*
+    Save states for the Space Invaders machine: the CPU and memory plus the
+    shift register, port latches and frame timing.
+*/
+use std::path::{Path, PathBuf};
+use invaders::*;
+use savestate::{put_u16, put_u64, SaveState, StateError, VmState};
+
+pub const MACHINE: &str = "invaders";
+// The ROM must match, or the saved code and the running code disagree
+const ROM_LEN: usize = 0x2000;
+
+/// Where save slot `slot` lives for the ROM at `rom`: next to it, as
+/// invaders.state1 for slot 1 of invaders.rom.
+pub fn slot_path(rom: &Path, slot: u8) -> PathBuf {
+    rom.with_extension(format!("state{}", slot))
+}
+
+/// Device and timing state, read and checked but not yet applied.
+struct Devices {
+    io: InvadersIo,
+    frame: u64,
+    frame_start: u64,
+    next_event: usize,
+}
+
+impl Devices {
+    fn read(state: &SaveState) -> Result<Devices, StateError> {
+        let mut reader = state.section(b"IO  ")?;
+        let shifter = ShiftRegister { value: reader.u16()?, offset: reader.u8()? };
+        if shifter.offset > 7 {
+            return Err(StateError::Corrupt(format!("shift offset is {}", shifter.offset)));
+        }
+        let io = InvadersIo {
+            shifter,
+            port1: reader.u8()?,
+            port2: reader.u8()?,
+            sound1: reader.u8()?,
+            sound2: reader.u8()?,
+            watchdog: reader.u8()?,
+        };
+        reader.finish()?;
+
+        let mut reader = state.section(b"TIME")?;
+        let frame: u64 = reader.u64()?;
+        let frame_start: u64 = reader.u64()?;
+        let next_event: usize = reader.u8()? as usize;
+        reader.finish()?;
+        if next_event >= SCHEDULE.len() {
+            return Err(StateError::Corrupt(format!("next event is {}", next_event)));
+        }
+        Ok(Devices { io, frame, frame_start, next_event })
+    }
+    fn apply(self, machine: &mut Invaders) {
+        machine.vm.io = self.io;
+        machine.frame = self.frame;
+        machine.frame_start = self.frame_start;
+        machine.next_event = self.next_event;
+    }
+}
+
+impl Invaders {
+    /// Adds the IO and TIME sections: the shift register, port latches and
+    /// frame timing.
+    pub fn save_devices(&self, state: &mut SaveState) {
+        let io: &InvadersIo = &self.vm.io;
+        let mut ports: Vec<u8> = Vec::new();
+        put_u16(&mut ports, io.shifter.value);
+        ports.extend_from_slice(&[io.shifter.offset, io.port1, io.port2, io.sound1, io.sound2, io.watchdog]);
+        state.add(b"IO  ", ports);
+        let mut timing: Vec<u8> = Vec::new();
+        put_u64(&mut timing, self.frame);
+        put_u64(&mut timing, self.frame_start);
+        timing.push(self.next_event as u8);
+        state.add(b"TIME", timing);
+    }
+    /// Restores the IO and TIME sections, or nothing if either is invalid.
+    pub fn load_devices(&mut self, state: &SaveState) -> Result<(), StateError> {
+        Devices::read(state)?.apply(self);
+        Ok(())
+    }
+    pub fn save_state(&self) -> SaveState {
+        let mut state = SaveState::new(MACHINE);
+        ::savestate::save_vm(&self.vm, &mut state);
+        self.save_devices(&mut state);
+        state
+    }
+    /// Restores `state`.  Nothing changes unless the whole state is valid
+    /// and was saved with the ROM this machine is running.
+    pub fn load_state(&mut self, state: &SaveState) -> Result<(), StateError> {
+        state.expect_machine(MACHINE)?;
+        let vm: VmState = VmState::read(state)?;
+        if vm.memory(0, ROM_LEN) != &self.vm.memory.read_range(0, ROM_LEN)[..] {
+            return Err(StateError::Machine("a different ROM".to_string()));
+        }
+        let devices: Devices = Devices::read(state)?;
+        if devices.frame_start > vm.cycles() {
+            return Err(StateError::Corrupt("frame timing is out of range".to_string()));
+        }
+        vm.apply(&mut self.vm);
+        devices.apply(self);
+        Ok(())
+    }
+}
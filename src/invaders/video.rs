@@ -0,0 +1,56 @@
+/*
+    Video output.  The game draws into a 256x224 1-bit framebuffer at
+    0x2400-0x3fff, one byte per 8 horizontal pixels with the least significant
+    bit leftmost.  The monitor is mounted rotated 90 degrees counter-clockwise,
+    so the picture the player sees is 224 wide and 256 tall.
+*/
+use vm::{PortIo, Vm};
+
+pub const VRAM_START: u16 = 0x2400;
+pub const VRAM_LEN: usize = 0x1c00;
+// Size of the rotated picture
+pub const WIDTH: usize = 224;
+pub const HEIGHT: usize = 256;
+
+const WHITE: [u8; 4] = [0xff, 0xff, 0xff, 0xff];
+const RED: [u8; 4] = [0xff, 0x20, 0x20, 0xff];
+const GREEN: [u8; 4] = [0x20, 0xff, 0x20, 0xff];
+const BLACK: [u8; 4] = [0x00, 0x00, 0x00, 0xff];
+
+/// Colour of a lit pixel at (`x`, `y`) of the rotated picture under the
+/// cabinet's cellophane strips: red across the scores and UFO, green over the
+/// shields and player, and green under the reserve ships in the bottom row.
+pub fn overlay_colour(x: usize, y: usize) -> [u8; 4] {
+    match y {
+        32..=63 => RED,
+        184..=239 => GREEN,
+        240..=255 if (16..134).contains(&x) => GREEN,
+        _ => WHITE,
+    }
+}
+
+/// Converts the raw video RAM (`VRAM_LEN` bytes from 0x2400) into a
+/// `WIDTH * HEIGHT` RGBA buffer, top row first.  Lit pixels are white, or
+/// tinted by the colour overlay when `overlay` is set.
+pub fn render(vram: &[u8], overlay: bool) -> Vec<u8> {
+    let mut pixels: Vec<u8> = BLACK.iter().cycle().take(WIDTH * HEIGHT * 4).cloned().collect();
+    for (offset, byte) in vram.iter().take(VRAM_LEN).enumerate() {
+        // Each unrotated line of 32 bytes becomes a column of the picture
+        let x: usize = offset / 32;
+        for bit in 0..8 {
+            if byte & (1 << bit) == 0 {
+                continue;
+            }
+            let y: usize = HEIGHT - 1 - ((offset % 32) * 8 + bit);
+            let colour = if overlay { overlay_colour(x, y) } else { WHITE };
+            let index: usize = (y * WIDTH + x) * 4;
+            pixels[index..index + 4].copy_from_slice(&colour);
+        }
+    }
+    pixels
+}
+
+/// Renders the current contents of video RAM.
+pub fn frame<P: PortIo>(vm: &Vm<P>, overlay: bool) -> Vec<u8> {
+    render(&vm.memory.read_range(VRAM_START, VRAM_LEN), overlay)
+}
@@ -0,0 +1,10 @@
+pub mod assemble;
+pub mod debugger;
+pub mod decompile;
+pub mod disassemble;
+pub mod image;
+pub mod invaders;
+pub mod savestate;
+pub mod symbols;
+pub mod trace;
+pub mod vm;
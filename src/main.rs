@@ -1,50 +1,346 @@
-#![feature(augmented_assignments)]
-
 extern crate ncurses;
+extern crate rust8080;
 extern crate time;
 
+mod tui;
+
 use std::fs::File;
 use std::io::prelude::*;
 use std::env;
-use std::time::Duration;
-use std::thread;
-use ncurses::*;
-mod disassemble;
-mod vm;
+use std::path::{Path, PathBuf};
+use std::process;
+use rust8080::assemble;
+use rust8080::debugger::Debugger;
+use rust8080::debugger::{gdb, repl};
+use rust8080::decompile::Decompiler;
+use rust8080::disassemble::cfg::Cfg;
+use rust8080::disassemble::flow;
+use rust8080::disassemble::flow::Disassembly;
+use rust8080::invaders::Invaders;
+use rust8080::invaders::headless;
+use rust8080::invaders::headless::{HeadlessConfig, InputScript};
+use rust8080::invaders::movie;
+use rust8080::invaders::movie::Movie;
+use rust8080::invaders::state;
+use rust8080::savestate::SaveState;
+use rust8080::symbols;
+use rust8080::symbols::SymbolTable;
+use rust8080::trace::TraceFilter;
 
-fn main() {
-    if let Some(arg1) = env::args().nth(1) {
-        let mut rom = File::open(arg1).unwrap();
-        let mut buffer: Vec<u8> = Vec::new();
-        let block = rom.read_to_end(&mut buffer);
-        println!("Read {} bytes from file", block.unwrap());
-        let mut vm = vm::Vm::new();
-        vm.load_rom(buffer);
-        println!("Time: {}", time::precise_time_s());
-        let mut last_interrupt: f64 = 0.0;
-        let mut interrupt_num: i32 = 2;
-//        panic!("instruction: {}", vm.memory[0x1a3a]);
-        initscr();        
-        let mut cycles: i32 = 0;
-        loop {
-        
-            vm.run_current_opcode();
-            if  (time::precise_time_s() - last_interrupt) > 1.0/60.0  {
-                if vm.int_enable == 1 {
-                    //vm.generate_interrupt(interrupt_num);
-                    last_interrupt = time::precise_time_s();
+const USAGE: &str = "usage: rust8080 <rom> [--disassemble] [--cfg dot|calls|json] [--decompile] [--debug] [--gdb port] [--load-state file|slot] \
+[--symbols file] [--export-symbols file] [--assemble [--binary file] [--listing file]] \
+[--headless [--frames N] [--dump N,N,...] [--input script] [--out dir] [--overlay] \
+[--trace file [--trace-range A-B]... [--trace-start A] [--trace-stop A]] \
+[--save-state file|slot] [--record-movie file] [--play-movie file]]";
+
+struct Options {
+    rom: String,
+    disassemble: bool,
+    symbols: SymbolTable,
+    export_symbols: Option<PathBuf>,
+    cfg: Option<String>,      // Graph format to print
+    decompile: bool,
+    debug: bool,
+    gdb: Option<u16>,     // Port to serve the GDB remote protocol on
+    load_state: Option<String>,   // Save state file or slot number to start from
+    save_state: Option<String>,   // Where to save the state after a headless run
+    assemble: bool,
+    binary: Option<PathBuf>,
+    listing: Option<PathBuf>,
+    headless: bool,
+    config: HeadlessConfig,
+}
+
+fn parse_args(mut args: env::Args) -> Result<Options, String> {
+    args.next();
+    let mut rom: Option<String> = None;
+    let mut options = Options {
+        rom: String::new(),
+        disassemble: false,
+        symbols: SymbolTable::new(),
+        export_symbols: None,
+        cfg: None,
+        decompile: false,
+        debug: false,
+        gdb: None,
+        load_state: None,
+        save_state: None,
+        assemble: false,
+        binary: None,
+        listing: None,
+        headless: false,
+        config: HeadlessConfig {
+            frames: 600,
+            dump_frames: Vec::new(),
+            output_dir: None,
+            overlay: false,
+            script: InputScript::new(),
+            trace: None,
+            trace_filter: TraceFilter::default(),
+            play: None,
+            record: None,
+        },
+    };
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--disassemble" => options.disassemble = true,
+            "--symbols" => {
+                let value = args.next().ok_or("--symbols needs a symbol file")?;
+                options.symbols = SymbolTable::load(&PathBuf::from(value)).map_err(|e| e.to_string())?;
+            },
+            "--export-symbols" => {
+                let value = args.next().ok_or("--export-symbols needs a file name")?;
+                options.export_symbols = Some(PathBuf::from(value));
+            },
+            "--cfg" => {
+                let value = args.next().ok_or("--cfg needs a format")?;
+                match value.as_str() {
+                    "dot" | "calls" | "json" => options.cfg = Some(value),
+                    _ => return Err(format!("unknown graph format `{}`", value)),
+                }
+            },
+            "--decompile" => options.decompile = true,
+            "--debug" => options.debug = true,
+            "--gdb" => {
+                let value = args.next().ok_or("--gdb needs a port")?;
+                options.gdb = Some(value.parse().map_err(|_| format!("bad port `{}`", value))?);
+            },
+            "--assemble" => options.assemble = true,
+            "--binary" => {
+                let value = args.next().ok_or("--binary needs a file name")?;
+                options.binary = Some(PathBuf::from(value));
+            },
+            "--listing" => {
+                let value = args.next().ok_or("--listing needs a file name")?;
+                options.listing = Some(PathBuf::from(value));
+            },
+            "--load-state" => options.load_state = Some(args.next().ok_or("--load-state needs a file or slot")?),
+            "--save-state" => options.save_state = Some(args.next().ok_or("--save-state needs a file or slot")?),
+            "--headless" => options.headless = true,
+            "--overlay" => options.config.overlay = true,
+            "--frames" => {
+                let value = args.next().ok_or("--frames needs a count")?;
+                options.config.frames = value.parse().map_err(|_| format!("bad frame count `{}`", value))?;
+            },
+            "--dump" => {
+                let value = args.next().ok_or("--dump needs a list of frames")?;
+                for frame in value.split(',') {
+                    let frame: u64 = frame.parse().map_err(|_| format!("bad frame number `{}`", frame))?;
+                    options.config.dump_frames.push(frame);
+                }
+            },
+            "--input" => {
+                let value = args.next().ok_or("--input needs a script file")?;
+                options.config.script = InputScript::load(&PathBuf::from(value)).map_err(|e| e.to_string())?;
+            },
+            "--trace" => {
+                let value = args.next().ok_or("--trace needs a file name")?;
+                options.config.trace = Some(PathBuf::from(value));
+            },
+            "--trace-range" => {
+                let value = args.next().ok_or("--trace-range needs a range")?;
+                let mut ends = value.splitn(2, '-').map(symbols::parse_addr);
+                match (ends.next(), ends.next()) {
+                    (Some(Some(start)), Some(Some(end))) if start <= end => {
+                        options.config.trace_filter.ranges.push((start, end));
+                    },
+                    _ => return Err(format!("bad address range `{}`", value)),
+                }
+            },
+            "--trace-start" | "--trace-stop" => {
+                let value = args.next().ok_or(format!("{} needs an address", arg))?;
+                let addr: u16 = symbols::parse_addr(&value).ok_or(format!("bad address `{}`", value))?;
+                if arg == "--trace-start" {
+                    options.config.trace_filter.start = Some(addr);
+                }
+                else {
+                    options.config.trace_filter.stop = Some(addr);
+                }
+            },
+            "--record-movie" => {
+                let value = args.next().ok_or("--record-movie needs a file name")?;
+                options.config.record = Some(PathBuf::from(value));
+            },
+            "--play-movie" => {
+                let value = args.next().ok_or("--play-movie needs a movie file")?;
+                let movie = Movie::load(&PathBuf::from(&value)).map_err(|e| format!("{}: {}", value, e))?;
+                options.config.play = Some(movie);
+            },
+            "--out" => {
+                let value = args.next().ok_or("--out needs a directory")?;
+                options.config.output_dir = Some(PathBuf::from(value));
+            },
+            _ if arg.starts_with("--") => return Err(format!("unknown option `{}`", arg)),
+            _ => rom = Some(arg),
+        }
+    }
+    options.rom = rom.ok_or("no rom given")?;
+    if options.export_symbols.is_some() && (options.assemble || options.cfg.is_some() || options.decompile) {
+        return Err("--export-symbols only goes with --disassemble or on its own".to_string());
+    }
+    Ok(options)
+}
+
+/// Assembles the source file `options.rom` into a binary next to it (or at
+/// --binary) and prints the listing, or writes it to --listing.
+fn run_assembler(options: &Options, source: &[u8]) {
+    let assembly = match assemble::assemble(&String::from_utf8_lossy(source)) {
+        Ok(assembly) => assembly,
+        Err(error) => {
+            eprintln!("{}: {}", options.rom, error);
+            process::exit(1);
+        },
+    };
+    let binary: PathBuf = options.binary.clone().unwrap_or_else(|| PathBuf::from(&options.rom).with_extension("bin"));
+    let written = File::create(&binary).and_then(|mut file| file.write_all(&assembly.bytes));
+    let written = written.and_then(|_| match options.listing {
+        Some(ref path) => File::create(path).and_then(|mut file| file.write_all(assembly.listing.as_bytes())),
+        None => {
+            print!("{}", assembly.listing);
+            Ok(())
+        },
+    });
+    if let Err(error) = written {
+        eprintln!("{}", error);
+        process::exit(1);
+    }
+}
+
+/// A save state argument: a single digit names a slot next to the ROM.
+fn state_path(rom: &str, value: &str) -> PathBuf {
+    match value.parse::<u8>() {
+        Ok(slot) if value.len() == 1 => state::slot_path(Path::new(rom), slot),
+        _ => PathBuf::from(value),
+    }
+}
+
+fn run_headless(machine: &mut Invaders, config: &HeadlessConfig, save: Option<PathBuf>) {
+    match headless::run(machine, config) {
+        Ok(dumps) => {
+            for dump in dumps {
+                match dump.path {
+                    Some(path) => println!("frame {} {:016x} {}", dump.frame, dump.hash, path.display()),
+                    None => println!("frame {} {:016x}", dump.frame, dump.hash),
                 }
             }
-            mvprintw(0,0,format!("{:#?}", vm).as_str()); 
-//            thread::sleep(Duration::from_millis(5));
-            refresh();
-            cycles += 1;
-            mvprintw(25,0,format!("{}", cycles).as_str());
-            if cycles >= 420000 {
-                let mut file = File::create("screen.bmp").unwrap();
-                file.write_all(&vm.memory[0x2400..0x4000]);
-                getch();
+        },
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(1);
+        },
+    }
+    if let Some(ref path) = config.record {
+        println!("recorded movie to {}", path.display());
+    }
+    if let Some(ref played) = config.play {
+        println!("played {} frames, checksum {:016x} matches", played.inputs.len(), movie::checksum(machine));
+    }
+    if let Some(path) = save {
+        if let Err(error) = machine.save_state().save(&path) {
+            eprintln!("{}: {}", path.display(), error);
+            process::exit(1);
+        }
+        println!("saved frame {} to {}", machine.frame, path.display());
+    }
+}
+
+/// Runs the machine under the command line debugger on stdin and stdout.
+fn run_debugger(machine: Invaders, symbols: SymbolTable) {
+    let mut debugger = Debugger::new(machine, symbols);
+    let stdin = std::io::stdin();
+    let stdout = std::io::stdout();
+    if let Err(error) = repl::run(&mut debugger, &mut stdin.lock(), &mut stdout.lock()) {
+        eprintln!("{}", error);
+        process::exit(1);
+    }
+}
+
+/// Waits for one GDB remote protocol client on localhost and serves it.
+fn run_gdb(machine: Invaders, symbols: SymbolTable, port: u16) {
+    let mut debugger = Debugger::new(machine, symbols);
+    let served = gdb::listen(port).and_then(|listener| {
+        println!("waiting for gdb on {}", listener.local_addr()?);
+        let (stream, peer) = listener.accept()?;
+        println!("connected to {}", peer);
+        gdb::serve(&mut debugger, stream)
+    });
+    if let Err(error) = served {
+        eprintln!("{}", error);
+        process::exit(1);
+    }
+}
+
+fn main() {
+    let options = match parse_args(env::args()) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\n{}", message, USAGE);
+            process::exit(2);
+        },
+    };
+    let mut buffer: Vec<u8> = Vec::new();
+    let block: usize = match File::open(&options.rom).and_then(|mut rom| rom.read_to_end(&mut buffer)) {
+        Ok(block) => block,
+        Err(error) => {
+            eprintln!("{}: {}", options.rom, error);
+            process::exit(1);
+        },
+    };
+    if options.assemble {
+        run_assembler(&options, &buffer);
+        return;
+    }
+    if let Some(ref format) = options.cfg {
+        let disassembly = Disassembly::analyze_with_symbols(&buffer, 0x0000, &flow::DEFAULT_ENTRIES,
+                                                            &options.symbols);
+        let cfg = Cfg::build(&disassembly);
+        match format.as_str() {
+            "dot" => print!("{}", cfg.to_dot()),
+            "calls" => print!("{}", cfg.call_graph_dot()),
+            _ => print!("{}", cfg.to_json()),
+        }
+        return;
+    }
+    if options.decompile {
+        let disassembly = Disassembly::analyze_with_symbols(&buffer, 0x0000, &flow::DEFAULT_ENTRIES,
+                                                            &options.symbols);
+        print!("{}", Decompiler::new(&Cfg::build(&disassembly)).program());
+        return;
+    }
+    if options.disassemble || options.export_symbols.is_some() {
+        let disassembly = Disassembly::analyze_with_symbols(&buffer, 0x0000, &flow::DEFAULT_ENTRIES,
+                                                            &options.symbols);
+        if options.disassemble {
+            print!("{}", disassembly.listing());
+        }
+        if let Some(path) = options.export_symbols {
+            if let Err(error) = disassembly.export_symbols().save(&path) {
+                eprintln!("{}: {}", path.display(), error);
+                process::exit(1);
             }
         }
+        return;
+    }
+    let mut machine = Invaders::new(&buffer);
+    if let Some(ref value) = options.load_state {
+        let path: PathBuf = state_path(&options.rom, value);
+        if let Err(error) = SaveState::load(&path).and_then(|saved| machine.load_state(&saved)) {
+            eprintln!("{}: {}", path.display(), error);
+            process::exit(1);
+        }
+    }
+    if options.debug {
+        run_debugger(machine, options.symbols);
+    }
+    else if let Some(port) = options.gdb {
+        run_gdb(machine, options.symbols, port);
+    }
+    else if options.headless {
+        let save: Option<PathBuf> = options.save_state.as_ref().map(|value| state_path(&options.rom, value));
+        run_headless(&mut machine, &options.config, save);
+    }
+    else {
+        println!("Read {} bytes from file", block);
+        tui::run(&mut Debugger::new(machine, options.symbols), Path::new(&options.rom));
     }
 }
@@ -0,0 +1,263 @@
+/*
+    Save states: a versioned snapshot of a machine, made of tagged sections
+    so each machine can add its own device state next to the CPU and memory.
+
+    File layout, all integers little-endian:
+        "R8080ST" 0x1a          magic
+        u16                     format version
+        sections until the end: 4-byte tag, u32 length, payload
+
+    Every state starts with a NAME section naming the machine.  Loaders check
+    everything before they touch the machine, so a bad file leaves it as it
+    was.
+*/
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+use vm::{ConditionCodes, Vm};
+
+pub const MAGIC: &[u8; 8] = b"R8080ST\x1a";
+pub const VERSION: u16 = 1;
+// Bytes in the CPU section
+const CPU_LEN: usize = 23;
+const MEMORY_LEN: usize = 0x10000;
+
+#[derive(Debug)]
+pub enum StateError {
+    Io(io::Error),
+    NotAState,
+    Version(u16),             // Written by a newer or older format
+    Machine(String),          // Saved from a different machine or ROM
+    Missing(String),          // Section tag
+    Corrupt(String),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StateError::Io(ref error) => write!(f, "{}", error),
+            StateError::NotAState => write!(f, "not a save state"),
+            StateError::Version(version) => {
+                write!(f, "save state version {} is not supported (expected {})", version, VERSION)
+            },
+            StateError::Machine(ref message) => write!(f, "save state is for {}", message),
+            StateError::Missing(ref tag) => write!(f, "save state has no {} section", tag),
+            StateError::Corrupt(ref message) => write!(f, "corrupt save state: {}", message),
+        }
+    }
+}
+
+impl Error for StateError {}
+
+impl From<io::Error> for StateError {
+    fn from(error: io::Error) -> StateError {
+        StateError::Io(error)
+    }
+}
+
+/// Reads the fields of one section in order.
+pub struct Reader<'a> {
+    tag: &'a str,
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.data.len() - self.position < len {
+            return Err(StateError::Corrupt(format!("{} section is too short", self.tag)));
+        }
+        self.position += len;
+        Ok(&self.data[self.position - len..self.position])
+    }
+    pub fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.bytes(1)?[0])
+    }
+    pub fn u16(&mut self) -> Result<u16, StateError> {
+        let bytes: &[u8] = self.bytes(2)?;
+        Ok(bytes[0] as u16 | (bytes[1] as u16) << 8)
+    }
+    pub fn u64(&mut self) -> Result<u64, StateError> {
+        let bytes: &[u8] = self.bytes(8)?;
+        Ok(bytes.iter().rev().fold(0u64, |value, &byte| value << 8 | byte as u64))
+    }
+    /// A byte that must be 0 or 1.
+    pub fn flag(&mut self, name: &str) -> Result<bool, StateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            value => Err(StateError::Corrupt(format!("{} is {}", name, value))),
+        }
+    }
+    /// Checks that every byte of the section was used.
+    pub fn finish(self) -> Result<(), StateError> {
+        if self.position != self.data.len() {
+            return Err(StateError::Corrupt(format!("{} section is too long", self.tag)));
+        }
+        Ok(())
+    }
+}
+
+pub fn put_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&[value as u8, (value >> 8) as u8]);
+}
+
+pub fn put_u64(out: &mut Vec<u8>, value: u64) {
+    out.extend((0..8).map(|byte| (value >> (8 * byte)) as u8));
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SaveState {
+    pub machine: String,
+    sections: Vec<([u8; 4], Vec<u8>)>,
+}
+
+impl SaveState {
+    pub fn new(machine: &str) -> SaveState {
+        SaveState { machine: machine.to_string(), sections: Vec::new() }
+    }
+    pub fn add(&mut self, tag: &[u8; 4], data: Vec<u8>) {
+        self.sections.push((*tag, data));
+    }
+    /// A reader over section `tag`.
+    pub fn section(&self, tag: &'static [u8; 4]) -> Result<Reader<'_>, StateError> {
+        let name: &str = ::std::str::from_utf8(tag).unwrap_or("?").trim_end();
+        match self.sections.iter().find(|(found, _)| found == tag) {
+            Some((_, data)) => Ok(Reader { tag: name, data, position: 0 }),
+            None => Err(StateError::Missing(name.to_string())),
+        }
+    }
+    /// Fails unless the state was saved from `machine`.
+    pub fn expect_machine(&self, machine: &str) -> Result<(), StateError> {
+        if self.machine != machine {
+            return Err(StateError::Machine(format!("a {} machine", self.machine)));
+        }
+        Ok(())
+    }
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out: Vec<u8> = MAGIC.to_vec();
+        put_u16(&mut out, VERSION);
+        let name: ([u8; 4], Vec<u8>) = (*b"NAME", self.machine.as_bytes().to_vec());
+        for (tag, data) in Some(&name).into_iter().chain(self.sections.iter()) {
+            out.extend_from_slice(tag);
+            out.extend((0..4).map(|byte| (data.len() >> (8 * byte)) as u8));
+            out.extend_from_slice(data);
+        }
+        out
+    }
+    pub fn parse(bytes: &[u8]) -> Result<SaveState, StateError> {
+        if bytes.len() < MAGIC.len() + 2 || &bytes[..MAGIC.len()] != MAGIC {
+            return Err(StateError::NotAState);
+        }
+        let version: u16 = bytes[8] as u16 | (bytes[9] as u16) << 8;
+        if version != VERSION {
+            return Err(StateError::Version(version));
+        }
+        let mut sections: Vec<([u8; 4], Vec<u8>)> = Vec::new();
+        let mut rest: &[u8] = &bytes[10..];
+        while !rest.is_empty() {
+            if rest.len() < 8 {
+                return Err(StateError::Corrupt("truncated section header".to_string()));
+            }
+            let tag: [u8; 4] = [rest[0], rest[1], rest[2], rest[3]];
+            let len: usize = rest[4..8].iter().rev().fold(0usize, |len, &byte| len << 8 | byte as usize);
+            if rest.len() - 8 < len {
+                return Err(StateError::Corrupt(format!("{} section is truncated", String::from_utf8_lossy(&tag))));
+            }
+            sections.push((tag, rest[8..8 + len].to_vec()));
+            rest = &rest[8 + len..];
+        }
+        if sections.first().is_none_or(|(tag, _)| tag != b"NAME") {
+            return Err(StateError::Missing("NAME".to_string()));
+        }
+        let machine: String = String::from_utf8_lossy(&sections[0].1).into_owned();
+        Ok(SaveState { machine, sections: sections[1..].to_vec() })
+    }
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        File::create(path)?.write_all(&self.to_bytes())
+    }
+    pub fn load(path: &Path) -> Result<SaveState, StateError> {
+        let mut bytes: Vec<u8> = Vec::new();
+        File::open(path)?.read_to_end(&mut bytes)?;
+        SaveState::parse(&bytes)
+    }
+}
+
+/// Everything in a Vm except its ports, read from a state and checked but
+/// not yet applied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VmState {
+    registers: [u8; 7],   // A B C D E H L
+    sp: u16,
+    pc: u16,
+    psw: u8,
+    int_enable: bool,
+    ei_pending: bool,
+    halted: bool,
+    cycles: u64,
+    memory: Vec<u8>,      // The whole address space
+}
+
+/// Adds CPU and MEM sections holding everything in `vm` except its ports.
+pub fn save_vm<P>(vm: &Vm<P>, state: &mut SaveState) {
+    let mut cpu: Vec<u8> = vec![vm.a, vm.b, vm.c, vm.d, vm.e, vm.h, vm.l];
+    put_u16(&mut cpu, vm.sp);
+    put_u16(&mut cpu, vm.pc);
+    cpu.extend_from_slice(&[vm.condition_codes.to_psw(), vm.int_enable, vm.ei_pending as u8, vm.halted as u8]);
+    put_u64(&mut cpu, vm.cycles);
+    debug_assert_eq!(cpu.len(), CPU_LEN);
+    state.add(b"CPU ", cpu);
+    state.add(b"MEM ", vm.memory.read_range(0, MEMORY_LEN));
+}
+
+impl VmState {
+    /// Reads and checks the CPU and MEM sections.
+    pub fn read(state: &SaveState) -> Result<VmState, StateError> {
+        let mut reader: Reader<'_> = state.section(b"CPU ")?;
+        let mut registers = [0u8; 7];
+        registers.copy_from_slice(reader.bytes(7)?);
+        let sp: u16 = reader.u16()?;
+        let pc: u16 = reader.u16()?;
+        let psw: u8 = reader.u8()?;
+        let int_enable: bool = reader.flag("int_enable")?;
+        let ei_pending: bool = reader.flag("ei_pending")?;
+        let halted: bool = reader.flag("halted")?;
+        let cycles: u64 = reader.u64()?;
+        reader.finish()?;
+        let mut reader: Reader<'_> = state.section(b"MEM ")?;
+        let memory: Vec<u8> = reader.bytes(MEMORY_LEN)?.to_vec();
+        reader.finish()?;
+        Ok(VmState { registers, sp, pc, psw, int_enable, ei_pending, halted, cycles, memory })
+    }
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+    /// The saved bytes at `addr..addr + len`.
+    pub fn memory(&self, addr: u16, len: usize) -> &[u8] {
+        let start: usize = addr as usize;
+        &self.memory[start..(start + len).min(MEMORY_LEN)]
+    }
+    pub fn apply<P>(&self, vm: &mut Vm<P>) {
+        let [a, b, c, d, e, h, l] = self.registers;
+        vm.a = a;
+        vm.b = b;
+        vm.c = c;
+        vm.d = d;
+        vm.e = e;
+        vm.h = h;
+        vm.l = l;
+        vm.sp = self.sp;
+        vm.pc = self.pc;
+        vm.condition_codes = ConditionCodes::from_psw(self.psw);
+        vm.int_enable = self.int_enable as u8;
+        vm.ei_pending = self.ei_pending;
+        vm.halted = self.halted;
+        vm.cycles = self.cycles;
+        // Ignores write protection; writes through mirrors land on the bytes
+        // they were read from
+        vm.memory.load(0, &self.memory);
+    }
+}
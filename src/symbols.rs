@@ -0,0 +1,243 @@
+/*
+    Symbol files: names, comments and data types for addresses, shared by the
+    disassembler and the debugger views.
+
+    One symbol per line, fields separated by whitespace:
+
+        ; address  name            type     ; comment
+        20EB       numCoins        byte     ; credits, in BCD
+        2400       vram            byte*7168
+        1947       DrawNumCredits  code     ; prints the credit count
+        0008       ScanLine96
+
+    The address is hex, optionally written as $20EB or 0x20EB.  The type is
+    optional: `code` marks a routine the disassembler should decode from, and
+    `byte`, `word` and `text` mark data, with `*N` for N elements.  Everything
+    after a `;` is a comment.
+*/
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+use assemble::expr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataType {
+    Code,
+    Byte,
+    Word,
+    Text,
+}
+
+impl DataType {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            DataType::Code => "code",
+            DataType::Byte => "byte",
+            DataType::Word => "word",
+            DataType::Text => "text",
+        }
+    }
+    /// Bytes taken by one element.
+    pub fn size(&self) -> usize {
+        match *self {
+            DataType::Word => 2,
+            _ => 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub addr: u16,
+    pub name: String,
+    pub data: Option<(DataType, usize)>,  // Type and element count
+    pub comment: Option<String>,
+}
+
+impl Symbol {
+    pub fn new(addr: u16, name: &str) -> Symbol {
+        Symbol { addr, name: name.to_string(), data: None, comment: None }
+    }
+    /// Bytes covered by the symbol: its typed data, or just its address.
+    pub fn size(&self) -> usize {
+        match self.data {
+            Some((DataType::Code, _)) | None => 1,
+            Some((data_type, count)) => data_type.size() * count,
+        }
+    }
+    pub fn is_data(&self) -> bool {
+        match self.data {
+            Some((DataType::Code, _)) | None => false,
+            Some(_) => true,
+        }
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04X}  {:<24}", self.addr, self.name)?;
+        match self.data {
+            Some((data_type, 1)) => write!(f, "  {:<10}", data_type.name())?,
+            Some((data_type, count)) => write!(f, "  {:<10}", format!("{}*{}", data_type.name(), count))?,
+            None => write!(f, "  {:<10}", "")?,
+        }
+        if let Some(ref comment) = self.comment {
+            write!(f, "  ; {}", comment)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum SymbolError {
+    Io(io::Error),
+    Parse { line: usize, message: String },
+}
+
+impl fmt::Display for SymbolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SymbolError::Io(ref error) => write!(f, "{}", error),
+            SymbolError::Parse { line, ref message } => write!(f, "symbol file line {}: {}", line, message),
+        }
+    }
+}
+
+impl Error for SymbolError {}
+
+impl From<io::Error> for SymbolError {
+    fn from(error: io::Error) -> SymbolError {
+        SymbolError::Io(error)
+    }
+}
+
+/// Parses a hex address written as 20EB, $20EB or 0x20EB.
+pub fn parse_addr(text: &str) -> Option<u16> {
+    let digits: &str = text.strip_prefix('$')
+        .or_else(|| text.strip_prefix("0x"))
+        .or_else(|| text.strip_prefix("0X"))
+        .unwrap_or(text);
+    u16::from_str_radix(digits, 16).ok()
+}
+
+fn parse_type(text: &str) -> Option<(DataType, usize)> {
+    let mut parts = text.splitn(2, '*');
+    let data_type: DataType = match parts.next() {
+        Some("code") => DataType::Code,
+        Some("byte") => DataType::Byte,
+        Some("word") => DataType::Word,
+        Some("text") => DataType::Text,
+        _ => return None,
+    };
+    let count: usize = match parts.next() {
+        Some(count) => count.parse().ok().filter(|&count: &usize| count > 0)?,
+        None => 1,
+    };
+    Some((data_type, count))
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct SymbolTable {
+    symbols: BTreeMap<u16, Symbol>,
+}
+
+impl SymbolTable {
+    pub fn new() -> SymbolTable {
+        SymbolTable { symbols: BTreeMap::new() }
+    }
+    pub fn parse(text: &str) -> Result<SymbolTable, SymbolError> {
+        let mut table = SymbolTable::new();
+        for (index, line) in text.lines().enumerate() {
+            let error = |message: String| SymbolError::Parse { line: index + 1, message };
+            let mut halves = line.splitn(2, ';');
+            let fields: Vec<&str> = halves.next().unwrap_or("").split_whitespace().collect();
+            let comment: Option<String> = halves.next()
+                .map(|comment| comment.trim().to_string())
+                .filter(|comment| !comment.is_empty());
+            if fields.is_empty() {
+                continue;
+            }
+            let addr: u16 = parse_addr(fields[0])
+                .ok_or_else(|| error(format!("bad address `{}`", fields[0])))?;
+            let name: &str = fields.get(1).ok_or_else(|| error("missing name".to_string()))?;
+            if !expr::is_symbol(name) {
+                return Err(error(format!("`{}` is not a name the assembler would accept", name)));
+            }
+            let data: Option<(DataType, usize)> = match fields.get(2) {
+                Some(text) => Some(parse_type(text).ok_or_else(|| error(format!("bad type `{}`", text)))?),
+                None => None,
+            };
+            if fields.len() > 3 {
+                return Err(error(format!("unexpected `{}`", fields[3])));
+            }
+            if let Some(other) = table.by_name(name) {
+                return Err(error(format!("duplicate name `{}`, already at {:04X}", name, other.addr)));
+            }
+            if let Some(other) = table.get(addr) {
+                return Err(error(format!("duplicate address {:04X}, already named `{}`", addr, other.name)));
+            }
+            table.insert(Symbol { addr, name: name.to_string(), data, comment });
+        }
+        Ok(table)
+    }
+    pub fn load(path: &Path) -> Result<SymbolTable, SymbolError> {
+        let mut text = String::new();
+        File::open(path)?.read_to_string(&mut text)?;
+        SymbolTable::parse(&text)
+    }
+    /// The table in symbol file form, sorted by address.
+    pub fn to_text(&self) -> String {
+        let mut text = String::from("; address  name  type  ; comment\n");
+        for symbol in self.symbols.values() {
+            text.push_str(symbol.to_string().trim_end());
+            text.push('\n');
+        }
+        text
+    }
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        File::create(path)?.write_all(self.to_text().as_bytes())
+    }
+    /// Adds `symbol`, replacing any symbol at the same address.
+    pub fn insert(&mut self, symbol: Symbol) {
+        self.symbols.insert(symbol.addr, symbol);
+    }
+    pub fn get(&self, addr: u16) -> Option<&Symbol> {
+        self.symbols.get(&addr)
+    }
+    pub fn by_name(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.values().find(|symbol| symbol.name == name)
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &Symbol> {
+        self.symbols.values()
+    }
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+    /// The symbol whose typed data covers `addr`, with the offset into it.
+    pub fn containing(&self, addr: u16) -> Option<(&Symbol, usize)> {
+        let (_, symbol) = self.symbols.range(..=addr).next_back()?;
+        let offset: usize = (addr - symbol.addr) as usize;
+        if offset < symbol.size() {
+            Some((symbol, offset))
+        }
+        else {
+            None
+        }
+    }
+    /// Name to show for `addr`: the symbol's name, or name+offset inside
+    /// typed data.
+    pub fn name_for(&self, addr: u16) -> Option<String> {
+        match self.containing(addr) {
+            Some((symbol, 0)) => Some(symbol.name.clone()),
+            Some((symbol, offset)) => Some(format!("{}+{}", symbol.name, offset)),
+            None => None,
+        }
+    }
+}
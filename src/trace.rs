@@ -0,0 +1,194 @@
+/*
+    Execution traces: one line per instruction with the machine state before
+    it ran, for comparing runs against each other or against traces from
+    other emulators.  A line looks like
+
+          1234 0003  c3 d4 18  JMP     $18d4       A=00 BC=0000 DE=0000 HL=0000 SP=0000 F=.....
+
+    with the T-state count, PC, opcode bytes, disassembly, registers and the
+    flags as S, Z, A(uxiliary carry), P and C, '.' when clear.  `diff` only
+    looks at the cycle count, PC and the KEY=VALUE fields, so traces from
+    other tools can be compared once they are massaged into that shape.
+*/
+use std::io;
+use std::io::prelude::*;
+use debugger::Machine;
+use disassemble::{decode, OPCODES};
+use vm::{Event, Vm, VmError};
+
+/// Which instructions get logged.  With a start trigger nothing is logged
+/// until PC reaches it; a stop trigger ends logging after the instruction at
+/// that address, until the start trigger is reached again.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TraceFilter {
+    pub ranges: Vec<(u16, u16)>,  // Inclusive PC ranges to log; empty logs everything
+    pub start: Option<u16>,
+    pub stop: Option<u16>,
+}
+
+impl TraceFilter {
+    fn in_range(&self, pc: u16) -> bool {
+        self.ranges.is_empty() || self.ranges.iter().any(|&(start, end)| start <= pc && pc <= end)
+    }
+}
+
+/// Registers before an instruction runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct State {
+    cycles: u64,
+    pc: u16,
+    a: u8,
+    bc: u16,
+    de: u16,
+    hl: u16,
+    sp: u16,
+    flags: [bool; 5],     // S Z AC P CY
+    bytes: [u8; 3],       // At PC, in case the instruction overwrites itself
+}
+
+impl State {
+    fn of<P>(vm: &Vm<P>) -> State {
+        let pair = |high: u8, low: u8| -> u16 { (high as u16) << 8 | low as u16 };
+        let flags = &vm.condition_codes;
+        let bytes: Vec<u8> = vm.memory.read_range(vm.pc, 3);
+        State {
+            cycles: vm.cycles,
+            pc: vm.pc,
+            a: vm.a,
+            bc: pair(vm.b, vm.c),
+            de: pair(vm.d, vm.e),
+            hl: pair(vm.h, vm.l),
+            sp: vm.sp,
+            flags: [flags.s != 0, flags.z != 0, flags.ac != 0, flags.p != 0, flags.cy != 0],
+            bytes: [bytes[0], bytes[1], bytes[2]],
+        }
+    }
+    fn line(&self) -> String {
+        let length: usize = OPCODES[self.bytes[0] as usize].length as usize;
+        let bytes: Vec<String> = self.bytes[..length].iter().map(|byte| format!("{:02x}", byte)).collect();
+        let flags: String = self.flags.iter().zip("SZAPC".chars())
+            .map(|(&set, name)| if set { name } else { '.' })
+            .collect();
+        format!("{:>10} {:04x}  {:<8}  {:<18}  A={:02x} BC={:04x} DE={:04x} HL={:04x} SP={:04x} F={}",
+                self.cycles, self.pc, bytes.join(" "), decode(&self.bytes, self.pc).to_string(),
+                self.a, self.bc, self.de, self.hl, self.sp, flags)
+    }
+}
+
+/// Writes a trace line for every instruction a machine runs through it.
+/// Write errors stop the logging and are reported by `finish`.
+pub struct Tracer {
+    pub filter: TraceFilter,
+    pub lines: u64,       // Lines written so far
+    out: Box<dyn Write>,
+    active: bool,         // Between the start and stop triggers
+    error: Option<io::Error>,
+}
+
+impl Tracer {
+    pub fn new(out: Box<dyn Write>, filter: TraceFilter) -> Tracer {
+        let active: bool = filter.start.is_none();
+        Tracer { filter, lines: 0, out, active, error: None }
+    }
+    /// Steps `machine` once, logging the instruction if it ran one.
+    pub fn step<M: Machine>(&mut self, machine: &mut M) -> Result<Event, VmError> {
+        let state: State = State::of(machine.vm());
+        let event: Event = machine.step()?;
+        if let Event::Instruction(_) = event {
+            self.record(&state);
+        }
+        Ok(event)
+    }
+    /// Steps `machine` until it finishes a video frame.
+    pub fn run_frame<M: Machine>(&mut self, machine: &mut M) -> Result<(), VmError> {
+        loop {
+            if let Event::Frame = self.step(machine)? {
+                return Ok(());
+            }
+        }
+    }
+    fn record(&mut self, state: &State) {
+        if self.filter.start == Some(state.pc) {
+            self.active = true;
+        }
+        if self.active && self.error.is_none() && self.filter.in_range(state.pc) {
+            match writeln!(self.out, "{}", state.line()) {
+                Ok(()) => self.lines += 1,
+                Err(error) => self.error = Some(error),
+            }
+        }
+        if self.filter.stop == Some(state.pc) {
+            self.active = false;
+        }
+    }
+    /// Flushes the output and reports the first write error, if any.
+    pub fn finish(mut self) -> io::Result<u64> {
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+        self.out.flush()?;
+        Ok(self.lines)
+    }
+}
+
+/// Where two traces part ways.  Line numbers count from 1; a missing side
+/// means that trace ended first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    pub line: usize,
+    pub fields: Vec<String>,      // Names of the fields that differ
+    pub left: Option<String>,
+    pub right: Option<String>,
+    pub previous: Option<String>, // Last line both traces agree on
+}
+
+/// The comparable fields of a trace line: CYC and PC from the first two
+/// columns, then every KEY=VALUE.  Values are compared case-insensitively.
+pub fn fields(line: &str) -> Vec<(String, String)> {
+    let mut words = line.split_whitespace();
+    let mut fields: Vec<(String, String)> = Vec::new();
+    if let Some(cycles) = words.next() {
+        fields.push(("CYC".to_string(), cycles.to_string()));
+    }
+    if let Some(pc) = words.next() {
+        fields.push(("PC".to_string(), pc.to_ascii_lowercase()));
+    }
+    for word in words {
+        let mut parts = word.splitn(2, '=');
+        if let (Some(key), Some(value)) = (parts.next(), parts.next()) {
+            if !key.is_empty() && key.chars().all(|c| c.is_ascii_uppercase()) {
+                fields.push((key.to_string(), value.to_ascii_lowercase()));
+            }
+        }
+    }
+    fields
+}
+
+/// Compares two traces line by line, skipping fields named in `ignore`
+/// (e.g. CYC for emulators that count cycles differently).  Fields only
+/// one side has are ignored too.
+pub fn diff<A: BufRead, B: BufRead>(left: A, right: B, ignore: &[String]) -> io::Result<Option<Divergence>> {
+    let mut left = left.lines();
+    let mut right = right.lines();
+    let mut previous: Option<String> = None;
+    let mut line: usize = 0;
+    loop {
+        line += 1;
+        let (a, b): (Option<String>, Option<String>) = (left.next().transpose()?, right.next().transpose()?);
+        let (a_text, b_text): (&String, &String) = match (a.as_ref(), b.as_ref()) {
+            (None, None) => return Ok(None),
+            (Some(a), Some(b)) => (a, b),
+            _ => return Ok(Some(Divergence { line, fields: Vec::new(), left: a, right: b, previous })),
+        };
+        let b_fields: Vec<(String, String)> = fields(b_text);
+        let differing: Vec<String> = fields(a_text).into_iter()
+            .filter(|(key, _)| !ignore.iter().any(|ignored| ignored.eq_ignore_ascii_case(key)))
+            .filter(|(key, value)| b_fields.iter().any(|(other, theirs)| other == key && theirs != value))
+            .map(|(key, _)| key)
+            .collect();
+        if !differing.is_empty() {
+            return Ok(Some(Divergence { line, fields: differing, left: a, right: b, previous }));
+        }
+        previous = a;
+    }
+}
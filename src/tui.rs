@@ -0,0 +1,338 @@
+/*
+    Full-screen ncurses front end for the debugger: disassembly around PC,
+    registers and flags, the stack and a scrolling memory viewer.  While
+    running, the screen is redrawn once per emulated frame and the keyboard
+    is polled between frames; while stopped it waits for keys.
+*/
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use std::thread;
+use ncurses::*;
+use rust8080::debugger::{Debugger, Stop};
+use rust8080::debugger::expr::Expr;
+use rust8080::debugger::repl;
+use rust8080::disassemble::OPCODES;
+use rust8080::invaders;
+use rust8080::invaders::Invaders;
+use rust8080::invaders::state;
+use rust8080::savestate::SaveState;
+
+const HELP: &str = "s step  n next  o out  c run/stop  i interrupt  p/r step/run back  R record  b break  \
+                    g goto code  m goto memory  arrows/pgup/pgdn scroll  S/L save/load slot  : command  q quit";
+const CODE_WIDTH: i32 = 44;   // Disassembly pane, registers and stack to its right
+const HEX_PER_ROW: u16 = 16;
+const STACK_WORDS: usize = 64;
+
+struct Tui {
+    rom: PathBuf,         // Save slots live next to it
+    running: bool,
+    code: Option<u16>,    // Address the disassembly starts from, None to follow PC
+    memory: u16,          // First address in the memory viewer
+    message: String,      // Shown on the status line until the next key
+}
+
+/// Writes `text` at (`y`, `x`), cut or padded to `width` columns.
+fn put(y: i32, x: i32, width: i32, text: &str) {
+    let width: usize = width.max(0) as usize;
+    let text: String = format!("{:<width$}", text.chars().take(width).collect::<String>(), width = width);
+    mvaddstr(y, x, &text);
+}
+
+fn length_at(debugger: &Debugger<Invaders>, addr: u16) -> u16 {
+    OPCODES[debugger.vm().memory.read_range(addr, 1)[0] as usize].length as u16
+}
+
+/// Addresses of `count` instructions with up to `before` of them ahead of
+/// `anchor`.  Earlier boundaries are found by decoding from a little
+/// further back until an instruction lands exactly on `anchor`.
+fn code_lines(debugger: &Debugger<Invaders>, anchor: u16, before: usize, count: usize) -> Vec<u16> {
+    let mut lines: Vec<u16> = Vec::new();
+    for back in (1..=(3 * before).min(anchor as usize) as u16).rev() {
+        let mut addr: u16 = anchor.wrapping_sub(back);
+        let mut found: Vec<u16> = Vec::new();
+        while addr.wrapping_sub(anchor.wrapping_sub(back)) < back {
+            found.push(addr);
+            addr = addr.wrapping_add(length_at(debugger, addr));
+        }
+        if addr == anchor {
+            let skip: usize = found.len().saturating_sub(before);
+            lines = found[skip..].to_vec();
+            break;
+        }
+    }
+    let mut addr: u16 = anchor;
+    while lines.len() < count {
+        lines.push(addr);
+        addr = addr.wrapping_add(length_at(debugger, addr));
+    }
+    lines
+}
+
+impl Tui {
+    fn draw(&self, debugger: &Debugger<Invaders>) {
+        let (mut rows, mut columns): (i32, i32) = (0, 0);
+        getmaxyx(stdscr(), &mut rows, &mut columns);
+        erase();
+        let vm = debugger.vm();
+        let memory_rows: i32 = (rows / 3).max(4);
+        let memory_top: i32 = rows - 1 - memory_rows;
+
+        attron(A_REVERSE());
+        put(0, 0, columns, &format!(" rust8080  frame {}  cycles {}  {}", debugger.machine.frame, vm.cycles,
+                                    if self.running { "running" } else { "stopped" }));
+        put(memory_top - 1, 0, columns, " memory");
+        attroff(A_REVERSE());
+
+        // Disassembly, with a marker at PC and a star on breakpoints
+        let code_rows: usize = (memory_top - 2).max(0) as usize;
+        let anchor: u16 = self.code.unwrap_or(vm.pc);
+        let before: usize = if self.code.is_some() { 0 } else { code_rows / 3 };
+        let mut y: i32 = 1;
+        for addr in code_lines(debugger, anchor, before, code_rows) {
+            if y > code_rows as i32 {
+                break;
+            }
+            if let Some(symbol) = debugger.symbols.get(addr) {
+                put(y, 0, CODE_WIDTH, &format!("{}:", symbol.name));
+                y += 1;
+                if y > code_rows as i32 {
+                    break;
+                }
+            }
+            let marker: &str = if addr == vm.pc { ">" } else { " " };
+            let breakpoint: &str = if debugger.breakpoints().iter().any(|bp| bp.addr == addr && bp.enabled) { "*" } else { " " };
+            if addr == vm.pc {
+                attron(A_BOLD());
+            }
+            put(y, 0, CODE_WIDTH, &format!("{}{} {:04x}  {}", marker, breakpoint, addr, debugger.instruction_at(addr)));
+            attroff(A_BOLD());
+            y += 1;
+        }
+
+        // Registers and flags
+        let x: i32 = CODE_WIDTH + 2;
+        let width: i32 = columns - x;
+        let flags = &vm.condition_codes;
+        put(1, x, width, &format!("A  {:02x}    BC {:02x}{:02x}", vm.a, vm.b, vm.c));
+        put(2, x, width, &format!("DE {:02x}{:02x}  HL {:02x}{:02x}", vm.d, vm.e, vm.h, vm.l));
+        put(3, x, width, &format!("SP {:04x}  PC {:04x}", vm.sp, vm.pc));
+        put(4, x, width, &format!("z{} s{} p{} cy{} ac{}  int {}", flags.z, flags.s, flags.p, flags.cy, flags.ac,
+                                  if vm.int_enable != 0 { "on" } else { "off" }));
+
+        // Stack, top first, with names for anything that looks like code
+        put(6, x, width, "stack");
+        let stack_rows: usize = (memory_top - 8).max(0) as usize;
+        for index in 0..stack_rows.min(STACK_WORDS) {
+            let addr: u16 = vm.sp.wrapping_add(2 * index as u16);
+            let bytes: Vec<u8> = vm.memory.read_range(addr, 2);
+            let word: u16 = (bytes[1] as u16) << 8 | bytes[0] as u16;
+            let name: String = debugger.name_for(word).map(|name| format!("  {}", name)).unwrap_or_default();
+            put(7 + index as i32, x, width, &format!("{:04x}  {:04x}{}", addr, word, name));
+        }
+
+        // Memory
+        for row in 0..memory_rows {
+            let addr: u16 = self.memory.wrapping_add(row as u16 * HEX_PER_ROW);
+            let bytes: Vec<u8> = vm.memory.read_range(addr, HEX_PER_ROW as usize);
+            let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+            let ascii: String = bytes.iter()
+                .map(|&byte| if (0x20..0x7f).contains(&byte) { byte as char } else { '.' })
+                .collect();
+            let name: String = debugger.symbols.get(addr).map(|symbol| format!("  {}", symbol.name)).unwrap_or_default();
+            put(memory_top + row, 0, columns, &format!("{:04x}  {}  {}{}", addr, hex.join(" "), ascii, name));
+        }
+
+        let status: &str = if self.message.is_empty() { HELP } else { &self.message };
+        put(rows - 1, 0, columns, status);
+        refresh();
+    }
+
+    /// Reads a line on the status row.
+    fn prompt(&self, text: &str) -> String {
+        let (mut rows, mut columns): (i32, i32) = (0, 0);
+        getmaxyx(stdscr(), &mut rows, &mut columns);
+        put(rows - 1, 0, columns, text);
+        mv(rows - 1, text.len() as i32);
+        echo();
+        curs_set(CURSOR_VISIBILITY::CURSOR_VISIBLE);
+        timeout(-1);
+        let mut line = String::new();
+        getnstr(&mut line, columns - text.len() as i32 - 1);
+        noecho();
+        curs_set(CURSOR_VISIBILITY::CURSOR_INVISIBLE);
+        line.trim().to_string()
+    }
+
+    /// Prompts for an address expression.  Empty input gives None.
+    fn address(&mut self, debugger: &Debugger<Invaders>, text: &str) -> Option<u16> {
+        let line: String = self.prompt(text);
+        if line.is_empty() {
+            return None;
+        }
+        match Expr::parse(&line, &debugger.symbols) {
+            Ok(expr) => Some(expr.evaluate(debugger.vm()) as u16),
+            Err(message) => {
+                self.message = message;
+                None
+            },
+        }
+    }
+
+    /// Shows how a run ended: the first line of what the command line
+    /// debugger would print.
+    fn stopped(&mut self, debugger: &Debugger<Invaders>, stop: Stop) {
+        self.running = false;
+        self.code = None;
+        if stop != Stop::Done {
+            self.message = repl::describe(debugger, &stop).lines().next().unwrap_or("").to_string();
+        }
+    }
+
+    /// Prompts for a slot number, 1 if none is given.
+    fn slot(&mut self, text: &str) -> Option<PathBuf> {
+        let line: String = self.prompt(text);
+        match line.parse::<u8>() {
+            Ok(slot) if slot <= 9 => Some(state::slot_path(&self.rom, slot)),
+            _ if line.is_empty() => Some(state::slot_path(&self.rom, 1)),
+            _ => {
+                self.message = format!("bad slot `{}`", line);
+                None
+            },
+        }
+    }
+
+    /// Handles one key.  Returns false to quit.
+    fn key(&mut self, debugger: &mut Debugger<Invaders>, key: i32) -> bool {
+        self.message.clear();
+        let rows: u16 = 8;
+        match key {
+            k if k == 'q' as i32 => return false,
+            k if k == 'c' as i32 => {
+                self.running = !self.running;
+                self.code = None;
+            },
+            k if self.running && k != KEY_UP && k != KEY_DOWN && k != KEY_PPAGE && k != KEY_NPAGE => (),
+            k if k == 's' as i32 => {
+                let stop: Stop = debugger.step();
+                self.stopped(debugger, stop);
+            },
+            k if k == 'n' as i32 => {
+                let stop: Stop = debugger.step_over();
+                self.stopped(debugger, stop);
+            },
+            k if k == 'o' as i32 => {
+                let stop: Stop = debugger.step_out();
+                self.stopped(debugger, stop);
+            },
+            k if k == 'i' as i32 => {
+                let stop: Stop = debugger.run_until_interrupt();
+                self.stopped(debugger, stop);
+            },
+            k if k == 'R' as i32 => {
+                let on: bool = debugger.history().is_none();
+                debugger.set_recording(on);
+                self.message = (if on { "recording" } else { "not recording" }).to_string();
+            },
+            k if (k == 'p' as i32 || k == 'r' as i32) && debugger.history().is_none() => {
+                self.message = "not recording, press R first".to_string();
+            },
+            k if k == 'p' as i32 => {
+                let stop: Stop = debugger.step_back();
+                self.stopped(debugger, stop);
+            },
+            k if k == 'r' as i32 => {
+                let stop: Stop = debugger.reverse_continue();
+                self.stopped(debugger, stop);
+            },
+            k if k == 'b' as i32 => {
+                // Toggles: an existing unconditional breakpoint is removed
+                let pc: u16 = debugger.vm().pc;
+                let addr: u16 = self.address(debugger, &format!("break at [{:04x}]: ", pc)).unwrap_or(pc);
+                let existing: Option<usize> = debugger.breakpoints().iter()
+                    .find(|bp| bp.addr == addr && bp.condition.is_none())
+                    .map(|bp| bp.id);
+                match existing {
+                    Some(id) => {
+                        debugger.remove(id);
+                        self.message = format!("deleted breakpoint {}", id);
+                    },
+                    None => self.message = format!("breakpoint {} at {:04x}", debugger.add_breakpoint(addr, None), addr),
+                }
+            },
+            k if k == 'g' as i32 => self.code = self.address(debugger, "code at (empty follows PC): "),
+            k if k == 'm' as i32 => {
+                if let Some(addr) = self.address(debugger, "memory at: ") {
+                    self.memory = addr & !(HEX_PER_ROW - 1);
+                }
+            },
+            k if k == 'S' as i32 => {
+                if let Some(path) = self.slot("save to slot [1]: ") {
+                    self.message = match debugger.machine.save_state().save(&path) {
+                        Ok(()) => format!("saved frame {} to {}", debugger.machine.frame, path.display()),
+                        Err(error) => format!("{}: {}", path.display(), error),
+                    };
+                }
+            },
+            k if k == 'L' as i32 => {
+                if let Some(path) = self.slot("load slot [1]: ") {
+                    self.message = match SaveState::load(&path).and_then(|saved| debugger.machine.load_state(&saved)) {
+                        Ok(()) => {
+                            debugger.reset_history();
+                            format!("loaded frame {} from {}", debugger.machine.frame, path.display())
+                        },
+                        Err(error) => format!("{}: {}", path.display(), error),
+                    };
+                    self.code = None;
+                }
+            },
+            k if k == ':' as i32 => {
+                let line: String = self.prompt(":");
+                self.message = match repl::execute(debugger, &line) {
+                    Ok(text) => text.lines().next().unwrap_or("").to_string(),
+                    Err(message) => format!("error: {}", message),
+                };
+            },
+            KEY_UP => self.memory = self.memory.wrapping_sub(HEX_PER_ROW),
+            KEY_DOWN => self.memory = self.memory.wrapping_add(HEX_PER_ROW),
+            KEY_PPAGE => self.memory = self.memory.wrapping_sub(rows * HEX_PER_ROW),
+            KEY_NPAGE => self.memory = self.memory.wrapping_add(rows * HEX_PER_ROW),
+            _ => (),
+        }
+        true
+    }
+}
+
+/// Runs the debugger full screen until the user quits.
+pub fn run(debugger: &mut Debugger<Invaders>, rom: &Path) {
+    let frame_time: f64 = 1.0 / invaders::FRAMES_PER_SECOND as f64;
+    initscr();
+    cbreak();
+    noecho();
+    keypad(stdscr(), true);
+    curs_set(CURSOR_VISIBILITY::CURSOR_INVISIBLE);
+    let mut tui = Tui { rom: rom.to_path_buf(), running: false, code: None, memory: 0x2000, message: String::new() };
+    loop {
+        let started: f64 = time::precise_time_s();
+        if tui.running {
+            let stop: Stop = debugger.run_frame();
+            if stop != Stop::Done {
+                tui.stopped(debugger, stop);
+            }
+        }
+        tui.draw(debugger);
+        // Poll while running, wait while stopped
+        timeout(if tui.running { 0 } else { -1 });
+        let key: i32 = getch();
+        if key != ERR && !tui.key(debugger, key) {
+            break;
+        }
+        if tui.running {
+            // Hold the emulated 60 Hz against the host clock
+            let remaining: f64 = frame_time - (time::precise_time_s() - started);
+            if remaining > 0.0 {
+                thread::sleep(Duration::from_millis((remaining * 1000.0) as u64));
+            }
+        }
+    }
+    endwin();
+}
@@ -0,0 +1,73 @@
+/*
+    Results of stepping the CPU.
+*/
+use std::error::Error;
+use std::fmt;
+
+/// What a successfully executed instruction did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StepOutcome {
+    pub pc: u16,          // Address the instruction was fetched from
+    pub opcode: u8,
+    pub cycles: u32,      // T-states taken
+}
+
+/// What one step of a machine did: run an instruction, or something that
+/// happens between instructions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    Instruction(StepOutcome),
+    /// An interrupt was raised; `accepted` is false if the CPU ignored it
+    Interrupt { vector: u8, accepted: bool },
+    /// The CPU is halted and idled until the next interrupt is due
+    Idle { cycles: u32 },
+    /// A video frame ended
+    Frame,
+}
+
+/// Why an instruction could not run.  The CPU state is left as it was before
+/// the instruction, so the caller can inspect it, fix things up and retry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmError {
+    /// An opcode the core refuses to execute (undocumented aliases when
+    /// `Vm::strict_opcodes` is set)
+    UnimplementedOpcode { pc: u16, opcode: u8 },
+    /// IN or OUT on a port the machine has nothing wired to
+    UnmappedPort { pc: u16, port: u8 },
+    /// POP or RET with SP at 0xffff, where the word would be read from
+    /// 0xffff and 0x0000.  This is the only underflow the core can see: it
+    /// has no idea where a program put its stack, and a pop that ends at
+    /// the top of memory (SP 0xfffe, leaving 0x0000) is ordinary use.
+    StackUnderflow { pc: u16, sp: u16 },
+    /// The CPU executed HLT and is waiting for an interrupt
+    Halted { pc: u16 },
+}
+
+impl VmError {
+    /// Returns the error reported against the instruction at `pc`.
+    pub fn at(self, pc: u16) -> VmError {
+        match self {
+            VmError::UnimplementedOpcode { opcode, .. } => VmError::UnimplementedOpcode { pc, opcode },
+            VmError::UnmappedPort { port, .. } => VmError::UnmappedPort { pc, port },
+            VmError::StackUnderflow { sp, .. } => VmError::StackUnderflow { pc, sp },
+            VmError::Halted { .. } => VmError::Halted { pc },
+        }
+    }
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            VmError::UnimplementedOpcode { pc, opcode } =>
+                write!(f, "unimplemented opcode 0x{:02x} at 0x{:04x}", opcode, pc),
+            VmError::UnmappedPort { pc, port } =>
+                write!(f, "unmapped port 0x{:02x} at 0x{:04x}", port, pc),
+            VmError::StackUnderflow { pc, sp } =>
+                write!(f, "stack underflow (sp 0x{:04x}) at 0x{:04x}", sp, pc),
+            VmError::Halted { pc } =>
+                write!(f, "halted at 0x{:04x}", pc),
+        }
+    }
+}
+
+impl Error for VmError {}
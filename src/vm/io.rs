@@ -0,0 +1,74 @@
+/*
+    Devices attached to the IN and OUT instructions.
+*/
+
+/// A machine's I/O port map.  The CPU core calls `input` for IN and `output`
+/// for OUT, so each machine wires its own devices without touching the core.
+pub trait PortIo {
+    /// Returns the byte read by `IN port`, or None if nothing answers on it.
+    fn input(&mut self, port: u8) -> Option<u8>;
+    /// Receives the accumulator written by `OUT port`.  Returns false if
+    /// nothing listens on the port.
+    fn output(&mut self, port: u8, value: u8) -> bool;
+}
+
+/// Ports for a machine with no devices: every IN reads 0 and OUT is ignored.
+#[derive(Debug, Default)]
+pub struct NullPorts;
+
+impl PortIo for NullPorts {
+    fn input(&mut self, _port: u8) -> Option<u8> {
+        Some(0)
+    }
+    fn output(&mut self, _port: u8, _value: u8) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vm::{Vm, VmError};
+
+    /// Answers IN on ports below 0x10 with the port number plus 0x40, and
+    /// takes OUT on any port but 0xff.
+    #[derive(Default)]
+    struct Ports {
+        written: Vec<(u8, u8)>,
+    }
+
+    impl PortIo for Ports {
+        fn input(&mut self, port: u8) -> Option<u8> {
+            if port < 0x10 { Some(port + 0x40) } else { None }
+        }
+        fn output(&mut self, port: u8, value: u8) -> bool {
+            self.written.push((port, value));
+            port != 0xff
+        }
+    }
+
+    #[test]
+    fn in_and_out_reach_the_machine_ports() {
+        // IN 3; OUT 7; MVI A,9; OUT 2
+        let mut vm: Vm<Ports> = Vm::default();
+        vm.memory.load(0, &[0xdb, 0x03, 0xd3, 0x07, 0x3e, 0x09, 0xd3, 0x02]);
+        for _ in 0..4 {
+            vm.run_current_opcode().unwrap();
+        }
+        assert_eq!(vm.a, 0x09);
+        assert_eq!(vm.io.written, vec![(0x07, 0x43), (0x02, 0x09)]);
+    }
+
+    #[test]
+    fn ports_nothing_answers_on_are_errors() {
+        // IN 20h; OUT 0FFh
+        let mut vm: Vm<Ports> = Vm::default();
+        vm.memory.load(0, &[0xdb, 0x20, 0xd3, 0xff]);
+        vm.a = 0x55;
+        assert_eq!(vm.run_current_opcode(), Err(VmError::UnmappedPort { pc: 0, port: 0x20 }));
+        assert_eq!((vm.pc, vm.a), (0, 0x55));
+        vm.pc = 2;
+        assert_eq!(vm.run_current_opcode(), Err(VmError::UnmappedPort { pc: 2, port: 0xff }));
+        assert_eq!(vm.pc, 2);
+    }
+}
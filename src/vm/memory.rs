@@ -0,0 +1,202 @@
+/*
+    The memory bus the CPU reads and writes through.
+*/
+
+/// Everything the CPU can address.  Machines pick how the 64 KiB address
+/// space is decoded: flat RAM, ROM with write protection, mirrors and so on.
+pub trait Bus {
+    /// Reads the byte at `addr`.
+    fn read(&self, addr: u16) -> u8;
+    /// Writes `value` to `addr`, subject to the machine's protection rules.
+    fn write(&mut self, addr: u16, value: u8);
+    /// Copies `data` to `addr` ignoring write protection, for loading ROM
+    /// images and restoring state.
+    fn load(&mut self, addr: u16, data: &[u8]);
+    /// Reads `len` consecutive bytes starting at `addr`, wrapping at 0xffff.
+    fn read_range(&self, addr: u16, len: usize) -> Vec<u8> {
+        (0..len).map(|offset| self.read(addr.wrapping_add(offset as u16))).collect()
+    }
+}
+
+/// 64 KiB of RAM with nothing special mapped anywhere.
+pub struct FlatRam {
+    pub bytes: Vec<u8>,
+}
+
+impl FlatRam {
+    pub fn new() -> FlatRam {
+        FlatRam { bytes: vec![0; 0x10000] }
+    }
+}
+
+impl Default for FlatRam {
+    fn default() -> FlatRam {
+        FlatRam::new()
+    }
+}
+
+impl Bus for FlatRam {
+    fn read(&self, addr: u16) -> u8 {
+        self.bytes[addr as usize]
+    }
+    fn write(&mut self, addr: u16, value: u8) {
+        self.bytes[addr as usize] = value;
+    }
+    fn load(&mut self, addr: u16, data: &[u8]) {
+        for (offset, byte) in data.iter().enumerate() {
+            self.bytes[addr.wrapping_add(offset as u16) as usize] = *byte;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionKind {
+    /// Readable, writes are dropped and recorded
+    Rom,
+    /// Readable and writable
+    Ram,
+    /// Repeats the `len` bytes starting at `target` across the region
+    Mirror { target: u16, len: u16 },
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Region {
+    pub start: u16,
+    pub end: u16,         // Inclusive
+    pub kind: RegionKind,
+}
+
+/// Address space decoded through a list of regions.  Addresses outside every
+/// region read as 0 and ignore writes, like ROM.
+pub struct MemoryMap {
+    bytes: Vec<u8>,
+    regions: Vec<Region>,
+    pub ignored_writes: u64,              // Writes dropped by ROM or unmapped space
+    pub last_ignored_write: Option<u16>,  // Address of the most recent one
+}
+
+impl MemoryMap {
+    pub fn new() -> MemoryMap {
+        MemoryMap {
+            bytes: vec![0; 0x10000],
+            regions: Vec::new(),
+            ignored_writes: 0,
+            last_ignored_write: None,
+        }
+    }
+    /// Maps `start..=end` as `kind`.  Regions added later take precedence.
+    pub fn map(&mut self, start: u16, end: u16, kind: RegionKind) {
+        self.regions.insert(0, Region { start, end, kind });
+    }
+    pub fn regions(&self) -> &[Region] {
+        &self.regions
+    }
+    /// Follows mirrors and returns the backing address and its region kind.
+    fn decode(&self, mut addr: u16) -> Option<(u16, RegionKind)> {
+        // Bounded so a mirror pointing at itself can't loop forever
+        for _ in 0..self.regions.len() + 1 {
+            let region = match self.regions.iter().find(|r| r.start <= addr && addr <= r.end) {
+                Some(region) => *region,
+                None => return None,
+            };
+            match region.kind {
+                RegionKind::Mirror { target, len } => {
+                    addr = target.wrapping_add((addr - region.start) % len.max(1));
+                },
+                kind => return Some((addr, kind)),
+            }
+        }
+        None
+    }
+}
+
+impl Default for MemoryMap {
+    fn default() -> MemoryMap {
+        MemoryMap::new()
+    }
+}
+
+impl Bus for MemoryMap {
+    fn read(&self, addr: u16) -> u8 {
+        match self.decode(addr) {
+            Some((addr, _)) => self.bytes[addr as usize],
+            None => 0,
+        }
+    }
+    fn write(&mut self, addr: u16, value: u8) {
+        match self.decode(addr) {
+            Some((target, RegionKind::Ram)) => self.bytes[target as usize] = value,
+            _ => {
+                self.ignored_writes += 1;
+                self.last_ignored_write = Some(addr);
+            },
+        }
+    }
+    fn load(&mut self, addr: u16, data: &[u8]) {
+        for (offset, byte) in data.iter().enumerate() {
+            let addr: u16 = addr.wrapping_add(offset as u16);
+            let target: u16 = match self.decode(addr) {
+                Some((target, _)) => target,
+                None => addr,
+            };
+            self.bytes[target as usize] = *byte;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// ROM at 0000-1fff, RAM at 2000-3fff mirrored at 4000-5fff.
+    fn map() -> MemoryMap {
+        let mut map = MemoryMap::new();
+        map.map(0x0000, 0x1fff, RegionKind::Rom);
+        map.map(0x2000, 0x3fff, RegionKind::Ram);
+        map.map(0x4000, 0x5fff, RegionKind::Mirror { target: 0x2000, len: 0x2000 });
+        map
+    }
+
+    #[test]
+    fn rom_ignores_writes_but_loads() {
+        let mut map = map();
+        map.load(0x0010, &[0xc3, 0x00]);
+        map.write(0x0010, 0x00);
+        assert_eq!(map.read(0x0010), 0xc3);
+        assert_eq!((map.ignored_writes, map.last_ignored_write), (1, Some(0x0010)));
+    }
+
+    #[test]
+    fn mirrors_share_the_bytes_they_repeat() {
+        let mut map = map();
+        map.write(0x4123, 0x77);
+        assert_eq!(map.read(0x2123), 0x77);
+        map.write(0x2400, 0x11);
+        assert_eq!(map.read(0x4400), 0x11);
+        // A short mirror repeats several times over its region
+        map.map(0x6000, 0x60ff, RegionKind::Mirror { target: 0x2000, len: 0x10 });
+        map.write(0x2003, 0x99);
+        for &addr in [0x6003, 0x6013, 0x60f3].iter() {
+            assert_eq!(map.read(addr), 0x99);
+        }
+        // Loading through a mirror lands in what it mirrors
+        map.load(0x5000, &[0x42]);
+        assert_eq!(map.read(0x3000), 0x42);
+        assert_eq!(map.ignored_writes, 0);
+    }
+
+    #[test]
+    fn counts_writes_to_rom_and_unmapped_space() {
+        let mut map = map();
+        map.write(0x2000, 0x01);
+        assert_eq!((map.ignored_writes, map.last_ignored_write), (0, None));
+        map.write(0x1000, 0x01);
+        map.write(0x8000, 0x02);
+        assert_eq!(map.read(0x8000), 0x00);
+        assert_eq!((map.ignored_writes, map.last_ignored_write), (2, Some(0x8000)));
+        // Mirrors of ROM are read-only too
+        map.map(0x6000, 0x7fff, RegionKind::Mirror { target: 0x0000, len: 0x2000 });
+        map.write(0x6005, 0x03);
+        assert_eq!((map.ignored_writes, map.last_ignored_write), (3, Some(0x6005)));
+    }
+}
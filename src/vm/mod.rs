@@ -1,518 +1,958 @@
 #![allow(dead_code)]
 use std::time::Duration;
 use std::thread;
-use std::num::Wrapping;
 use std::fmt;
+use disassemble::OPCODES;
+
+pub mod error;
+pub mod io;
+pub mod memory;
+pub use self::error::{Event, StepOutcome, VmError};
+pub use self::io::{NullPorts, PortIo};
+pub use self::memory::{Bus, FlatRam, MemoryMap, RegionKind};
 /*
-    This is the implementation of the VM itself.  
+    This is the implementation of the VM itself.
 */
+// T-states of the RST instruction jammed onto the bus by an interrupt
+const INTERRUPT_CYCLES: u32 = 11;
+
 #[derive(Debug, Default)]
-struct ConditionCodes {
+pub struct ConditionCodes {
     // Condition codes
-    z: u8,
-    s: u8,
-    p: u8,
-    cy: u8,
-    ac: u8,
+    pub z: u8,
+    pub s: u8,
+    pub p: u8,
+    pub cy: u8,
+    pub ac: u8,
     pad: u8,
 }
-#[derive(Default)]
-pub struct Vm {
+impl ConditionCodes {
+    /// Packs the flags into the byte PUSH PSW stores below A: S Z 0 AC 0 P 1 CY.
+    /// Bits 5 and 3 always read as 0 and bit 1 always reads as 1.
+    pub fn to_psw(&self) -> u8 {
+        self.s << 7 |
+            self.z << 6 |
+            self.ac << 4 |
+            self.p << 2 |
+            0x02 |
+            self.cy
+    }
+    /// Unpacks a flag byte popped by POP PSW, ignoring the fixed bits.
+    pub fn from_psw(psw: u8) -> ConditionCodes {
+        ConditionCodes {
+            z: (0x40 == (psw & 0x40)) as u8,
+            s: (0x80 == (psw & 0x80)) as u8,
+            p: (0x04 == (psw & 0x04)) as u8,
+            cy: (0x01 == (psw & 0x01)) as u8,
+            ac: (0x10 == (psw & 0x10)) as u8,
+            pad: 0,
+        }
+    }
+}
+pub struct Vm<P = NullPorts> {
     // State
-    a: u8,
-    b: u8,
-    c: u8,
-    d: u8,
-    e: u8,
-    h: u8,
-    l: u8,
-    sp: usize,            // Stack Pointer
-    pc: usize,            // Program Counter
+    pub a: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub sp: u16,          // Stack Pointer
+    pub pc: u16,          // Program Counter
     pub int_enable: u8,
-    pub memory: Vec<u8>,
-    condition_codes: ConditionCodes,
+    pub ei_pending: bool, // EI only takes effect after the following instruction
+    pub halted: bool,
+    pub strict_opcodes: bool, // Refuse the undocumented opcode aliases
+    pub cycles: u64,      // T-states executed since reset
+    pub memory: Box<dyn Bus>,  // Address space, flat RAM unless the machine maps its own
+    pub condition_codes: ConditionCodes,
+    pub io: P,            // Devices behind IN and OUT
 }
 fn format(hex: u8) -> String {
     format!("{:01$x}", hex, 2)
 }
-fn format_usize(hex: usize) -> String {
+fn format_u16(hex: u16) -> String {
     format!("{:01$x}", hex, 4)
 }
 
-impl fmt::Debug for Vm {
+impl<P> fmt::Debug for Vm<P> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "Vm {{\n\t a: {}\n\t b: {}\n\t c: {}\n\t d: {}\n\t e: {}\n\t h: {}\n\t \
         l: {}\n\t sp: {}\n\t pc: {}\n\t int_enable: {}\n\t condition_codes:\n\t {:#?}\n }}",
         format(self.a), format(self.b), format(self.c), format(self.d), format(self.e),
-        format(self.h), format(self.l), format_usize(self.sp),
-        format_usize(self.pc), format(self.int_enable), self.condition_codes)
+        format(self.h), format(self.l), format_u16(self.sp),
+        format_u16(self.pc), format(self.int_enable), self.condition_codes)
     }
 }
 impl Vm {
-    pub fn run_current_opcode(&mut self) {
-        let opcode: u8 = self.memory[self.pc];
+    pub fn new() -> Vm {
+        Vm::with_io(NullPorts)
+    }
+}
+impl<P: PortIo + Default> Default for Vm<P> {
+    fn default() -> Vm<P> {
+        Vm::with_io(P::default())
+    }
+}
+impl<P: PortIo> Vm<P> {
+    /// Executes a single instruction.  On error nothing has been changed, so
+    /// the same instruction runs again on the next call.
+    pub fn run_current_opcode(&mut self) -> Result<StepOutcome, VmError> {
+        let pc: u16 = self.pc;
+        if self.halted {
+            // HLT only ends with an interrupt
+            return Err(VmError::Halted { pc });
+        }
+        let opcode: u8 = self.read_byte(pc);
+        if self.strict_opcodes && OPCODES[opcode as usize].undocumented {
+            return Err(VmError::UnimplementedOpcode { pc, opcode });
+        }
+        let ei_pending: bool = self.ei_pending;
+        self.ei_pending = false;
+        match self.execute() {
+            Ok(cycles) => {
+                self.cycles += cycles as u64;
+                Ok(StepOutcome { pc, opcode, cycles })
+            },
+            Err(error) => {
+                self.pc = pc;
+                self.ei_pending = ei_pending;
+                Err(error.at(pc))
+            },
+        }
+    }
+    /// Runs instructions until at least `budget` T-states have elapsed and returns
+    /// how many T-states the last instruction ran past the budget.  A halted CPU
+    /// idles away the rest of the budget.
+    pub fn run_cycles(&mut self, budget: u32) -> Result<u32, VmError> {
+        let mut elapsed: u32 = 0;
+        while elapsed < budget {
+            match self.run_current_opcode() {
+                Ok(outcome) => elapsed += outcome.cycles,
+                Err(VmError::Halted { .. }) => {
+                    self.cycles += (budget - elapsed) as u64;
+                    elapsed = budget;
+                },
+                Err(error) => return Err(error),
+            }
+        }
+        Ok(elapsed - budget)
+    }
+    /// Fetches and executes the instruction at PC, returning its T-states.
+    /// Errors carry the PC at the point of failure, run_current_opcode
+    /// rewrites it to the instruction's address.
+    fn execute(&mut self) -> Result<u32, VmError> {
+        let opcode: u8 = self.fetch_byte();
+        let mut cycles: u32 = OPCODES[opcode as usize].cycles as u32;
         match opcode {
-            0x00 => { self.pc += 1;  }, 
-            0x01 => { 
-                // LXI B, D16
-                self.c = self.memory[self.pc + 1];
-                self.b = self.memory[self.pc + 2];
-                self.pc += 3;
-            },
-            0xc3 => {
-                // JMP addr
-                self.pc =  self.merge_addr_pair(self.memory[self.pc + 1],
-                                                self.memory[self.pc + 2]) as usize;
-            },
-            0x31 => {
-                // LXI SP, D16
-                self.sp = self.merge_addr_pair(self.memory[self.pc + 1],
-                                               self.memory[self.pc + 2]) as usize;
-                self.pc += 3;
-            },
-            0x06 => {
-                // MVI B, D8
-                self.b = self.memory[self.pc + 1];
-                self.pc += 2;
-            },
-            0xcd => {
-                // CALL
-                let ret_addr: u16 = (self.pc + 3) as u16;
-                self.memory[self.sp - 1] = ((ret_addr >> 8) & 0xff) as u8;
-                self.memory[self.sp - 2] = (ret_addr & 0xff) as u8;
-                self.sp -= 2;
-                self.pc = self.merge_addr_pair(self.memory[self.pc+1],
-                                               self.memory[self.pc+2]) as usize;
-            },
-            0x11 => {
-                // LXI D, D16
-                self.e = self.memory[self.pc + 1];
-                self.d = self.memory[self.pc + 2];
-                self.pc += 3;
-            },
-            0x21 => {
-                // LXI H, D16
-                self.l = self.memory[self.pc + 1];
-                self.h = self.memory[self.pc + 2];
-                self.pc += 3;
-            },
-            0x1a => {
-                // LDAX D
-                let mut offset: u16 = (self.d as u16) << 8;
-                offset = offset | (self.e as u16);
-                self.a = self.memory[offset as usize];
-                self.pc += 1;
-
+            0x00 | 0x08 | 0x10 | 0x18 | 0x20 | 0x28 | 0x30 | 0x38 => {
+                // NOP (0x08-0x38 are undocumented aliases)
+            },
+            0x01 | 0x11 | 0x21 | 0x31 => {
+                // LXI rp, D16
+                let value: u16 = self.fetch_word();
+                self.set_pair(opcode >> 4, value);
+            },
+            0x02 | 0x12 => {
+                // STAX B / STAX D
+                let offset: u16 = self.get_pair(opcode >> 4);
+                let a: u8 = self.a;
+                self.write_byte(offset, a);
+            },
+            0x0a | 0x1a => {
+                // LDAX B / LDAX D
+                let offset: u16 = self.get_pair(opcode >> 4);
+                self.a = self.read_byte(offset);
+            },
+            0x03 | 0x13 | 0x23 | 0x33 => {
+                // INX rp
+                let rp: u8 = opcode >> 4;
+                let value: u16 = self.get_pair(rp).wrapping_add(1);
+                self.set_pair(rp, value);
+            },
+            0x0b | 0x1b | 0x2b | 0x3b => {
+                // DCX rp
+                let rp: u8 = opcode >> 4;
+                let value: u16 = self.get_pair(rp).wrapping_sub(1);
+                self.set_pair(rp, value);
+            },
+            0x04 | 0x0c | 0x14 | 0x1c | 0x24 | 0x2c | 0x34 | 0x3c => {
+                // INR r
+                let r: u8 = (opcode >> 3) & 0x07;
+                let res: u8 = self.get_reg(r).wrapping_add(1);
+                self.condition_codes.ac = (res & 0x0f == 0x00) as u8;
+                self.zsp_flags(res);
+                self.set_reg(r, res);
             },
-            0x77 => {
-                // MOVE M,A
-                let mut offset: u16 = (self.h as u16) << 8;
-                offset = offset | (self.l as u16);
-                self.memory[offset as usize] = self.a;
-                self.pc += 1;
-            },
-            0x23 => {
-                // INX H
-                self.l = self.floating_point_add(self.l, 1);
-                if self.l == 0 {
-                    self.h += 1;
-                }
-                self.pc += 1;
+            0x05 | 0x0d | 0x15 | 0x1d | 0x25 | 0x2d | 0x35 | 0x3d => {
+                // DCR r
+                let r: u8 = (opcode >> 3) & 0x07;
+                let res: u8 = self.get_reg(r).wrapping_sub(1);
+                self.condition_codes.ac = (res & 0x0f != 0x0f) as u8;
+                self.zsp_flags(res);
+                self.set_reg(r, res);
+            },
+            0x06 | 0x0e | 0x16 | 0x1e | 0x26 | 0x2e | 0x36 | 0x3e => {
+                // MVI r, D8
+                let value: u8 = self.fetch_byte();
+                self.set_reg((opcode >> 3) & 0x07, value);
+            },
+            0x09 | 0x19 | 0x29 | 0x39 => {
+                // DAD rp
+                let hl: u32 = self.get_pair(2) as u32;
+                let res: u32 = hl + self.get_pair(opcode >> 4) as u32;
+                self.condition_codes.cy = (res > 0xffff) as u8;
+                self.set_pair(2, res as u16);
+            },
+            0x07 => {
+                // RLC
+                let x: u8 = self.a;
+                self.a = x.rotate_left(1);
+                self.condition_codes.cy = x >> 7;
             },
-            0x13 => {
-                // INX D
-                self.e = self.floating_point_add(self.e,1);
-                if self.e == 0 {
-                    self.d += 1;
-                }
-                self.pc += 1;
-            },
-            0x05 => {
-                // DCR B
-                
-                let res: u8 = self.floating_point_sub(self.b, 1);
-                self.condition_codes.z = (res == 0) as u8;
-                self.condition_codes.s = (0x80 == (res & 0x80)) as u8;
-                self.condition_codes.p = self.parity(res);
-                self.b = res;
-                self.pc += 1;
-            },
-            0xc2 => {
-                // JNZ
-                if self.condition_codes.z == 0 {
-                    self.pc = self.merge_addr_pair(self.memory[self.pc+1], 
-                                                   self.memory[self.pc+2]) as usize;
+            0x0f => {
+                // RRC
+                let x: u8 = self.a;
+                self.a = x.rotate_right(1);
+                self.condition_codes.cy = x & 1;
+            },
+            0x17 => {
+                // RAL
+                let x: u8 = self.a;
+                self.a = (x << 1) | self.condition_codes.cy;
+                self.condition_codes.cy = x >> 7;
+            },
+            0x1f => {
+                // RAR
+                let x: u8 = self.a;
+                self.a = (x >> 1) | (self.condition_codes.cy << 7);
+                self.condition_codes.cy = x & 1;
+            },
+            0x22 => {
+                // SHLD word
+                let offset: u16 = self.fetch_word();
+                let (l, h) = (self.l, self.h);
+                self.write_byte(offset, l);
+                self.write_byte(offset.wrapping_add(1), h);
+            },
+            0x2a => {
+                // LHLD word
+                let offset: u16 = self.fetch_word();
+                self.l = self.read_byte(offset);
+                self.h = self.read_byte(offset.wrapping_add(1));
+            },
+            0x27 => {
+                // DAA
+                let mut correction: u8 = 0;
+                let mut cy: u8 = self.condition_codes.cy;
+                let lsb: u8 = self.a & 0x0f;
+                let msb: u8 = self.a >> 4;
+                if lsb > 9 || self.condition_codes.ac == 1 {
+                    correction |= 0x06;
                 }
-                else {
-                    self.pc += 3;
+                if msb > 9 || cy == 1 || (msb >= 9 && lsb > 9) {
+                    correction |= 0x60;
+                    cy = 1;
                 }
+                // The correction goes through the adder, which leaves the
+                // half carry of the low digit adjustment in AC.
+                self.add(correction, 0);
+                self.condition_codes.cy = cy;
             },
-            0xc9 => {
-                // RET
-                self.pc = self.merge_addr_pair(self.memory[self.sp],
-                                               self.memory[self.sp + 1]) as usize;
-                self.sp += 2;
-            },
-            0x19 => {
-                // DAD D
-                let hl: u32 = ((self.h as u32) << 8) | (self.l as u32);
-                let de: u32 = ((self.d as u32) << 8) | (self.e as u32);
-                let res: u32 = hl + de;
-                self.h = ((res & 0xff00) >> 8) as u8;
-                self.l = (res & 0xff) as u8;
-                self.condition_codes.cy = ((res & 0xffff0000) != 0) as u8;
-                self.pc += 1;
-            },
-            0x3e => {
-                // MVI A, D8
-                self.a = self.memory[self.pc + 1];
-                self.pc += 2;
+            0x2f => {
+                // CMA
+                self.a = !self.a;
             },
             0x32 => {
                 // STA word
-                let offset: u16 = self.merge_addr_pair(self.memory[self.pc + 1],
-                                                       self.memory[self.pc + 2]);
-                self.memory[offset as usize] = self.a;
-                self.pc += 3;
-            },
-            0xaf => {
-                // XRA A
-                self.a = self.a ^ self.a;
-                self.logic_flags_a();
-                self.pc += 1;
-            },
-            0xd3 => {
-                // OUT D8
-                // NOT IMPLEMENTED YET
-                println!("OUT: {}", self.memory[self.pc + 1]);
-                self.pc += 2;
-            },
-            0xfb => {
-                // EI
-                // Enable interrupt?
-                self.int_enable = 1;
-                self.pc += 1;
+                let offset: u16 = self.fetch_word();
+                let a: u8 = self.a;
+                self.write_byte(offset, a);
             },
             0x3a => {
                 // LDA word
-                let offset: u16 = self.merge_addr_pair(self.memory[self.pc + 1],
-                                                       self.memory[self.pc + 2]);
-                self.a = self.memory[offset as usize];
-                self.pc += 3;
-            },
-            0xa7 => {
-                // ANA A
-                self.a = self.a & self.a;
+                let offset: u16 = self.fetch_word();
+                self.a = self.read_byte(offset);
+            },
+            0x37 => {
+                // STC
+                self.condition_codes.cy = 1;
+            },
+            0x3f => {
+                // CMC
+                self.condition_codes.cy ^= 1;
+            },
+            0x76 => {
+                // HLT
+                self.halted = true;
+            },
+            0x40..=0x7f => {
+                // MOV r, r
+                let value: u8 = self.get_reg(opcode & 0x07);
+                self.set_reg((opcode >> 3) & 0x07, value);
+            },
+            0x80..=0x87 => {
+                // ADD r
+                let value: u8 = self.get_reg(opcode & 0x07);
+                self.add(value, 0);
+            },
+            0x88..=0x8f => {
+                // ADC r
+                let value: u8 = self.get_reg(opcode & 0x07);
+                let cy: u8 = self.condition_codes.cy;
+                self.add(value, cy);
+            },
+            0x90..=0x97 => {
+                // SUB r
+                let value: u8 = self.get_reg(opcode & 0x07);
+                self.a = self.sub(value, 0);
+            },
+            0x98..=0x9f => {
+                // SBB r
+                let value: u8 = self.get_reg(opcode & 0x07);
+                let cy: u8 = self.condition_codes.cy;
+                self.a = self.sub(value, cy);
+            },
+            0xa0..=0xa7 => {
+                // ANA r
+                let value: u8 = self.get_reg(opcode & 0x07);
+                self.ana(value);
+            },
+            0xa8..=0xaf => {
+                // XRA r
+                let value: u8 = self.get_reg(opcode & 0x07);
+                self.a ^= value;
                 self.logic_flags_a();
-                self.pc += 1;
             },
-            0xf5 => {
-                self.memory[self.sp - 1] = self.a;
-                let psw: u8 =  self.condition_codes.z |
-                                 self.condition_codes.s << 1 |
-                                 self.condition_codes.p << 2 |
-                                 self.condition_codes.cy << 3 |
-                                 self.condition_codes.ac << 4 ;
-                self.memory[self.sp - 2] = psw as u8;
-                self.sp -= 2;
-                self.pc += 1;
-            },
-            0xc5 => {
-                self.memory[self.sp - 1] = self.b;
-                self.memory[self.sp - 2] = self.c;
-                self.sp -= 2;
-                self.pc += 1;
-            },
-            0xd5 => {
-                self.memory[self.sp - 1] = self.d;
-                self.memory[self.sp - 2] = self.e;
-                self.sp -= 2;
-                self.pc += 1;
-            },
-            0xe5 => {
-                self.memory[self.sp - 1] = self.h;
-                self.memory[self.sp - 2] = self.l;
-                self.sp -= 2;
-                self.pc += 1;
-            },
-            0x35 => {
-                let mut res: u8 = self.read_from_hl();
-                res = self.floating_point_add(res, 1);
-                self.zsp_flags(res);
-                self.write_to_hl(res);
-                self.pc += 1;
+            0xb0..=0xb7 => {
+                // ORA r
+                let value: u8 = self.get_reg(opcode & 0x07);
+                self.a |= value;
+                self.logic_flags_a();
             },
-            0xdb => {
-                let port = self.memory[self.pc + 1];
-                if port == 2 {
-                    self.a = 0;
+            0xb8..=0xbf => {
+                // CMP r
+                let value: u8 = self.get_reg(opcode & 0x07);
+                self.sub(value, 0);
+            },
+            0xc0 | 0xc8 | 0xd0 | 0xd8 | 0xe0 | 0xe8 | 0xf0 | 0xf8 => {
+                // Rcc
+                if self.condition((opcode >> 3) & 0x07) {
+                    self.pc = self.pop_word()?;
+                    cycles = OPCODES[opcode as usize].cycles_taken as u32;
                 }
-                else if port == 1 {
-                    self.a = 0;
+            },
+            0xc9 | 0xd9 => {
+                // RET (0xd9 is an undocumented alias)
+                self.pc = self.pop_word()?;
+            },
+            0xc2 | 0xca | 0xd2 | 0xda | 0xe2 | 0xea | 0xf2 | 0xfa => {
+                // Jcc addr
+                let addr: u16 = self.fetch_word();
+                if self.condition((opcode >> 3) & 0x07) {
+                    self.pc = addr;
                 }
-                else {
-                    panic!("Found port: {}", port);
+            },
+            0xc3 | 0xcb => {
+                // JMP addr (0xcb is an undocumented alias)
+                self.pc = self.fetch_word();
+            },
+            0xc4 | 0xcc | 0xd4 | 0xdc | 0xe4 | 0xec | 0xf4 | 0xfc => {
+                // Ccc addr
+                let addr: u16 = self.fetch_word();
+                if self.condition((opcode >> 3) & 0x07) {
+                    self.call(addr);
+                    cycles = OPCODES[opcode as usize].cycles_taken as u32;
                 }
-                self.pc += 2;
+            },
+            0xcd | 0xdd | 0xed | 0xfd => {
+                // CALL addr (0xdd, 0xed and 0xfd are undocumented aliases)
+                let addr: u16 = self.fetch_word();
+                self.call(addr);
+            },
+            0xc1 | 0xd1 | 0xe1 => {
+                // POP rp
+                let value: u16 = self.pop_word()?;
+                self.set_pair((opcode >> 4) & 0x03, value);
+            },
+            0xf1 => {
+                // POP PSW
+                let value: u16 = self.pop_word()?;
+                self.a = (value >> 8) as u8;
+                self.condition_codes = ConditionCodes::from_psw(value as u8);
+            },
+            0xc5 | 0xd5 | 0xe5 => {
+                // PUSH rp
+                let value: u16 = self.get_pair((opcode >> 4) & 0x03);
+                self.push_word(value);
+            },
+            0xf5 => {
+                // PUSH PSW
+                let psw: u8 = self.condition_codes.to_psw();
+                let value: u16 = ((self.a as u16) << 8) | psw as u16;
+                self.push_word(value);
+            },
+            0xc6 => {
+                // ADI D8
+                let value: u8 = self.fetch_byte();
+                self.add(value, 0);
+            },
+            0xce => {
+                // ACI D8
+                let value: u8 = self.fetch_byte();
+                let cy: u8 = self.condition_codes.cy;
+                self.add(value, cy);
+            },
+            0xd6 => {
+                // SUI D8
+                let value: u8 = self.fetch_byte();
+                self.a = self.sub(value, 0);
+            },
+            0xde => {
+                // SBI D8
+                let value: u8 = self.fetch_byte();
+                let cy: u8 = self.condition_codes.cy;
+                self.a = self.sub(value, cy);
             },
             0xe6 => {
-                self.a = self.a & self.memory[self.pc + 1];
+                // ANI D8
+                let value: u8 = self.fetch_byte();
+                self.ana(value);
+            },
+            0xee => {
+                // XRI D8
+                self.a ^= self.fetch_byte();
                 self.logic_flags_a();
-                self.pc += 2;
-            },
-            0xc8 => {
-                if self.condition_codes.z == 1 {
-                    self.pc = ((self.memory[self.sp] as u16) |
-                        ((self.memory[self.sp + 1] as u16) << 8)) as usize;
-                    self.sp += 2;
-                   
-                }
-                 self.pc += 1;
             },
-            0x0f => {
-                let x: u8 = self.a;
-                self.a = ((x & 1) << 7) | ( x >> 1);
-                self.condition_codes.cy = (1 == (x & 1)) as u8;
-                self.pc += 1;
-            },
-            0xda => {
-                if self.condition_codes.cy != 0 {
-                    self.pc = self.merge_addr_pair(self.memory[self.pc + 1], 
-                                                   self.memory[self.pc + 2]) as usize;
-                }
-                else {
-                    self.pc += 3;
-                }
-
+            0xf6 => {
+                // ORI D8
+                self.a |= self.fetch_byte();
+                self.logic_flags_a();
             },
-            0xca => {
-                if self.condition_codes.z == 1 {
-                    self.pc = self.merge_addr_pair(self.memory[self.pc + 1], 
-                                                   self.memory[self.pc + 2]) as usize;
-                }
-                else {
-                    self.pc += 3;
+            0xfe => {
+                // CPI D8
+                let value: u8 = self.fetch_byte();
+                self.sub(value, 0);
+            },
+            0xc7 | 0xcf | 0xd7 | 0xdf | 0xe7 | 0xef | 0xf7 | 0xff => {
+                // RST n
+                self.call((opcode & 0x38) as u16);
+            },
+            0xd3 => {
+                // OUT D8
+                let port: u8 = self.fetch_byte();
+                if !self.io.output(port, self.a) {
+                    return Err(VmError::UnmappedPort { pc: self.pc, port });
                 }
             },
-            0x0a => {
-                let offset: u16 = self.merge_addr_pair(self.c, self.b);
-                self.a = self.memory[offset as usize];
-                self.pc += 1;
+            0xdb => {
+                // IN D8
+                let port: u8 = self.fetch_byte();
+                match self.io.input(port) {
+                    Some(value) => self.a = value,
+                    None => return Err(VmError::UnmappedPort { pc: self.pc, port }),
+                }
             },
-            0x36 => {
-                let offset: u16 = ((self.h as u16 )<< 8) | (self.l as u16);
-                self.memory[offset as usize] = self.memory[self.pc + 1];
-                self.pc += 2;
+            0xe3 => {
+                // XTHL
+                let sp: u16 = self.sp;
+                let (l, h) = (self.l, self.h);
+                self.l = self.read_byte(sp);
+                self.h = self.read_byte(sp.wrapping_add(1));
+                self.write_byte(sp, l);
+                self.write_byte(sp.wrapping_add(1), h);
             },
-            0x7c => {
-                self.a = self.h;
-                self.pc += 1;
-            }, 
-            0xfe => {
-                let x: u8 = self.floating_point_sub(self.a, self.memory[self.pc + 1]) as u8;
-                self.condition_codes.z = (x == 0) as u8;
-                self.condition_codes.s = (0x80 == (x & 0x80)) as u8;
-                self.condition_codes.p = self.parity(x);
-                self.condition_codes.cy = (self.a < self.memory[self.pc + 1]) as u8;
-                self.pc += 2;
-            },
-            0x0e => {
-                self.c = self.memory[self.pc + 1];
-                self.pc += 2;
-            },
-            0x26 => {
-                self.h = self.memory[self.pc + 1];
-                self.pc += 2;
-            },
-            0x6f => {
-                self.l = self.a;
-                self.pc += 1;
-            },
-            0x29 => {
-                let hl: u32 = ((self.h as u32) << 8) | (self.l as u32);
-                let res: u32 = hl + hl;
-                self.h = ((res & 0xff00) >> 8) as u8;
-                self.l = (res & 0xff) as u8;
-                self.condition_codes.cy = ((res & 0xffff0000) != 0) as u8;
-                self.pc += 1;
-
+            0xe9 => {
+                // PCHL
+                self.pc = self.get_pair(2);
             },
             0xeb => {
+                // XCHG
                 let d: u8 = self.d;
                 let e: u8 = self.e;
                 self.d = self.h;
                 self.e = self.l;
                 self.h = d;
                 self.l = e;
-                self.pc += 1;
-            },
-            0xe1 => {
-                self.l = self.memory[self.sp];
-                self.h = self.memory[self.sp + 1];
-                self.sp += 2;
-                self.pc += 1;
-            },
-            0x09 => {
-                let hl: u32 = ((self.h as u32) << 8) | (self.l as u32);
-                let bc: u32 = ((self.b as u32) << 8) | (self.c as u32);
-                let res = hl + bc;
-                self.h = ((res & 0xff00) >> 8) as u8;
-                self.l = (res & 0xff) as u8;
-                self.condition_codes.cy = ((res & 0xffff0000) > 0) as u8;
-                self.pc += 1;
-            },
-            0xc1 => {
-                self.c = self.memory[self.sp];
-                self.b = self.memory[self.sp + 1];
-                self.sp += 2;
-                self.pc += 1;
-            },
-            0xd1 => {
-                self.e = self.memory[self.sp];
-                self.d = self.memory[self.sp + 1];
-                self.sp += 2;
-                self.pc += 1;
-            },
-            0x0d => {
-                let res: u8 = self.floating_point_sub(self.c, 1);
-                self.condition_codes.z = (res == 0) as u8;
-                self.condition_codes.s = (0x80 == (res & 0x80)) as u8;
-                self.condition_codes.p = self.parity(res);
-                self.c = res;
-                self.pc += 1;
-            },
-            0x5e => {
-                let offset: u16 = self.merge_addr_pair(self.l, self.h);
-                self.e = self.memory[offset as usize];
-                self.pc += 1;
-            },
-            0x56 => {
-                let offset: u16 = self.merge_addr_pair(self.l, self.h);
-                self.d = self.memory[offset as usize];
-                self.pc += 1;
-            },
-            0x7e => {
-                let offset: u16 = self.merge_addr_pair(self.l, self.h);
-                self.a = self.memory[offset as usize];
-                self.pc += 1;
-            },
-            0x66 => {
-                let offset: u16 = self.merge_addr_pair(self.l, self.h);
-                self.h = self.memory[offset as usize];
-                self.pc += 1;
-            },
-            0x7a => {
-                self.a = self.d;
-                self.pc += 1;
             },
-            0xc6 => {
-                let x: u16 = self.floating_point_add(self.a, self.memory[self.pc + 1]) as u16;
-                self.condition_codes.z = ((x & 0xff) == 0) as u8;
-                self.condition_codes.s = (0x80 == (x & 0x80)) as u8;
-                self.condition_codes.p = self.parity((x & 0xff) as u8);
-                self.condition_codes.cy = (x > 0xff) as u8;
-                self.a = x as u8;
-                self.pc += 2;
+            0xf3 => {
+                // DI
+                self.int_enable = 0;
+            },
+            0xf9 => {
+                // SPHL
+                self.sp = self.get_pair(2);
+            },
+            0xfb => {
+                // EI
+                self.int_enable = 1;
+                self.ei_pending = true;
             },
-            0xf1 => {
-                self.a = self.memory[self.sp + 1];
-                let psw: u8 = self.memory[self.sp];
-                self.condition_codes.z = (0x01 == (psw & 0x01)) as u8;
-                self.condition_codes.s = (0x02 == (psw & 0x02)) as u8;
-                self.condition_codes.p = (0x04 == (psw & 0x04)) as u8;
-                self.condition_codes.cy = (0x05 == (psw & 0x05)) as u8;
-                self.condition_codes.ac = (0x10 == (psw & 0x10)) as u8;
-                self.sp += 2;
-                self.pc += 1;
-            },
-            0x7b => {
-                self.a = self.e;
-                self.pc += 1;
-            },
-                        
-            
-           _ => { self.unimplemented_instruction(opcode); }
-
         }
+        Ok(cycles)
     }
     fn zsp_flags(&mut self, value: u8) {
         self.condition_codes.z = (value == 0) as u8;
         self.condition_codes.s = (0x80 == (value & 0x80)) as u8;
         self.condition_codes.p = self.parity(value);
     }
+    fn read_byte(&self, offset: u16) -> u8 {
+        self.memory.read(offset)
+    }
+    fn write_byte(&mut self, offset: u16, value: u8) {
+        self.memory.write(offset, value);
+    }
+    fn fetch_byte(&mut self) -> u8 {
+        let value: u8 = self.read_byte(self.pc);
+        self.pc = self.pc.wrapping_add(1);
+        value
+    }
+    fn fetch_word(&mut self) -> u16 {
+        let lo: u8 = self.fetch_byte();
+        let hi: u8 = self.fetch_byte();
+        self.merge_addr_pair(lo, hi)
+    }
     fn read_from_hl(&self) -> u8 {
-        let offset: u16 = ((self.h as u16) << 8) | (self.l as u16);
-        self.memory[offset as usize]
+        self.read_byte(self.get_pair(2))
     }
     fn write_to_hl(&mut self, value: u8) {
-        let offset: u16 = ((self.h as u16) << 8) | (self.l as u16);
-        self.memory[offset as usize] = value;
+        let offset: u16 = self.get_pair(2);
+        self.write_byte(offset, value);
+    }
+    /// Reads register `r` using the 3-bit encoding of the opcode table:
+    /// B, C, D, E, H, L, M (memory at HL), A.
+    fn get_reg(&self, r: u8) -> u8 {
+        match r {
+            0 => self.b,
+            1 => self.c,
+            2 => self.d,
+            3 => self.e,
+            4 => self.h,
+            5 => self.l,
+            6 => self.read_from_hl(),
+            _ => self.a,
+        }
+    }
+    fn set_reg(&mut self, r: u8, value: u8) {
+        match r {
+            0 => self.b = value,
+            1 => self.c = value,
+            2 => self.d = value,
+            3 => self.e = value,
+            4 => self.h = value,
+            5 => self.l = value,
+            6 => self.write_to_hl(value),
+            _ => self.a = value,
+        }
+    }
+    /// Reads register pair `rp` using the 2-bit encoding of the opcode table:
+    /// BC, DE, HL, SP.
+    fn get_pair(&self, rp: u8) -> u16 {
+        match rp & 0x03 {
+            0 => self.merge_addr_pair(self.c, self.b),
+            1 => self.merge_addr_pair(self.e, self.d),
+            2 => self.merge_addr_pair(self.l, self.h),
+            _ => self.sp,
+        }
     }
-    pub fn generate_interrupt(&mut self, interrupt_num: i32) {
-        let pc: u16 = self.pc as u16;
-        self.push_stack(((pc & 0xff00) >> 8) as u8, (pc & 0xff) as u8);
-        self.pc = (8 * interrupt_num) as usize;
+    fn set_pair(&mut self, rp: u8, value: u16) {
+        let hi: u8 = (value >> 8) as u8;
+        let lo: u8 = value as u8;
+        match rp & 0x03 {
+            0 => { self.b = hi; self.c = lo; },
+            1 => { self.d = hi; self.e = lo; },
+            2 => { self.h = hi; self.l = lo; },
+            _ => self.sp = value,
+        }
+    }
+    /// Evaluates the 3-bit condition field of Jcc/Ccc/Rcc:
+    /// NZ, Z, NC, C, PO, PE, P, M.
+    fn condition(&self, cc: u8) -> bool {
+        match cc {
+            0 => self.condition_codes.z == 0,
+            1 => self.condition_codes.z == 1,
+            2 => self.condition_codes.cy == 0,
+            3 => self.condition_codes.cy == 1,
+            4 => self.condition_codes.p == 0,
+            5 => self.condition_codes.p == 1,
+            6 => self.condition_codes.s == 0,
+            _ => self.condition_codes.s == 1,
+        }
+    }
+    fn add(&mut self, value: u8, carry: u8) {
+        let res: u16 = self.a as u16 + value as u16 + carry as u16;
+        self.condition_codes.cy = (res > 0xff) as u8;
+        self.condition_codes.ac = ((self.a & 0x0f) + (value & 0x0f) + carry > 0x0f) as u8;
+        self.a = res as u8;
+        let a: u8 = self.a;
+        self.zsp_flags(a);
+    }
+    /// Subtracts `value` and `borrow` from A, setting flags, and returns the result
+    /// without storing it so CMP/CPI can share it.
+    fn sub(&mut self, value: u8, borrow: u8) -> u8 {
+        let res: u16 = (self.a as u16).wrapping_sub(value as u16).wrapping_sub(borrow as u16);
+        self.condition_codes.cy = (res > 0xff) as u8;
+        // The ALU subtracts by adding the complement, so the half carry is the
+        // carry out of bit 3 of A + !value + !borrow.
+        self.condition_codes.ac = ((self.a & 0x0f) + (!value & 0x0f) + (borrow ^ 1) > 0x0f) as u8;
+        self.zsp_flags(res as u8);
+        res as u8
+    }
+    fn call(&mut self, addr: u16) {
+        let ret_addr: u16 = self.pc;
+        self.push_word(ret_addr);
+        self.pc = addr;
+    }
+    /// Requests RST `interrupt_num` from an external device.  Returns false and
+    /// leaves the CPU untouched if interrupts are disabled or an EI has not
+    /// taken effect yet; otherwise interrupts are disabled, as on the real CPU.
+    pub fn generate_interrupt(&mut self, interrupt_num: i32) -> bool {
+        if self.int_enable == 0 || self.ei_pending {
+            return false;
+        }
+        let pc: u16 = self.pc;
+        self.push_word(pc);
+        self.pc = (8 * interrupt_num) as u16;
         self.int_enable = 0;
-        println!("Interrupt!");
+        self.halted = false;
+        self.cycles += INTERRUPT_CYCLES as u64;
+        true
     }
     fn merge_addr_pair(&self, lo: u8, hi: u8) -> u16 {
-        let mut new_addr: u16 = (hi as u16) << 8;
-        new_addr = new_addr | (lo as u16);
-        new_addr
-    }
-    fn push_stack(&mut self, hi: u8, lo: u8) {
-        self.memory[self.sp - 1] = hi;
-        self.memory[self.sp - 2] = lo;
-        self.sp -= 2;
-    }
-    fn floating_point_add(&self, x: u8, y:u8) -> u8 {
-        // We need to wrap these to allow overflows
-        (Wrapping(x) + Wrapping(y)).0
-    }
-    fn floating_point_sub(&self, x: u8, y: u8) -> u8 {
-        // We need to wrap these to allow overflows
-        (Wrapping(x) - Wrapping(y)).0
-    }
-    pub fn parity(&mut self, mut res: u8) -> u8 {
-        let mut p: i32 = 0;
-        for x in 0..8 {
-            if (x & 0x1) == 1 {
-                p += 1;
-            }
-            res = res >> 1;
+        ((hi as u16) << 8) | (lo as u16)
+    }
+    fn push_word(&mut self, value: u16) {
+        let sp: u16 = self.sp;
+        self.write_byte(sp.wrapping_sub(1), (value >> 8) as u8);
+        self.write_byte(sp.wrapping_sub(2), value as u8);
+        self.sp = sp.wrapping_sub(2);
+    }
+    fn pop_word(&mut self) -> Result<u16, VmError> {
+        let sp: u16 = self.sp;
+        if sp > 0xfffe {
+            // The high byte would come from 0x0000; see VmError::StackUnderflow
+            return Err(VmError::StackUnderflow { pc: self.pc, sp });
         }
-        (0 == (p & 0x1)) as u8
+        let value: u16 = self.merge_addr_pair(self.read_byte(sp),
+                                              self.read_byte(sp.wrapping_add(1)));
+        self.sp = sp.wrapping_add(2);
+        Ok(value)
+    }
+    pub fn parity(&self, res: u8) -> u8 {
+        (res.count_ones() & 1 == 0) as u8
+    }
+    fn ana(&mut self, value: u8) {
+        // ANA sets the half carry from bit 3 of the operands
+        let ac: u8 = ((self.a | value) & 0x08 != 0) as u8;
+        self.a &= value;
+        self.logic_flags_a();
+        self.condition_codes.ac = ac;
     }
     fn logic_flags_a(&mut self) {
         self.condition_codes.cy = 0;
         self.condition_codes.ac = 0;
-        self.condition_codes.z = (self.a == 0) as u8;
-        self.condition_codes.s = (0x80 == (self.a & 0x80)) as u8;
         let a: u8 = self.a;
-        self.condition_codes.p = self.parity(a);
-    }
-    fn unimplemented_instruction(&self, opcode: u8) {
-        panic!("Reached unimplemented opcode: 0x{}", format!("{:01$x}", opcode, 2));
+        self.zsp_flags(a);
     }
-   pub fn new() -> Vm {
+   pub fn with_io(io: P) -> Vm<P> {
        Vm {
-           memory: vec![0; 64000], ..Default::default()
+           a: 0, b: 0, c: 0, d: 0, e: 0, h: 0, l: 0,
+           sp: 0, pc: 0,
+           int_enable: 0,
+           ei_pending: false,
+           halted: false,
+           strict_opcodes: false,
+           cycles: 0,
+           memory: Box::new(FlatRam::new()),
+           condition_codes: ConditionCodes::default(),
+           io,
        }
    }
-   pub fn load_rom(&mut self, mut rom: Vec<u8>) {
-       self.memory[..rom.len()].clone_from_slice(&mut rom);
-       println!("size: {}", self.memory.len());
+   pub fn load_rom(&mut self, rom: Vec<u8>) {
+       self.memory.load(0x0000, &rom);
    }
     pub fn print_debug(&self) {
-        println!("{} => {}", format!("{:01$x}", self.pc, 4), 
-             format!("{:01$x}", self.memory[self.pc], 2));
+        println!("{} => {}", format_u16(self.pc), format(self.read_byte(self.pc)));
     }
     pub fn run(&mut self) {
         loop {
-            self.run_current_opcode();
+            if let Err(error) = self.run_current_opcode() {
+                println!("\r{}", error);
+                return;
+            }
             println!("\r{:#?}", self);
             thread::sleep(Duration::from_millis(300));
         }
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A CPU with `program` at 0 and the stack below 0x1000.
+    fn with_program(program: &[u8]) -> Vm {
+        let mut vm = Vm::new();
+        vm.memory.load(0, program);
+        vm.sp = 0x1000;
+        vm
+    }
+
+    fn step(vm: &mut Vm) -> u32 {
+        vm.run_current_opcode().unwrap().cycles
+    }
+
+    /// Makes condition `cc` (NZ, Z, NC, C, PO, PE, P, M) hold or not.
+    fn set_condition(vm: &mut Vm, cc: u8, holds: bool) {
+        let flag: u8 = (holds == (cc & 1 == 1)) as u8;
+        match cc >> 1 {
+            0 => vm.condition_codes.z = flag,
+            1 => vm.condition_codes.cy = flag,
+            2 => vm.condition_codes.p = flag,
+            _ => vm.condition_codes.s = flag,
+        }
+    }
+
+    #[test]
+    fn add_and_subtract_carry_and_borrow() {
+        // (opcode with B as operand, A, B, CY before, A after, CY after)
+        let cases: [(u8, u8, u8, u8, u8, u8); 18] = [
+            (0x80, 0x0f, 0x01, 0, 0x10, 0),     // ADD
+            (0x80, 0xff, 0x01, 0, 0x00, 1),
+            (0x80, 0x80, 0x80, 0, 0x00, 1),
+            (0x80, 0x01, 0x01, 1, 0x02, 0),     // ignores CY
+            (0x88, 0xfe, 0x01, 0, 0xff, 0),     // ADC
+            (0x88, 0xff, 0x00, 1, 0x00, 1),
+            (0x88, 0x7f, 0x80, 1, 0x00, 1),
+            (0x88, 0x01, 0x01, 1, 0x03, 0),
+            (0x90, 0x05, 0x03, 0, 0x02, 0),     // SUB
+            (0x90, 0x03, 0x05, 0, 0xfe, 1),
+            (0x90, 0x00, 0x00, 1, 0x00, 0),     // ignores CY
+            (0x98, 0x05, 0x04, 1, 0x00, 0),     // SBB
+            (0x98, 0x05, 0x05, 1, 0xff, 1),
+            (0x98, 0x00, 0xff, 1, 0x00, 1),
+            (0x98, 0x05, 0x05, 0, 0x00, 0),
+            (0xb8, 0x05, 0x06, 0, 0x05, 1),     // CMP leaves A alone
+            (0xb8, 0x06, 0x05, 1, 0x06, 0),
+            (0xb8, 0x05, 0x05, 0, 0x05, 0),
+        ];
+        for &(opcode, a, b, cy, result, carry) in cases.iter() {
+            let mut vm = with_program(&[opcode]);
+            vm.a = a;
+            vm.b = b;
+            vm.condition_codes.cy = cy;
+            step(&mut vm);
+            assert_eq!((vm.a, vm.condition_codes.cy), (result, carry),
+                       "opcode {:02x} with A {:02x}, B {:02x}, CY {}", opcode, a, b, cy);
+            let alu: u8 = if opcode == 0xb8 { a.wrapping_sub(b) } else { result };
+            assert_eq!(vm.condition_codes.z, (alu == 0) as u8);
+        }
+    }
+
+    #[test]
+    fn increment_and_decrement_leave_carry_alone() {
+        // (opcode, B before, B after)
+        let cases: [(u8, u8, u8); 4] = [
+            (0x04, 0xff, 0x00),       // INR B
+            (0x04, 0x7f, 0x80),
+            (0x05, 0x00, 0xff),       // DCR B
+            (0x05, 0x01, 0x00),
+        ];
+        for &(opcode, before, after) in cases.iter() {
+            for cy in 0..2 {
+                let mut vm = with_program(&[opcode]);
+                vm.b = before;
+                vm.condition_codes.cy = cy;
+                step(&mut vm);
+                assert_eq!(vm.b, after);
+                assert_eq!(vm.condition_codes.cy, cy, "opcode {:02x} on {:02x}", opcode, before);
+                assert_eq!(vm.condition_codes.z, (after == 0) as u8);
+                assert_eq!(vm.condition_codes.s, after >> 7);
+            }
+        }
+    }
+
+    #[test]
+    fn conditional_jumps_calls_and_returns_take_their_time() {
+        for cc in 0..8u8 {
+            for &taken in [false, true].iter() {
+                // Jcc 0x1234: 10 T-states either way
+                let mut vm = with_program(&[0xc2 | cc << 3, 0x34, 0x12]);
+                set_condition(&mut vm, cc, taken);
+                assert_eq!(step(&mut vm), 10);
+                assert_eq!(vm.pc, if taken { 0x1234 } else { 3 });
+
+                // Ccc 0x1234: 17 taken, 11 not
+                let mut vm = with_program(&[0xc4 | cc << 3, 0x34, 0x12]);
+                set_condition(&mut vm, cc, taken);
+                assert_eq!(step(&mut vm), if taken { 17 } else { 11 });
+                assert_eq!((vm.pc, vm.sp), if taken { (0x1234, 0x0ffe) } else { (3, 0x1000) });
+
+                // Rcc to 0x0200: 11 taken, 5 not
+                let mut vm = with_program(&[0xc0 | cc << 3]);
+                vm.memory.load(0x1000, &[0x00, 0x02]);
+                set_condition(&mut vm, cc, taken);
+                assert_eq!(step(&mut vm), if taken { 11 } else { 5 });
+                assert_eq!((vm.pc, vm.sp), if taken { (0x0200, 0x1002) } else { (1, 0x1000) });
+            }
+        }
+    }
+
+    #[test]
+    fn auxiliary_carry() {
+        // (opcode with B as operand, A, B, AC after)
+        let cases: [(u8, u8, u8, u8); 15] = [
+            (0x80, 0x0f, 0x01, 1),    // ADD: carry out of bit 3
+            (0x80, 0x0e, 0x01, 0),
+            (0x80, 0x08, 0x08, 1),
+            (0x90, 0x05, 0x01, 1),    // SUB: carry out of bit 3 of A + !B + 1
+            (0x90, 0x10, 0x01, 0),
+            (0x90, 0x00, 0x00, 1),
+            (0x04, 0x00, 0x0f, 1),    // INR B: the low digit wrapped to 0
+            (0x04, 0x00, 0x0e, 0),
+            (0x04, 0x00, 0xff, 1),
+            (0x05, 0x00, 0x11, 1),    // DCR B: set unless the low digit wrapped to f
+            (0x05, 0x00, 0x10, 0),
+            (0x05, 0x00, 0x00, 0),
+            (0xa0, 0x08, 0x00, 1),    // ANA: bit 3 of either operand
+            (0xa0, 0x07, 0x07, 0),
+            (0xa0, 0xf0, 0x08, 1),
+        ];
+        for &(opcode, a, b, ac) in cases.iter() {
+            let mut vm = with_program(&[opcode]);
+            vm.a = a;
+            vm.b = b;
+            vm.condition_codes.ac = ac ^ 1;
+            step(&mut vm);
+            assert_eq!(vm.condition_codes.ac, ac, "opcode {:02x} with A {:02x}, B {:02x}", opcode, a, b);
+        }
+    }
+
+    #[test]
+    fn decimal_adjust() {
+        // ADI then DAA: (A, addend, A after, CY after), as BCD sums
+        let cases: [(u8, u8, u8, u8); 7] = [
+            (0x12, 0x34, 0x46, 0),    // No correction
+            (0x19, 0x28, 0x47, 0),    // Low digit: AC set
+            (0x38, 0x45, 0x83, 0),    // Low digit: above 9
+            (0x90, 0x20, 0x10, 1),    // High digit: above 9
+            (0x80, 0x90, 0x70, 1),    // High digit: CY set
+            (0x99, 0x99, 0x98, 1),    // Both, from AC and CY
+            (0x99, 0x01, 0x00, 1),    // Both, the low correction carrying into a high 9
+        ];
+        for &(a, addend, result, carry) in cases.iter() {
+            let mut vm = with_program(&[0xc6, addend, 0x27]);
+            vm.a = a;
+            step(&mut vm);
+            assert_eq!(step(&mut vm), 4);
+            assert_eq!((vm.a, vm.condition_codes.cy), (result, carry), "{:02x} + {:02x}", a, addend);
+            assert_eq!(vm.condition_codes.z, (result == 0) as u8);
+        }
+    }
+
+    #[test]
+    fn push_and_pop_psw_round_trip_every_flag() {
+        for flags in 0..32u8 {
+            // PUSH PSW; XRA A; MVI A,0; POP PSW
+            let mut vm = with_program(&[0xf5, 0xaf, 0x3e, 0x00, 0xf1]);
+            vm.a = 0x5a;
+            vm.condition_codes = ConditionCodes {
+                s: flags & 1, z: flags >> 1 & 1, ac: flags >> 2 & 1, p: flags >> 3 & 1, cy: flags >> 4 & 1, pad: 0,
+            };
+            step(&mut vm);
+            let psw: u8 = vm.memory.read(0x0ffe);
+            assert_eq!(vm.memory.read(0x0fff), 0x5a);
+            // Bit 1 reads as 1, bits 3 and 5 as 0
+            assert_eq!(psw & 0x2a, 0x02, "flags {:05b} pushed as {:08b}", flags, psw);
+            assert_eq!(psw, vm.condition_codes.to_psw());
+            step(&mut vm);
+            step(&mut vm);
+            step(&mut vm);
+            assert_eq!(vm.a, 0x5a);
+            assert_eq!(vm.condition_codes.to_psw(), psw);
+            let cc = &vm.condition_codes;
+            assert_eq!([cc.s, cc.z, cc.ac, cc.p, cc.cy],
+                       [flags & 1, flags >> 1 & 1, flags >> 2 & 1, flags >> 3 & 1, flags >> 4 & 1]);
+        }
+    }
+
+    #[test]
+    fn popped_flags_ignore_the_fixed_bits() {
+        for psw in 0..=255u8 {
+            let cc: ConditionCodes = ConditionCodes::from_psw(psw);
+            assert_eq!(cc.to_psw(), psw & !0x2a | 0x02, "{:08b}", psw);
+        }
+    }
+
+    #[test]
+    fn unimplemented_opcodes_only_when_strict() {
+        let mut vm = with_program(&[0x08, 0x08]);
+        assert_eq!(step(&mut vm), 4);
+        vm.strict_opcodes = true;
+        assert_eq!(vm.run_current_opcode(), Err(VmError::UnimplementedOpcode { pc: 1, opcode: 0x08 }));
+        assert_eq!((vm.pc, vm.cycles), (1, 4));
+    }
+
+    #[test]
+    fn unmapped_ports() {
+        struct Deaf;
+        impl PortIo for Deaf {
+            fn input(&mut self, _port: u8) -> Option<u8> {
+                None
+            }
+            fn output(&mut self, _port: u8, _value: u8) -> bool {
+                false
+            }
+        }
+        let mut vm = Vm::with_io(Deaf);
+        vm.memory.load(0, &[0xdb, 0x01, 0xd3, 0x02]);
+        assert_eq!(vm.run_current_opcode(), Err(VmError::UnmappedPort { pc: 0, port: 0x01 }));
+        vm.pc = 2;
+        assert_eq!(vm.run_current_opcode(), Err(VmError::UnmappedPort { pc: 2, port: 0x02 }));
+        assert_eq!(vm.pc, 2);
+    }
+
+    #[test]
+    fn stack_underflow_only_past_the_top_of_memory() {
+        // POP B; RET
+        let mut vm = with_program(&[0xc1, 0xc9]);
+        vm.memory.load(0xfffe, &[0x34, 0x12]);
+        vm.sp = 0xfffe;
+        step(&mut vm);
+        assert_eq!((vm.b, vm.c, vm.sp), (0x12, 0x34, 0x0000));
+        vm.sp = 0xffff;
+        assert_eq!(vm.run_current_opcode(), Err(VmError::StackUnderflow { pc: 1, sp: 0xffff }));
+        assert_eq!((vm.pc, vm.sp), (1, 0xffff));
+        // Conditional returns only check when taken
+        let mut vm = with_program(&[0xc0, 0xc8]);
+        vm.sp = 0xffff;
+        vm.condition_codes.z = 1;
+        assert_eq!(step(&mut vm), 5);
+        assert_eq!(vm.run_current_opcode(), Err(VmError::StackUnderflow { pc: 1, sp: 0xffff }));
+    }
+
+    #[test]
+    fn halted_until_an_interrupt() {
+        // EI; HLT
+        let mut vm = with_program(&[0xfb, 0x76]);
+        step(&mut vm);
+        step(&mut vm);
+        assert_eq!(vm.run_current_opcode(), Err(VmError::Halted { pc: 2 }));
+        assert_eq!(vm.run_cycles(100), Ok(0));
+        assert!(vm.generate_interrupt(1));
+        assert!(!vm.halted);
+        assert_eq!((vm.pc, vm.memory.read(0x0ffe)), (0x0008, 0x02));
+    }
+
+    #[test]
+    fn errors_move_to_the_failing_instruction() {
+        let error: VmError = VmError::StackUnderflow { pc: 5, sp: 0xffff };
+        assert_eq!(error.at(9), VmError::StackUnderflow { pc: 9, sp: 0xffff });
+        assert_eq!(error.to_string(), "stack underflow (sp 0xffff) at 0x0005");
+        assert_eq!(VmError::Halted { pc: 3 }.to_string(), "halted at 0x0003");
+        assert_eq!(VmError::UnmappedPort { pc: 3, port: 9 }.to_string(), "unmapped port 0x09 at 0x0003");
+        assert_eq!(VmError::UnimplementedOpcode { pc: 3, opcode: 0xcb }.to_string(),
+                   "unimplemented opcode 0xcb at 0x0003");
+    }
+}
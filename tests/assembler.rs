@@ -0,0 +1,92 @@
+extern crate rust8080;
+
+use std::fs::File;
+use std::io::prelude::*;
+use rust8080::assemble::assemble;
+use rust8080::disassemble::flow;
+use rust8080::disassemble::flow::Disassembly;
+use rust8080::symbols::SymbolTable;
+
+fn invaders_rom() -> Vec<u8> {
+    let mut rom: Vec<u8> = Vec::new();
+    File::open("roms/invaders.rom").unwrap().read_to_end(&mut rom).unwrap();
+    rom
+}
+
+#[test]
+fn round_trips_invaders() {
+    let rom: Vec<u8> = invaders_rom();
+    let listing: String = Disassembly::analyze(&rom, 0x0000, &flow::DEFAULT_ENTRIES).listing();
+    let assembly = assemble(&listing).unwrap();
+    assert_eq!(assembly.origin, 0x0000);
+    assert!(assembly.bytes == rom, "assembled listing differs from invaders.rom");
+}
+
+#[test]
+fn round_trips_with_symbols() {
+    let rom: Vec<u8> = invaders_rom();
+    let symbols = SymbolTable::parse("\
+20EB  numCoins     byte   ; credits
+1A32  BlockCopy    code
+1A33  midCopy
+1E00  charset      byte*16
+0100  words        word*3
+1A5C  ClearScreen
+").unwrap();
+    let disassembly = Disassembly::analyze_with_symbols(&rom, 0x0000, &flow::DEFAULT_ENTRIES, &symbols);
+    let assembly = assemble(&disassembly.listing()).unwrap();
+    assert!(assembly.bytes == rom, "assembled listing differs from invaders.rom");
+    assert_eq!(assembly.labels["numCoins"], 0x20eb);
+    assert_eq!(assembly.labels["ClearScreen"], 0x1a5c);
+}
+
+#[test]
+fn directives_and_expressions() {
+    let assembly = assemble("
+        ORG     $100
+SIZE    EQU     END_ - START    ; forward reference
+START:  LXI     H,TABLE+2
+        MVI     A,SIZE*2
+        RST     (1 << 1) + 1
+        JMP     $
+TABLE:  DW      START,$1234
+        DB      'A',\"hi;\",0FFH,-1,count
+        DS      2
+END_:   DB      low
+count   EQU     3
+low     EQU     SIZE & 0xff
+").unwrap();
+    assert_eq!(assembly.origin, 0x100);
+    assert_eq!(assembly.bytes, vec![
+        0x21, 0x0b, 0x01,         // LXI H,TABLE+2
+        0x3e, 0x2c,               // MVI A,SIZE*2
+        0xdf,                     // RST 3
+        0xc3, 0x06, 0x01,         // JMP $
+        0x00, 0x01, 0x34, 0x12,   // DW
+        0x41, 0x68, 0x69, 0x3b, 0xff, 0xff, 0x03,
+        0x00, 0x00,               // DS
+        0x16,
+    ]);
+    assert_eq!(assembly.labels["SIZE"], 0x16);
+}
+
+#[test]
+fn forward_equs_keep_their_own_dollar() {
+    let assembly = assemble("
+        ORG     100H
+X       EQU     $+Y
+Y       EQU     2
+        DW      X
+").unwrap();
+    assert_eq!(assembly.bytes, vec![0x02, 0x01]);
+}
+
+#[test]
+fn errors_name_the_line() {
+    let error = assemble("  NOP\n  MOV A,Q\n").unwrap_err();
+    assert_eq!(error.line, 2);
+    let error = assemble("  JMP nowhere\n").unwrap_err();
+    assert_eq!(error.to_string(), "line 1: undefined symbol `nowhere`");
+    let error = assemble("  MVI A,256\n").unwrap_err();
+    assert_eq!(error.line, 1);
+}
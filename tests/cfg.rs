@@ -0,0 +1,58 @@
+extern crate rust8080;
+
+use rust8080::assemble::assemble;
+use rust8080::disassemble::cfg::{CallKind, Cfg, EdgeKind};
+use rust8080::disassemble::flow::Disassembly;
+
+const PROGRAM: &str = "
+        ORG     0
+START:  CALL    COUNT
+        RST     1
+        LXI     H,START
+        PCHL
+        ORG     8
+VECTOR: EI
+        RET
+COUNT:  MVI     B,3
+LOOP:   DCR     B
+        JNZ     LOOP
+        RZ
+        JMP     VECTOR
+";
+
+#[test]
+fn blocks_calls_and_flags() {
+    let assembly = assemble(PROGRAM).unwrap();
+    let disassembly = Disassembly::analyze(&assembly.bytes, assembly.origin, &[0x0000]);
+    let cfg = Cfg::build(&disassembly);
+    let label = |name: &str| assembly.labels[name];
+
+    let starts: Vec<u16> = cfg.blocks.keys().cloned().collect();
+    assert_eq!(starts, vec![label("START"), label("VECTOR"), label("COUNT"), label("LOOP"),
+                            label("LOOP") + 4, label("LOOP") + 5]);
+    let main = &cfg.blocks[&label("START")];
+    assert!(main.indirect);
+    assert!(main.successors.is_empty());
+    let loop_block = &cfg.blocks[&label("LOOP")];
+    assert_eq!(loop_block.successors[0].to, label("LOOP"));
+    assert_eq!(loop_block.successors[0].kind, EdgeKind::Branch);
+    assert_eq!(loop_block.successors[1].kind, EdgeKind::Fallthrough);
+    assert!(cfg.blocks[&(label("LOOP") + 4)].returns);
+    assert_eq!(cfg.indirect_jumps(), vec![label("START") + 7]);
+
+    let functions: Vec<u16> = cfg.functions.keys().cloned().collect();
+    assert_eq!(functions, vec![label("START"), label("VECTOR"), label("COUNT")]);
+    assert_eq!(cfg.functions[&label("VECTOR")].restart, Some(1));
+    assert!(cfg.functions[&label("START")].entry_point);
+    let calls: Vec<(u16, CallKind)> = cfg.functions[&label("START")].calls.iter()
+        .map(|call| (call.target, call.kind))
+        .collect();
+    assert_eq!(calls, vec![(label("COUNT"), CallKind::Call), (label("VECTOR"), CallKind::Restart)]);
+    let count = &cfg.functions[&label("COUNT")];
+    assert_eq!(count.blocks.len(), 4);
+    assert_eq!(count.calls[0].kind, CallKind::TailJump);
+
+    assert!(cfg.to_dot().contains("xlabel=\"PCHL\""));
+    assert!(cfg.call_graph_dot().contains("xlabel=\"RST 1\""));
+    assert!(cfg.to_json().contains("\"indirect_jumps\": [7]"));
+}
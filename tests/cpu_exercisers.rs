@@ -0,0 +1,127 @@
+// Runs the classic CP/M 8080 diagnostics against the CPU core.
+//
+// The .COM files are not vendored, so the tests that need them are ignored
+// by default: copy them into tests/roms (see tests/roms/README.md) and run
+// `cargo test --release -- --ignored`.  A missing file then fails the test.
+// tests/opcodes.rs checks the instruction set without them.
+extern crate rust8080;
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::PathBuf;
+use rust8080::vm::Vm;
+
+// CP/M loads programs here
+const TPA: u16 = 0x0100;
+// Entry point of the BDOS console calls
+const BDOS: u16 = 0x0005;
+
+fn load_com(name: &str) -> Vec<u8> {
+    let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    path.push("tests");
+    path.push("roms");
+    path.push(name);
+    let mut buffer: Vec<u8> = Vec::new();
+    match File::open(&path) {
+        Ok(mut file) => file.read_to_end(&mut buffer).unwrap(),
+        Err(error) => panic!("{}: {} (see tests/roms/README.md)", path.display(), error),
+    };
+    buffer
+}
+
+/// Runs a CP/M program until it warm boots (jumps to 0x0000) and returns
+/// everything it printed through BDOS functions 2 and 9.  Fails if the
+/// program is still going after `budget` instructions.
+fn run_com(program: &[u8], budget: u64) -> String {
+    let mut vm = Vm::new();
+    vm.memory.load(TPA, program);
+    // Warm boot halts, the BDOS entry returns straight away after being trapped
+    // and 0x0006 holds the top of the TPA that programs use as their stack.
+    vm.memory.load(0x0000, &[0x76]);
+    vm.memory.load(BDOS, &[0xc9, 0x00, 0xf0]);
+    vm.sp = 0xf000;
+    vm.pc = TPA;
+    let mut output = String::new();
+    for _ in 0..budget {
+        if vm.pc == BDOS {
+            bdos(&vm, &mut output);
+        }
+        if vm.pc == 0x0000 {
+            return output;
+        }
+        vm.run_current_opcode().unwrap();
+    }
+    panic!("still running at 0x{:04x} after {} instructions, output so far:\n{}", vm.pc, budget, output);
+}
+
+fn bdos(vm: &Vm, output: &mut String) {
+    match vm.c {
+        2 => output.push(vm.e as char),
+        9 => {
+            let mut addr: u16 = ((vm.d as u16) << 8) | vm.e as u16;
+            while vm.memory.read(addr) != b'$' {
+                output.push(vm.memory.read(addr) as char);
+                addr += 1;
+            }
+        },
+        _ => {},
+    }
+}
+
+#[test]
+fn bdos_trap_and_bcd() {
+    // MVI A,$09 / ADI $01 / DAA / CPI $10 / JNZ fail / STC / PUSH PSW / POP B /
+    // MOV A,C / CPI $57 / JNZ fail, then print "BCD OK" with function 9 and
+    // "!" with function 2, or "BCD BAD" from fail.
+    let program: [u8; 63] = [
+        0x3e, 0x09, 0xc6, 0x01, 0x27, 0xfe, 0x10, 0xc2, 0x25, 0x01, 0x37, 0xf5,
+        0xc1, 0x79, 0xfe, 0x57, 0xc2, 0x25, 0x01, 0x11, 0x30, 0x01, 0x0e, 0x09,
+        0xcd, 0x05, 0x00, 0x1e, 0x21, 0x0e, 0x02, 0xcd, 0x05, 0x00, 0xc3, 0x00,
+        0x00, 0x11, 0x37, 0x01, 0x0e, 0x09, 0xcd, 0x05, 0x00, 0xc3, 0x00, 0x00,
+        0x42, 0x43, 0x44, 0x20, 0x4f, 0x4b, 0x24, 0x42, 0x43, 0x44, 0x20, 0x42,
+        0x41, 0x44, 0x24,
+    ];
+    assert_eq!(run_com(&program, 1_000), "BCD OK!");
+}
+
+#[test]
+#[should_panic(expected = "still running at 0x0100 after 1000 instructions")]
+fn runaway_programs_fail() {
+    // JMP 0100h
+    run_com(&[0xc3, 0x00, 0x01], 1_000);
+}
+
+// Budgets below are several times what each program needs
+
+#[test]
+#[ignore = "needs TST8080.COM in tests/roms"]
+fn tst8080() {
+    let program: Vec<u8> = load_com("TST8080.COM");
+    let output = run_com(&program, 1_000_000);
+    assert!(output.contains("CPU IS OPERATIONAL"), "{}", output);
+}
+
+#[test]
+#[ignore = "needs 8080PRE.COM in tests/roms"]
+fn pre8080() {
+    let program: Vec<u8> = load_com("8080PRE.COM");
+    let output = run_com(&program, 10_000_000);
+    assert!(output.contains("8080 Preliminary tests complete"), "{}", output);
+}
+
+#[test]
+#[ignore = "needs CPUTEST.COM in tests/roms"]
+fn cputest() {
+    let program: Vec<u8> = load_com("CPUTEST.COM");
+    let output = run_com(&program, 1_000_000_000);
+    assert!(output.contains("CPU TESTS OK"), "{}", output);
+}
+
+#[test]
+#[ignore = "needs 8080EXM.COM in tests/roms, and minutes in release mode"]
+fn exm8080() {
+    let program: Vec<u8> = load_com("8080EXM.COM");
+    let output = run_com(&program, 10_000_000_000);
+    assert!(!output.contains("ERROR"), "{}", output);
+    assert!(output.contains("Tests complete"), "{}", output);
+}
@@ -0,0 +1,142 @@
+extern crate rust8080;
+
+use std::fs::File;
+use std::io::prelude::*;
+use rust8080::assemble::assemble;
+use rust8080::debugger::{Debugger, Stop, WatchKind};
+use rust8080::debugger::expr::Expr;
+use rust8080::debugger::repl;
+use rust8080::invaders::Invaders;
+use rust8080::vm::{Vm, VmError};
+
+const PROGRAM: &str = "
+        ORG 0
+        LXI SP,0100H
+START:  MVI A,3
+        CALL SUB
+        STA 0080H
+LOOP:   DCR A
+        JNZ LOOP
+        HLT
+SUB:    MVI B,1
+        CALL LEAF
+        RET
+LEAF:   INR B
+        PUSH PSW
+        LDA 0080H
+        POP PSW
+        RET
+";
+
+fn debugger() -> Debugger<Vm> {
+    let assembly = assemble(PROGRAM).unwrap();
+    let mut vm = Vm::new();
+    vm.memory.load(0, &assembly.bytes);
+    Debugger::new(vm, assembly.symbol_table())
+}
+
+#[test]
+fn steps_over_and_out_of_calls() {
+    let mut debugger = debugger();
+    assert_eq!(debugger.step(), Stop::Done);
+    assert_eq!(debugger.step(), Stop::Done);
+    assert_eq!(debugger.vm().pc, 0x0005);
+    // CALL SUB runs SUB and LEAF
+    assert_eq!(debugger.step_over(), Stop::Done);
+    assert_eq!(debugger.vm().pc, 0x0008);
+    assert_eq!(debugger.vm().b, 2);
+    assert_eq!(debugger.depth, 0);
+
+    let mut debugger = self::debugger();
+    debugger.step();
+    debugger.step();
+    debugger.step();
+    assert_eq!(debugger.vm().pc, 0x0010);
+    assert_eq!(debugger.depth, 1);
+    debugger.step();
+    debugger.step();
+    assert_eq!(debugger.vm().pc, 0x0016);
+    assert_eq!(debugger.step_out(), Stop::Done);
+    assert_eq!(debugger.vm().pc, 0x0015);
+    assert_eq!(debugger.step_out(), Stop::Done);
+    assert_eq!(debugger.vm().pc, 0x0008);
+    assert_eq!(debugger.depth, 0);
+}
+
+#[test]
+fn breakpoints_and_conditions() {
+    let mut debugger = debugger();
+    let condition = Expr::parse("a == 1 && !z", &debugger.symbols).unwrap();
+    let id: usize = debugger.add_breakpoint(0x000b, Some(condition));
+    assert_eq!(debugger.cont(), Stop::Breakpoint { id, addr: 0x000b });
+    assert_eq!(debugger.vm().a, 1);
+    assert_eq!(debugger.breakpoints()[0].hits, 1);
+    // Nothing else stops it before HLT
+    assert_eq!(debugger.cont(), Stop::Error(VmError::Halted { pc: 0x0010 }));
+
+    let mut debugger = self::debugger();
+    let id: usize = debugger.add_breakpoint(0x0016, None);
+    debugger.set_enabled(id, false);
+    let other: usize = debugger.add_breakpoint(0x000b, None);
+    assert_eq!(debugger.cont(), Stop::Breakpoint { id: other, addr: 0x000b });
+    assert!(debugger.remove(other));
+    assert!(!debugger.remove(other));
+}
+
+#[test]
+fn watchpoints_see_data_accesses() {
+    let mut debugger = debugger();
+    let read: usize = debugger.add_watchpoint(0x0080, 0x0080, WatchKind::Read);
+    let write: usize = debugger.add_watchpoint(0x0080, 0x0081, WatchKind::Write);
+    // LDA in LEAF reads before STA writes
+    match debugger.cont() {
+        Stop::Watchpoint { id, pc, access } => {
+            assert_eq!((id, pc, access.addr, access.write), (read, 0x0018, 0x0080, false));
+        },
+        stop => panic!("unexpected stop: {:?}", stop),
+    }
+    match debugger.cont() {
+        Stop::Watchpoint { id, pc, access } => {
+            assert_eq!((id, pc, access.addr, access.old, access.value), (write, 0x0008, 0x0080, 0x00, 0x03));
+        },
+        stop => panic!("unexpected stop: {:?}", stop),
+    }
+    // Opcode fetches never count as reads
+    let mut debugger = self::debugger();
+    debugger.add_watchpoint(0x0000, 0x001c, WatchKind::Read);
+    assert_eq!(debugger.cont(), Stop::Error(VmError::Halted { pc: 0x0010 }));
+}
+
+#[test]
+fn runs_until_interrupt() {
+    let mut rom: Vec<u8> = Vec::new();
+    File::open("roms/invaders.rom").unwrap().read_to_end(&mut rom).unwrap();
+    let mut debugger = Debugger::new(Invaders::new(&rom), Default::default());
+    match debugger.run_until_interrupt() {
+        Stop::Interrupt { vector } => assert_eq!(debugger.vm().pc, 8 * vector as u16),
+        stop => panic!("unexpected stop: {:?}", stop),
+    }
+    assert_eq!(debugger.vm().int_enable, 0);
+    // The handler returns to where the interrupt struck
+    let depth: i64 = debugger.depth;
+    assert_eq!(debugger.step_out(), Stop::Done);
+    assert_eq!(debugger.depth, depth - 1);
+    assert_eq!(debugger.vm().int_enable, 1);
+}
+
+#[test]
+fn repl_commands() {
+    let mut debugger = debugger();
+    let mut output: Vec<u8> = Vec::new();
+    let mut input: &[u8] = b"b LEAF if b == 1\nc\np hl + [0x0002]\nset hl 0x1234\nregs\nq\n";
+    repl::run(&mut debugger, &mut input, &mut output).unwrap();
+    let output: String = String::from_utf8(output).unwrap();
+    assert!(output.contains("breakpoint 1 at 0x0016 <LEAF>\n=> 0x0016 <LEAF>  INR     B"), "{}", output);
+    assert!(output.contains("0x1 (1)"), "{}", output);
+    assert!(output.contains("hl 0x1234"), "{}", output);
+    assert_eq!(repl::execute(&mut debugger, "frobnicate"), Err("unknown command `frobnicate`, try help".to_string()));
+    // Lengths are bounded by the address space
+    assert_eq!(repl::execute(&mut debugger, "x 0 65536").map(|text| text.lines().count()), Ok(0x1000));
+    assert!(repl::execute(&mut debugger, "x 0 99999999999").is_err());
+    assert!(repl::execute(&mut debugger, "dis 0 99999999999").is_err());
+}
@@ -0,0 +1,188 @@
+extern crate rust8080;
+
+use std::fs::File;
+use std::io::prelude::*;
+use rust8080::assemble::assemble;
+use rust8080::decompile::Decompiler;
+use rust8080::disassemble::cfg::Cfg;
+use rust8080::disassemble::flow;
+use rust8080::disassemble::flow::Disassembly;
+use rust8080::symbols::SymbolTable;
+
+const PROGRAM: &str = "
+        ORG     0
+START:  LXI     H,BUFFER
+        MVI     B,4
+FILL:   MVI     M,0
+        INX     H
+        DCR     B
+        JNZ     FILL
+        LDA     COUNT
+        CPI     3
+        JNZ     SKIP
+        XRA     A
+        STA     COUNT
+SKIP:   CALL    WAIT
+        JMP     START
+WAIT:   LDA     FLAG
+        ANA     A
+        JZ      WAIT
+        RET
+BUFFER  EQU     $2000
+COUNT   EQU     $2004
+FLAG    EQU     $2005
+";
+
+const SYMBOLS: &str = "
+0000  main     code  ; clears the buffer forever
+001E  wait
+2000  buffer   byte*4
+2004  count    byte
+2005  flag     byte
+";
+
+#[test]
+fn structures_loops_and_ifs() {
+    let assembly = assemble(PROGRAM).unwrap();
+    let symbols = SymbolTable::parse(SYMBOLS).unwrap();
+    let disassembly = Disassembly::analyze_with_symbols(&assembly.bytes, 0, &[0], &symbols);
+    let cfg = Cfg::build(&disassembly);
+    let decompiler = Decompiler::new(&cfg);
+    assert_eq!(decompiler.program(), "\
+/* entry point, RST 0 vector, clears the buffer forever */
+void main(void)
+{
+    for (;;) {
+        hl = &buffer;
+        b = 4;
+        do {
+            mem[hl] = 0;
+            hl++;
+            b--;
+        } while (b != 0);
+        a = count;
+        if (a == 3) {
+            a = 0;
+            count = a;
+        }
+        wait();
+    }
+}
+
+void wait(void)
+{
+    do {
+        a = flag;
+    } while (a == 0);
+    return;
+}
+");
+}
+
+#[test]
+fn decompiles_every_invaders_function() {
+    let mut rom: Vec<u8> = Vec::new();
+    File::open("roms/invaders.rom").unwrap().read_to_end(&mut rom).unwrap();
+    let disassembly = Disassembly::analyze(&rom, 0, &flow::DEFAULT_ENTRIES);
+    let cfg = Cfg::build(&disassembly);
+    let decompiler = Decompiler::new(&cfg);
+    for &entry in cfg.functions.keys() {
+        let text: String = decompiler.function(entry);
+        assert!(text.contains(&format!("void {}(void)", cfg.name(entry))));
+        assert_eq!(text.matches('{').count(), text.matches('}').count());
+    }
+}
+
+fn decompile(source: &str) -> String {
+    let assembly = assemble(source).unwrap();
+    let disassembly = Disassembly::analyze_with_symbols(&assembly.bytes, 0, &[0], &assembly.symbol_table());
+    let cfg = Cfg::build(&disassembly);
+    Decompiler::new(&cfg).program()
+}
+
+#[test]
+fn jumps_to_a_do_while_header_skip_the_test() {
+    let text: String = decompile("
+        ORG     0
+HEAD:   DCR     B
+        JZ      HEAD
+        DCR     C
+        JNZ     HEAD
+        RET
+");
+    // continue would test c first
+    assert_eq!(text, "\
+/* entry point, RST 0 vector */
+void HEAD(void)
+{
+HEAD:
+    do {
+        b--;
+        if (b == 0) goto HEAD;
+        c--;
+    } while (c != 0);
+    return;
+}
+");
+}
+
+#[test]
+fn falling_into_a_function_is_a_tail_call() {
+    let text: String = decompile("
+        ORG     0
+START:  CALL    TAIL
+        MVI     A,1
+TAIL:   INR     A
+        RET
+");
+    assert_eq!(text, "\
+/* entry point, RST 0 vector */
+void START(void)
+{
+    TAIL();
+    a = 1;
+    TAIL();
+    return;
+}
+
+void TAIL(void)
+{
+    a++;
+    return;
+}
+");
+}
+
+#[test]
+fn conditional_tail_calls_are_braced() {
+    let text: String = decompile("
+        ORG     0
+START:  CALL    TAIL
+        DCR     B
+        JZ      TAIL
+        MVI     A,1
+        RET
+TAIL:   INR     A
+        RET
+");
+    assert_eq!(text, "\
+/* entry point, RST 0 vector */
+void START(void)
+{
+    TAIL();
+    b--;
+    if (b == 0) {
+        TAIL();
+        return;
+    }
+    a = 1;
+    return;
+}
+
+void TAIL(void)
+{
+    a++;
+    return;
+}
+");
+}
@@ -0,0 +1,60 @@
+extern crate rust8080;
+
+use rust8080::disassemble::{decode, disassemble, Mnemonic, Operand, RegisterPair, OPCODES};
+
+#[test]
+fn opcode_lengths() {
+    let length = |opcode: u8| OPCODES[opcode as usize].length;
+    // Immediate words and addresses
+    for &opcode in &[0x01, 0x11, 0x21, 0x31, 0x22, 0x2a, 0x32, 0x3a, 0xc2, 0xc3, 0xc4, 0xcb, 0xcd, 0xdd, 0xed, 0xfd] {
+        assert_eq!(length(opcode), 3, "opcode {:02x}", opcode);
+    }
+    // Immediate bytes and ports
+    for &opcode in &[0x06, 0x0e, 0x36, 0x3e, 0xc6, 0xce, 0xd3, 0xdb, 0xfe] {
+        assert_eq!(length(opcode), 2, "opcode {:02x}", opcode);
+    }
+    assert_eq!((0..=255u8).filter(|&opcode| length(opcode) == 3).count(), 30);
+    assert_eq!((0..=255u8).filter(|&opcode| length(opcode) == 2).count(), 18);
+    assert!((0..=255u8).all(|opcode| (1..=3).contains(&length(opcode))));
+}
+
+#[test]
+fn undocumented_aliases() {
+    let aliases: [(u8, Mnemonic, u8); 12] = [
+        (0x08, Mnemonic::Nop, 1), (0x10, Mnemonic::Nop, 1), (0x18, Mnemonic::Nop, 1), (0x20, Mnemonic::Nop, 1),
+        (0x28, Mnemonic::Nop, 1), (0x30, Mnemonic::Nop, 1), (0x38, Mnemonic::Nop, 1), (0xcb, Mnemonic::Jmp, 3),
+        (0xd9, Mnemonic::Ret, 1), (0xdd, Mnemonic::Call, 3), (0xed, Mnemonic::Call, 3), (0xfd, Mnemonic::Call, 3),
+    ];
+    for &(opcode, mnemonic, length) in &aliases {
+        let info = OPCODES[opcode as usize];
+        assert!(info.undocumented, "opcode {:02x}", opcode);
+        assert_eq!((info.mnemonic, info.length), (mnemonic, length), "opcode {:02x}", opcode);
+    }
+    assert_eq!(OPCODES.iter().filter(|info| info.undocumented).count(), aliases.len());
+    // They decode like the real thing
+    let jump = decode(&[0xcb, 0x34, 0x12], 0x0100);
+    assert_eq!((jump.to_string(), jump.branch_target()), ("JMP     $1234".to_string(), Some(0x1234)));
+    assert_eq!(decode(&[0xd9], 0).to_string(), "RET");
+}
+
+#[test]
+fn formats_operands() {
+    let text = |bytes: &[u8]| decode(bytes, 0).to_string();
+    assert_eq!(text(&[0x00]), "NOP");
+    assert_eq!(text(&[0x3e, 0x0a]), "MVI     A,$0a");
+    assert_eq!(text(&[0x31, 0x00, 0x24]), "LXI     SP,$2400");
+    assert_eq!(text(&[0x77]), "MOV     M,A");
+    assert_eq!(text(&[0xf5]), "PUSH    PSW");
+    assert_eq!(text(&[0x33]), "INX     SP");
+    assert_eq!(text(&[0xdb, 0x01]), "IN      $01");
+    assert_eq!(text(&[0xff]), "RST     7");
+    assert_eq!(text(&[0x3a, 0xeb, 0x20]), "LDA     $20eb");
+    let call = decode(&[0xcd, 0xe6, 0x01], 0x0010);
+    assert_eq!(call.operands, vec![Operand::Addr(0x01e6)]);
+    assert_eq!((call.next_addr(), call.cycles), (0x0013, 17));
+    assert_eq!(decode(&[0xc1], 0).operands, vec![Operand::Pair(RegisterPair::B)]);
+    // Operands cut off by the end of the buffer read as zero
+    assert_eq!(text(&[0xc3, 0x12]), "JMP     $0012");
+    assert_eq!(call.format_with(|addr| if addr == 0x01e6 { Some("Wait".to_string()) } else { None }), "CALL    Wait");
+    assert_eq!(disassemble(&[0x3e, 0x01, 0xc9], 0x1000), "0x1000 MVI     A,$01\n0x1002 RET\n");
+}
@@ -0,0 +1,99 @@
+extern crate rust8080;
+
+use rust8080::assemble::assemble;
+use rust8080::disassemble::flow::{Disassembly, LabelKind};
+use rust8080::symbols::SymbolTable;
+
+// DISPATCH jumps through TABLE, so ONE and TWO are only reachable through
+// data; the byte after the JMP is never reached either.
+const PROGRAM: &str = "
+        ORG     0
+START:  CALL    INIT
+        JZ      DONE
+        CALL    DISPATCH
+DONE:   JMP     START
+        DB      $ed
+INIT:   XRA     A
+        RET
+DISPATCH: LHLD  TABLE
+        PCHL
+TABLE:  DW      ONE,TWO
+ONE:    MVI     A,1
+        RET
+TWO:    JMP     DONE
+";
+
+fn disassembly(symbols: &SymbolTable) -> (Disassembly, impl Fn(&str) -> u16) {
+    let assembly = assemble(PROGRAM).unwrap();
+    let disassembly = Disassembly::analyze_with_symbols(&assembly.bytes, assembly.origin, &[0x0000], symbols);
+    let labels = assembly.labels.clone();
+    (disassembly, move |name: &str| labels[name])
+}
+
+#[test]
+fn follows_reachable_code_only() {
+    let (disassembly, label) = disassembly(&SymbolTable::new());
+    let decoded: Vec<u16> = disassembly.instructions.keys().cloned().collect();
+    assert_eq!(decoded, vec![label("START"), label("START") + 3, label("START") + 6, label("DONE"),
+                             label("INIT"), label("INIT") + 1, label("DISPATCH"), label("DISPATCH") + 3]);
+    // The table and what only it reaches stay data
+    assert!(!disassembly.is_code(label("TABLE")));
+    assert!(!disassembly.is_code(label("ONE")));
+    assert_eq!(disassembly.code_bytes(), 3 + 3 + 3 + 3 + 1 + 1 + 3 + 1);
+}
+
+#[test]
+fn generates_labels_by_use() {
+    let (disassembly, label) = disassembly(&SymbolTable::new());
+    assert_eq!(disassembly.labels.get(&label("START")), Some(&LabelKind::Jump));
+    assert_eq!(disassembly.labels.get(&label("DONE")), Some(&LabelKind::Jump));
+    assert_eq!(disassembly.labels.get(&label("INIT")), Some(&LabelKind::Subroutine));
+    assert_eq!(disassembly.labels.get(&label("DISPATCH")), Some(&LabelKind::Subroutine));
+    assert_eq!(disassembly.labels.len(), 4);
+    assert_eq!(disassembly.label(label("INIT")), Some("SUB_000D".to_string()));
+    assert_eq!(disassembly.label(label("DONE")), Some("L_0009".to_string()));
+    assert_eq!(disassembly.label(label("TABLE")), None);
+    let exported = disassembly.export_symbols();
+    assert_eq!(exported.get(label("DISPATCH")).map(|symbol| symbol.name.as_str()), Some("SUB_000F"));
+}
+
+#[test]
+fn lists_code_and_data() {
+    let (disassembly, _) = disassembly(&SymbolTable::new());
+    assert_eq!(disassembly.listing(), "        ORG     $0000\n\
+L_0000:
+0x0000 CALL    SUB_000D
+0x0003 JZ      L_0009
+0x0006 CALL    SUB_000F
+L_0009:
+0x0009 JMP     L_0000
+0x000c DB      $ed
+SUB_000D:
+0x000d XRA     A
+0x000e RET
+SUB_000F:
+0x000f LHLD    $0013
+0x0012 PCHL
+0x0013 DB      $17,$00,$1a,$00,$3e,$01,$c9,$c3
+0x001b DB      $09,$00
+");
+    // The listing assembles back to the same bytes
+    assert_eq!(assemble(&disassembly.listing()).unwrap().bytes, assemble(PROGRAM).unwrap().bytes);
+}
+
+#[test]
+fn symbols_add_code_and_table_types() {
+    let symbols = SymbolTable::parse("
+0013  table    word*2
+0017  one      code
+001A  two      code
+").unwrap();
+    let (disassembly, label) = disassembly(&symbols);
+    assert!(disassembly.is_code(label("ONE")));
+    assert!(disassembly.is_code(label("TWO")));
+    assert!(!disassembly.is_code(label("TABLE")));
+    let listing: String = disassembly.listing();
+    assert!(listing.contains("0x000f LHLD    table\n"), "{}", listing);
+    assert!(listing.contains("table:\n0x0013 DW      $0017,$001a\none:\n"), "{}", listing);
+    assert!(listing.contains("two:\n0x001a JMP     L_0009\n"), "{}", listing);
+}
@@ -0,0 +1,149 @@
+extern crate rust8080;
+
+use std::io::prelude::*;
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+use rust8080::assemble::assemble;
+use rust8080::debugger::{gdb, Debugger};
+use rust8080::symbols::SymbolTable;
+use rust8080::vm::Vm;
+
+const PROGRAM: &str = "
+        ORG 0
+        LXI SP,0100H
+        MVI A,3
+LOOP:   STA 0080H
+        DCR A
+        JNZ LOOP
+        HLT
+";
+
+/// A scripted client: sends packets and returns the replies.
+struct Client {
+    stream: TcpStream,
+    acks: bool,
+}
+
+impl Client {
+    fn read_byte(&mut self) -> u8 {
+        let mut byte = [0u8; 1];
+        self.stream.read_exact(&mut byte).unwrap();
+        byte[0]
+    }
+    fn exchange(&mut self, packet: &str) -> String {
+        let sum: u8 = packet.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        self.stream.write_all(format!("${}#{:02x}", packet, sum).as_bytes()).unwrap();
+        if self.acks {
+            assert_eq!(self.read_byte(), b'+');
+        }
+        assert_eq!(self.read_byte(), b'$');
+        let mut reply: Vec<u8> = Vec::new();
+        loop {
+            match self.read_byte() {
+                b'#' => break,
+                byte => reply.push(byte),
+            }
+        }
+        let checksum: String = String::from_utf8(vec![self.read_byte(), self.read_byte()]).unwrap();
+        let sum: u8 = reply.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        assert_eq!(checksum, format!("{:02x}", sum));
+        if self.acks {
+            self.stream.write_all(b"+").unwrap();
+        }
+        String::from_utf8(reply).unwrap()
+    }
+}
+
+/// The client half of the session; panics on any unexpected reply.
+fn script(port: u16) {
+    let mut client = Client { stream: TcpStream::connect(("127.0.0.1", port)).unwrap(), acks: true };
+    assert!(client.exchange("qSupported:swbreak+").contains("qXfer:features:read+"));
+    assert_eq!(client.exchange("?"), "S05");
+    assert!(client.exchange("qXfer:features:read:target.xml:0,1000").contains(r#"<reg name="pc" bitsize="16""#));
+    // a b c d e h l sp pc flags
+    assert_eq!(client.exchange("g"), "000000000000000000000002");
+    assert_eq!(client.exchange("s"), "S05");
+    assert_eq!(client.exchange("p7"), "0001");
+    assert_eq!(client.exchange("p8"), "0300");
+    assert_eq!(client.exchange("m0,3"), "310001");
+
+    // Break on the second pass through LOOP
+    assert_eq!(client.exchange("Z0,5,1"), "OK");
+    assert_eq!(client.exchange("c"), "T05swbreak:;");
+    assert_eq!(client.exchange("p8"), "0500");
+    assert_eq!(client.exchange("c"), "T05swbreak:;");
+    assert_eq!(client.exchange("p0"), "02");
+    assert_eq!(client.exchange("z0,5,1"), "OK");
+
+    // Then a write watchpoint on the stored byte
+    assert_eq!(client.exchange("Z2,80,1"), "OK");
+    assert_eq!(client.exchange("c"), "T05watch:0080;");
+    assert_eq!(client.exchange("m80,1"), "02");
+    assert_eq!(client.exchange("z2,80,1"), "OK");
+
+    assert_eq!(client.exchange("QStartNoAckMode"), "OK");
+    client.acks = false;
+    assert_eq!(client.exchange("P0=07"), "OK");
+    assert_eq!(client.exchange("M90,2:beef"), "OK");
+    assert_eq!(client.exchange("m90,2"), "beef");
+    assert_eq!(client.exchange("vMustReplyEmpty"), "");
+    // Runs on to HLT
+    assert_eq!(client.exchange("c"), "S05");
+    assert_eq!(client.exchange("D"), "OK");
+}
+
+/// Serves `script` as the client of a debugger running `program`.
+fn session(program: &str, recording: bool, script: fn(u16)) -> Debugger<Vm> {
+    let assembly = assemble(program).unwrap();
+    let mut vm = Vm::new();
+    vm.memory.load(0, &assembly.bytes);
+    let mut debugger = Debugger::new(vm, SymbolTable::new());
+    debugger.set_recording(recording);
+    // The machine is not Send, so the client gets the thread
+    let listener = gdb::listen(0).unwrap();
+    let port: u16 = listener.local_addr().unwrap().port();
+    let client = thread::spawn(move || script(port));
+    let (stream, _) = listener.accept().unwrap();
+    gdb::serve(&mut debugger, stream).unwrap();
+    client.join().unwrap();
+    debugger
+}
+
+#[test]
+fn scripted_session() {
+    let debugger = session(PROGRAM, false, script);
+    assert!(debugger.vm().halted);
+    assert_eq!(debugger.vm().memory.read(0x0080), 1);
+}
+
+#[test]
+fn keeps_a_packet_sent_with_the_interrupt() {
+    let debugger = session("SPIN: JMP SPIN", false, |port| {
+        let mut client = Client { stream: TcpStream::connect(("127.0.0.1", port)).unwrap(), acks: true };
+        assert_eq!(client.exchange("QStartNoAckMode"), "OK");
+        client.acks = false;
+        client.stream.write_all(b"$c#63").unwrap();
+        thread::sleep(Duration::from_millis(50));
+        // ^C and the next packet in one write
+        client.stream.write_all(b"\x03$p8#a8").unwrap();
+        let mut replies = [0u8; 15];
+        client.stream.read_exact(&mut replies).unwrap();
+        assert_eq!(&replies, b"$S02#b5$0000#c0");
+        assert_eq!(client.exchange("D"), "OK");
+    });
+    assert_eq!(debugger.vm().pc, 0);
+}
+
+#[test]
+fn writes_start_the_history_afresh() {
+    let debugger = session(PROGRAM, true, |port| {
+        let mut client = Client { stream: TcpStream::connect(("127.0.0.1", port)).unwrap(), acks: true };
+        assert_eq!(client.exchange("s"), "S05");
+        assert_eq!(client.exchange("s"), "S05");
+        assert_eq!(client.exchange("P0=07"), "OK");
+        assert_eq!(client.exchange("D"), "OK");
+    });
+    let history = debugger.history().unwrap();
+    assert_eq!(history.start(), history.position());
+}
@@ -0,0 +1,70 @@
+// Golden frame hashes for invaders.rom under a fixed input script.  If a CPU
+// or video change alters these on purpose, inspect the new frames with
+// `rust8080 roms/invaders.rom --headless --frames 600 --dump 100,300,600
+// --input <script> --out <dir>` before updating the hashes.
+extern crate rust8080;
+
+use std::fs::File;
+use std::io::prelude::*;
+use rust8080::invaders::Invaders;
+use rust8080::invaders::headless;
+use rust8080::invaders::headless::{HeadlessConfig, InputScript};
+use rust8080::trace::TraceFilter;
+
+const SCRIPT: &str = "
+# insert a coin, start a one player game, then move and fire
+200 coin
+205
+260 p1start
+265
+400 p1fire p1left
+420 p1right
+440
+";
+
+fn load_invaders() -> Invaders {
+    let mut rom: Vec<u8> = Vec::new();
+    File::open(concat!(env!("CARGO_MANIFEST_DIR"), "/roms/invaders.rom"))
+        .unwrap()
+        .read_to_end(&mut rom)
+        .unwrap();
+    Invaders::new(&rom)
+}
+
+fn config() -> HeadlessConfig {
+    HeadlessConfig {
+        frames: 600,
+        dump_frames: vec![100, 300, 600],
+        output_dir: None,
+        overlay: true,
+        script: InputScript::parse(SCRIPT).unwrap(),
+        trace: None,
+        trace_filter: TraceFilter::default(),
+        play: None,
+        record: None,
+    }
+}
+
+#[test]
+fn golden_frames() {
+    let dumps = headless::run(&mut load_invaders(), &config()).unwrap();
+    let hashes: Vec<(u64, u64)> = dumps.iter().map(|dump| (dump.frame, dump.hash)).collect();
+    assert_eq!(hashes, vec![
+        (100, 0xc37a65ab30b8d1bd),  // Attract mode typing out "SPACE INVADERS"
+        (300, 0x412aeb3b41378c2c),  // "PLAY PLAYER<1>" after coin and start
+        (600, 0xfb0c521b751fcea5),  // Invaders on screen after moving and firing
+    ]);
+}
+
+#[test]
+fn runs_are_deterministic() {
+    let first = headless::run(&mut load_invaders(), &config()).unwrap();
+    let second = headless::run(&mut load_invaders(), &config()).unwrap();
+    assert_eq!(first, second);
+}
+
+#[test]
+fn script_errors_name_the_line() {
+    let error = InputScript::parse("10 coin\n20 jump\n").unwrap_err();
+    assert_eq!(error.to_string(), "input script line 2: unknown control `jump`");
+}
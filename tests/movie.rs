@@ -0,0 +1,130 @@
+extern crate rust8080;
+
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io::prelude::*;
+use rust8080::invaders::{Invaders, COIN};
+use rust8080::invaders::headless;
+use rust8080::invaders::headless::{HeadlessConfig, HeadlessError, InputScript};
+use rust8080::invaders::movie;
+use rust8080::invaders::movie::{Movie, MovieError, VERSION};
+use rust8080::trace::TraceFilter;
+
+const SCRIPT: &str = "
+200 coin
+205
+260 p1start
+265
+400 p1fire p1left
+420 p1right
+440
+";
+
+fn invaders() -> Invaders {
+    let mut rom: Vec<u8> = Vec::new();
+    File::open("roms/invaders.rom").unwrap().read_to_end(&mut rom).unwrap();
+    Invaders::new(&rom)
+}
+
+fn config(frames: u64) -> HeadlessConfig {
+    HeadlessConfig {
+        frames,
+        dump_frames: vec![300, 500],
+        output_dir: None,
+        overlay: false,
+        script: InputScript::parse(SCRIPT).unwrap(),
+        trace: None,
+        trace_filter: TraceFilter::default(),
+        play: None,
+        record: None,
+    }
+}
+
+#[test]
+fn replays_a_recorded_run() {
+    let path = env::temp_dir().join(format!("rust8080-movie-{}.mov", std::process::id()));
+    let mut recording = config(500);
+    recording.record = Some(path.clone());
+    let mut original = invaders();
+    let recorded = headless::run(&mut original, &recording).unwrap();
+    let movie: Movie = Movie::load(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(movie.inputs.len(), 500);
+    assert_eq!(movie.inputs[200].0 & COIN, COIN);
+    assert_eq!(movie.checksum, movie::checksum(&original));
+
+    // The movie alone drives the replay, whatever the script and frame count say
+    let mut playing = config(10);
+    playing.script = InputScript::new();
+    playing.play = Some(movie);
+    let mut replayed = invaders();
+    assert_eq!(headless::run(&mut replayed, &playing).unwrap(), recorded);
+    assert_eq!(replayed.save_state(), original.save_state());
+}
+
+#[test]
+fn starts_from_its_save_state() {
+    let mut machine = invaders();
+    headless::run(&mut machine, &config(250)).unwrap();
+    let mut movie = Movie::new(&machine);
+    for _ in 0..100 {
+        movie.record(&machine.vm.io);
+        machine.run_frame().unwrap();
+    }
+    movie.finish(&machine);
+
+    let mut fresh = invaders();
+    movie.rewind(&mut fresh).unwrap();
+    assert_eq!(fresh.frame, 250);
+    for index in 0..movie.inputs.len() {
+        movie.apply(index, &mut fresh.vm.io);
+        fresh.run_frame().unwrap();
+    }
+    movie.verify(&fresh).unwrap();
+    assert_eq!(Movie::parse(&movie.to_bytes()).unwrap(), movie);
+}
+
+#[test]
+fn detects_desyncs() {
+    let mut machine = invaders();
+    let mut movie = Movie::new(&machine);
+    for _ in 0..300 {
+        movie.record(&machine.vm.io);
+        machine.run_frame().unwrap();
+    }
+    movie.finish(&machine);
+    // A coin the recording never had
+    movie.inputs[100].0 |= COIN;
+    let mut config = config(0);
+    config.play = Some(movie.clone());
+    match headless::run(&mut invaders(), &config) {
+        Err(HeadlessError::Movie(MovieError::Desync { frames, expected, .. })) => {
+            assert_eq!((frames, expected), (300, movie.checksum));
+        },
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn rejects_bad_files() {
+    let movie = Movie::new(&invaders());
+    let bytes: Vec<u8> = movie.to_bytes();
+    assert_eq!(Movie::parse(&bytes).unwrap(), movie);
+    assert!(matches!(Movie::parse(b"R8080ST\x1a\x01\x00"), Err(MovieError::NotAMovie)));
+    let mut newer: Vec<u8> = bytes.clone();
+    newer[8] = (VERSION + 1) as u8;
+    assert!(matches!(Movie::parse(&newer), Err(MovieError::Version(version)) if version == VERSION + 1));
+    // Claims more frames than it holds
+    let mut long: Vec<u8> = bytes.clone();
+    long[10..18].copy_from_slice(&[0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0]);
+    assert!(matches!(Movie::parse(&long), Err(MovieError::Corrupt(_))));
+    // Counts whose byte length overflows
+    for frames in [u64::MAX, 1 << 63, (1 << 63) - 5] {
+        let mut huge: Vec<u8> = bytes.clone();
+        huge[10..18].copy_from_slice(&frames.to_le_bytes());
+        assert!(matches!(Movie::parse(&huge), Err(MovieError::Corrupt(_))));
+    }
+    assert!(matches!(Movie::parse(&bytes[..17]), Err(MovieError::Corrupt(_))));
+    assert!(matches!(Movie::parse(&bytes[..bytes.len() - 1]), Err(MovieError::State(_))));
+}
@@ -0,0 +1,382 @@
+// Checks every documented opcode against the Intel 8080 manual: the ALU
+// operations exhaustively over all operands and carries, the rest against
+// hand-picked cases.  Needs no ROMs, unlike tests/cpu_exercisers.rs.
+extern crate rust8080;
+
+use rust8080::vm::{ConditionCodes, Vm};
+
+// Where HL points for the M operand
+const M: u16 = 0x2000;
+
+/// Flags as S, Z, AC, P, CY, so failures print readably.
+fn flags(vm: &Vm) -> [u8; 5] {
+    let cc: &ConditionCodes = &vm.condition_codes;
+    [cc.s, cc.z, cc.ac, cc.p, cc.cy]
+}
+
+/// S, Z and P of `value` followed by `ac` and `cy`.
+fn expect_flags(value: u8, ac: bool, cy: bool) -> [u8; 5] {
+    [value >> 7, (value == 0) as u8, ac as u8, value.count_ones().is_multiple_of(2) as u8, cy as u8]
+}
+
+/// Runs the instruction `program` from 0 and returns its T-states.
+fn run(vm: &mut Vm, program: &[u8]) -> u32 {
+    vm.memory.load(0, program);
+    vm.pc = 0;
+    let outcome = vm.run_current_opcode().unwrap();
+    assert_eq!(vm.pc as usize, program.len(), "{:02x?} left PC at {:04x}", program, vm.pc);
+    outcome.cycles
+}
+
+/// What the manual says accumulator operation `op` (ADD, ADC, SUB, SBB,
+/// ANA, XRA, ORA, CMP) leaves in A and the flags.
+fn alu(op: u8, a: u8, value: u8, carry: u8) -> (u8, [u8; 5]) {
+    let (a16, v16, c16): (u16, u16, u16) = (a as u16, value as u16, carry as u16);
+    match op {
+        // ADD, ADC
+        0 | 1 => {
+            let c: u16 = if op == 1 { c16 } else { 0 };
+            let sum: u16 = a16 + v16 + c;
+            let result: u8 = sum as u8;
+            (result, expect_flags(result, (a16 & 0xf) + (v16 & 0xf) + c > 0xf, sum > 0xff))
+        },
+        // SUB, SBB, CMP: AC is set when the low digit did not borrow
+        2 | 3 | 7 => {
+            let b: i16 = if op == 3 { carry as i16 } else { 0 };
+            let difference: i16 = a as i16 - value as i16 - b;
+            let result: u8 = difference as u8;
+            let ac: bool = (a & 0xf) as i16 - (value & 0xf) as i16 - b >= 0;
+            (if op == 7 { a } else { result }, expect_flags(result, ac, difference < 0))
+        },
+        // ANA: AC is bit 3 of either operand
+        4 => (a & value, expect_flags(a & value, (a | value) & 0x08 != 0, false)),
+        5 => (a ^ value, expect_flags(a ^ value, false, false)),
+        _ => (a | value, expect_flags(a | value, false, false)),
+    }
+}
+
+#[test]
+fn accumulator_operations_on_every_operand() {
+    let mut vm = Vm::new();
+    vm.h = (M >> 8) as u8;
+    vm.l = M as u8;
+    for op in 0..8u8 {
+        for a in 0..=255u8 {
+            for value in 0..=255u8 {
+                for carry in 0..2u8 {
+                    let expected: (u8, [u8; 5]) = alu(op, a, value, carry);
+                    // Register B, memory at HL and immediate forms
+                    let forms: [(&[u8], u32); 3] = [
+                        (&[0x80 | op << 3], 4),
+                        (&[0x86 | op << 3], 7),
+                        (&[0xc6 | op << 3, value], 7),
+                    ];
+                    for &(program, cycles) in forms.iter() {
+                        vm.a = a;
+                        vm.b = value;
+                        vm.memory.write(M, value);
+                        vm.condition_codes = ConditionCodes::from_psw(carry);
+                        assert_eq!(run(&mut vm, program), cycles);
+                        assert_eq!((vm.a, flags(&vm)), expected,
+                                   "{:02x?} with A {:02x}, operand {:02x}, CY {}", program, a, value, carry);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn increment_and_decrement_every_value() {
+    let mut vm = Vm::new();
+    for value in 0..=255u8 {
+        for carry in 0..2u8 {
+            for r in 0..8u8 {
+                // INR r: AC when the low digit carries out; CY untouched
+                vm.h = (M >> 8) as u8;
+                vm.l = M as u8;
+                let up: u8 = value.wrapping_add(1);
+                vm.condition_codes = ConditionCodes::from_psw(carry);
+                set_reg(&mut vm, r, value);
+                assert_eq!(run(&mut vm, &[0x04 | r << 3]), if r == 6 { 10 } else { 5 });
+                assert_eq!(get_reg(&vm, r), up);
+                assert_eq!(flags(&vm), expect_flags(up, value & 0xf == 0xf, carry == 1), "INR {} of {:02x}", r, value);
+
+                // DCR r: adds 0xff, so AC unless the low digit was 0
+                let down: u8 = value.wrapping_sub(1);
+                set_reg(&mut vm, r, value);
+                assert_eq!(run(&mut vm, &[0x05 | r << 3]), if r == 6 { 10 } else { 5 });
+                assert_eq!(get_reg(&vm, r), down);
+                assert_eq!(flags(&vm), expect_flags(down, value & 0xf != 0, carry == 1), "DCR {} of {:02x}", r, value);
+            }
+        }
+    }
+}
+
+#[test]
+fn rotates_and_carry_operations() {
+    let mut vm = Vm::new();
+    for a in 0..=255u8 {
+        for carry in 0..2u8 {
+            // (opcode, A after, CY after)
+            let cases: [(u8, u8, u8); 7] = [
+                (0x07, a.rotate_left(1), a >> 7),             // RLC
+                (0x0f, a.rotate_right(1), a & 1),             // RRC
+                (0x17, a << 1 | carry, a >> 7),               // RAL
+                (0x1f, a >> 1 | carry << 7, a & 1),           // RAR
+                (0x2f, !a, carry),                            // CMA
+                (0x37, a, 1),                                 // STC
+                (0x3f, a, carry ^ 1),                         // CMC
+            ];
+            for &(opcode, result, cy) in cases.iter() {
+                // Every other flag is left alone
+                let psw: u8 = 0xd6 | carry;
+                vm.a = a;
+                vm.condition_codes = ConditionCodes::from_psw(psw);
+                assert_eq!(run(&mut vm, &[opcode]), 4);
+                assert_eq!((vm.a, vm.condition_codes.to_psw()), (result, psw & !1 | cy),
+                           "{:02x} with A {:02x}, CY {}", opcode, a, carry);
+            }
+        }
+    }
+}
+
+#[test]
+fn decimal_adjust_every_value() {
+    let mut vm = Vm::new();
+    for a in 0..=255u8 {
+        for psw in [0x00u8, 0x01, 0x10, 0x11].iter() {
+            let (ac, cy): (u8, u8) = (psw >> 4 & 1, psw & 1);
+            // The manual's two steps, the second looking at the first's result
+            let low: u8 = if a & 0xf > 9 || ac == 1 { 0x06 } else { 0x00 };
+            let corrected: u8 = a.wrapping_add(low);
+            let carried: bool = a as u16 + low as u16 > 0xff;
+            let high: u8 = if corrected >> 4 > 9 || cy == 1 || carried { 0x60 } else { 0x00 };
+            let result: u8 = corrected.wrapping_add(high);
+            vm.a = a;
+            vm.condition_codes = ConditionCodes::from_psw(*psw);
+            assert_eq!(run(&mut vm, &[0x27]), 4);
+            assert_eq!((vm.a, flags(&vm)), (result, expect_flags(result, (a & 0xf) + low > 0xf, high != 0)),
+                       "DAA of {:02x} with AC {}, CY {}", a, ac, cy);
+        }
+    }
+}
+
+/// Register `r` in opcode order: B, C, D, E, H, L, M, A.
+fn get_reg(vm: &Vm, r: u8) -> u8 {
+    match r {
+        0 => vm.b,
+        1 => vm.c,
+        2 => vm.d,
+        3 => vm.e,
+        4 => vm.h,
+        5 => vm.l,
+        6 => vm.memory.read((vm.h as u16) << 8 | vm.l as u16),
+        _ => vm.a,
+    }
+}
+
+fn set_reg(vm: &mut Vm, r: u8, value: u8) {
+    match r {
+        0 => vm.b = value,
+        1 => vm.c = value,
+        2 => vm.d = value,
+        3 => vm.e = value,
+        4 => vm.h = value,
+        5 => vm.l = value,
+        6 => {
+            let hl: u16 = (vm.h as u16) << 8 | vm.l as u16;
+            vm.memory.write(hl, value);
+        },
+        _ => vm.a = value,
+    }
+}
+
+#[test]
+fn moves_between_every_register_pair() {
+    let mut vm = Vm::new();
+    for dst in 0..8u8 {
+        for src in 0..8u8 {
+            if dst == 6 && src == 6 {
+                // 0x76 is HLT
+                continue;
+            }
+            let opcode: u8 = 0x40 | dst << 3 | src;
+            // Distinct values, with HL pointing at RAM
+            vm.a = 0xa7;
+            vm.b = 0xb0;
+            vm.c = 0xc1;
+            vm.d = 0xd2;
+            vm.e = 0xe3;
+            vm.h = 0x21;
+            vm.l = 0x45;
+            vm.memory.write(0x2145, 0x66);
+            vm.condition_codes = ConditionCodes::from_psw(0xd5);
+            let value: u8 = get_reg(&vm, src);
+            assert_eq!(run(&mut vm, &[opcode]), if dst == 6 || src == 6 { 7 } else { 5 });
+            assert_eq!(get_reg(&vm, dst), value, "MOV {},{}", dst, src);
+            assert_eq!(vm.condition_codes.to_psw(), 0xd7, "MOV {},{} changed the flags", dst, src);
+        }
+        // MVI
+        vm.h = 0x21;
+        vm.l = 0x45;
+        assert_eq!(run(&mut vm, &[0x06 | dst << 3, 0x3c]), if dst == 6 { 10 } else { 7 });
+        assert_eq!(get_reg(&vm, dst), 0x3c, "MVI {}", dst);
+    }
+}
+
+#[test]
+fn register_pair_operations() {
+    let mut vm = Vm::new();
+    // LXI, INX and DCX on BC, DE, HL and SP, wrapping at both ends
+    for rp in 0..4u8 {
+        let pair = |vm: &Vm| match rp {
+            0 => (vm.b as u16) << 8 | vm.c as u16,
+            1 => (vm.d as u16) << 8 | vm.e as u16,
+            2 => (vm.h as u16) << 8 | vm.l as u16,
+            _ => vm.sp,
+        };
+        assert_eq!(run(&mut vm, &[0x01 | rp << 4, 0xff, 0xff]), 10);
+        assert_eq!(pair(&vm), 0xffff);
+        assert_eq!(run(&mut vm, &[0x03 | rp << 4]), 5);
+        assert_eq!(pair(&vm), 0x0000);
+        assert_eq!(run(&mut vm, &[0x0b | rp << 4]), 5);
+        assert_eq!(pair(&vm), 0xffff);
+    }
+    // DAD: only CY changes
+    let cases: [(u16, u16, u16, u8); 3] = [
+        (0x1234, 0x1111, 0x2345, 0),
+        (0xffff, 0x0001, 0x0000, 1),
+        (0x8000, 0x8000, 0x0000, 1),
+    ];
+    for &(hl, bc, result, cy) in cases.iter() {
+        vm.h = (hl >> 8) as u8;
+        vm.l = hl as u8;
+        vm.b = (bc >> 8) as u8;
+        vm.c = bc as u8;
+        vm.condition_codes = ConditionCodes::from_psw(0xd4);
+        assert_eq!(run(&mut vm, &[0x09]), 10);
+        assert_eq!(((vm.h as u16) << 8 | vm.l as u16, vm.condition_codes.to_psw()), (result, 0xd6 | cy));
+    }
+    // DAD H doubles HL
+    vm.h = 0x81;
+    vm.l = 0x01;
+    run(&mut vm, &[0x29]);
+    assert_eq!((vm.h, vm.l, vm.condition_codes.cy), (0x02, 0x02, 1));
+}
+
+#[test]
+fn loads_stores_and_exchanges() {
+    let mut vm = Vm::new();
+    vm.a = 0x5a;
+    vm.b = 0x30;
+    vm.c = 0x00;
+    vm.d = 0x30;
+    vm.e = 0x01;
+    assert_eq!(run(&mut vm, &[0x02]), 7);               // STAX B
+    vm.a = 0xa5;
+    assert_eq!(run(&mut vm, &[0x12]), 7);               // STAX D
+    assert_eq!(run(&mut vm, &[0x0a]), 7);               // LDAX B
+    assert_eq!(vm.a, 0x5a);
+    assert_eq!(run(&mut vm, &[0x1a]), 7);               // LDAX D
+    assert_eq!(vm.a, 0xa5);
+    assert_eq!(run(&mut vm, &[0x32, 0x10, 0x30]), 13);  // STA 3010h
+    vm.a = 0;
+    assert_eq!(run(&mut vm, &[0x3a, 0x00, 0x30]), 13);  // LDA 3000h
+    assert_eq!(vm.a, 0x5a);
+    assert_eq!(vm.memory.read(0x3010), 0xa5);
+
+    vm.h = 0x12;
+    vm.l = 0x34;
+    assert_eq!(run(&mut vm, &[0x22, 0x20, 0x30]), 16);  // SHLD 3020h
+    assert_eq!((vm.memory.read(0x3020), vm.memory.read(0x3021)), (0x34, 0x12));
+    assert_eq!(run(&mut vm, &[0x2a, 0x00, 0x30]), 16);  // LHLD 3000h
+    assert_eq!((vm.h, vm.l), (0xa5, 0x5a));
+
+    assert_eq!(run(&mut vm, &[0xeb]), 4);               // XCHG
+    assert_eq!((vm.d, vm.e, vm.h, vm.l), (0xa5, 0x5a, 0x30, 0x01));
+
+    vm.sp = 0x3020;
+    assert_eq!(run(&mut vm, &[0xe3]), 18);              // XTHL
+    assert_eq!((vm.h, vm.l), (0x12, 0x34));
+    assert_eq!((vm.memory.read(0x3020), vm.memory.read(0x3021)), (0x01, 0x30));
+    assert_eq!(run(&mut vm, &[0xf9]), 5);               // SPHL
+    assert_eq!(vm.sp, 0x1234);
+}
+
+#[test]
+fn stack_operations() {
+    let mut vm = Vm::new();
+    vm.sp = 0x3000;
+    vm.b = 0x01;
+    vm.c = 0x02;
+    vm.d = 0x03;
+    vm.e = 0x04;
+    vm.h = 0x05;
+    vm.l = 0x06;
+    vm.a = 0x07;
+    vm.condition_codes = ConditionCodes::from_psw(0xff);
+    // PUSH B, D, H, PSW then POP them back in the other order
+    for &opcode in [0xc5u8, 0xd5, 0xe5, 0xf5].iter() {
+        assert_eq!(run(&mut vm, &[opcode]), 11);
+    }
+    assert_eq!(vm.sp, 0x2ff8);
+    assert_eq!(vm.memory.read_range(0x2ff8, 8), vec![0xd7, 0x07, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01]);
+    for &opcode in [0xc1u8, 0xd1, 0xe1, 0xf1].iter() {
+        assert_eq!(run(&mut vm, &[opcode]), 10);
+    }
+    assert_eq!(vm.sp, 0x3000);
+    // BC has PSW and A, DE has HL, and so on
+    assert_eq!((vm.b, vm.c, vm.d, vm.e, vm.h, vm.l), (0x07, 0xd7, 0x05, 0x06, 0x03, 0x04));
+    assert_eq!((vm.a, vm.condition_codes.to_psw()), (0x01, 0x02));
+}
+
+#[test]
+fn jumps_calls_returns_and_restarts() {
+    let mut vm = Vm::new();
+    vm.sp = 0x3000;
+    vm.memory.load(0, &[0xc3, 0x00, 0x10]);             // JMP 1000h
+    vm.pc = 0;
+    assert_eq!(vm.run_current_opcode().unwrap().cycles, 10);
+    assert_eq!(vm.pc, 0x1000);
+    vm.memory.load(0x1000, &[0xcd, 0x00, 0x20]);        // CALL 2000h
+    assert_eq!(vm.run_current_opcode().unwrap().cycles, 17);
+    assert_eq!((vm.pc, vm.sp, vm.memory.read(0x2ffe), vm.memory.read(0x2fff)), (0x2000, 0x2ffe, 0x03, 0x10));
+    vm.memory.load(0x2000, &[0xc9]);                    // RET
+    assert_eq!(vm.run_current_opcode().unwrap().cycles, 10);
+    assert_eq!((vm.pc, vm.sp), (0x1003, 0x3000));
+    for n in 0..8u16 {
+        vm.memory.load(0x1003, &[0xc7 | (n as u8) << 3]);   // RST n
+        vm.pc = 0x1003;
+        assert_eq!(vm.run_current_opcode().unwrap().cycles, 11);
+        assert_eq!((vm.pc, vm.sp), (n * 8, 0x2ffe));
+        assert_eq!((vm.memory.read(0x2ffe), vm.memory.read(0x2fff)), (0x04, 0x10));
+        vm.sp = 0x3000;
+    }
+    vm.h = 0x12;
+    vm.l = 0x34;
+    vm.memory.load(0x4000, &[0xe9]);                    // PCHL
+    vm.pc = 0x4000;
+    assert_eq!(vm.run_current_opcode().unwrap().cycles, 5);
+    assert_eq!(vm.pc, 0x1234);
+}
+
+#[test]
+fn interrupt_control() {
+    let mut vm = Vm::new();
+    vm.sp = 0x3000;
+    // EI takes effect after the next instruction
+    assert_eq!(run(&mut vm, &[0xfb]), 4);
+    assert!(!vm.generate_interrupt(2));
+    assert_eq!(run(&mut vm, &[0x00]), 4);
+    assert!(vm.generate_interrupt(2));
+    assert_eq!((vm.pc, vm.int_enable), (0x0010, 0));
+    // DI
+    run(&mut vm, &[0xfb]);
+    run(&mut vm, &[0x00]);
+    assert_eq!(run(&mut vm, &[0xf3]), 4);
+    assert!(!vm.generate_interrupt(1));
+    // HLT
+    assert_eq!(run(&mut vm, &[0x76]), 7);
+    assert!(vm.halted);
+}
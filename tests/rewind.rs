@@ -0,0 +1,216 @@
+extern crate rust8080;
+
+use std::fs::File;
+use std::io::prelude::*;
+use rust8080::assemble::assemble;
+use rust8080::debugger::{Debugger, Machine, Stop, WatchKind};
+use rust8080::debugger::bus::Access;
+use rust8080::debugger::expr::Expr;
+use rust8080::debugger::repl;
+use rust8080::debugger::rewind::{History, Record};
+use rust8080::invaders::Invaders;
+use rust8080::savestate;
+use rust8080::savestate::{SaveState, StateError};
+use rust8080::vm::{Event, NullPorts, Vm, VmError};
+
+const PROGRAM: &str = "
+        ORG 0
+        LXI SP,0100H
+START:  MVI A,3
+        CALL SUB
+        STA 0080H
+LOOP:   DCR A
+        JNZ LOOP
+        HLT
+SUB:    MVI B,1
+        CALL LEAF
+        RET
+LEAF:   INR B
+        PUSH PSW
+        LDA 0080H
+        POP PSW
+        RET
+";
+
+fn debugger() -> Debugger<Vm> {
+    let assembly = assemble(PROGRAM).unwrap();
+    let mut vm = Vm::new();
+    vm.memory.load(0, &assembly.bytes);
+    let mut debugger = Debugger::new(vm, assembly.symbol_table());
+    debugger.set_recording(true);
+    debugger
+}
+
+fn state<M: Machine>(debugger: &Debugger<M>) -> Vec<u8> {
+    let mut state = SaveState::new("test");
+    savestate::save_vm(debugger.vm(), &mut state);
+    debugger.machine.save_devices(&mut state);
+    state.to_bytes()
+}
+
+#[test]
+fn steps_back_to_every_earlier_state() {
+    let mut debugger = debugger();
+    let mut states: Vec<(Vec<u8>, i64)> = vec![(state(&debugger), debugger.depth)];
+    while debugger.step() == Stop::Done {
+        states.push((state(&debugger), debugger.depth));
+    }
+    // Halting moved PC on without leaving a record
+    states.pop();
+    while let Some((expected, depth)) = states.pop() {
+        assert_eq!(debugger.step_back(), Stop::Done);
+        assert_eq!(state(&debugger), expected);
+        assert_eq!(debugger.depth, depth);
+    }
+    assert_eq!(debugger.vm().pc, 0x0000);
+    assert_eq!(debugger.step_back(), Stop::HistoryStart);
+    // Running forward again gets to the same place
+    assert_eq!(debugger.cont(), Stop::Error(VmError::Halted { pc: 0x0010 }));
+    assert_eq!(debugger.vm().b, 2);
+}
+
+#[test]
+fn reverse_continues_to_writes_and_breakpoints() {
+    let mut debugger = debugger();
+    assert_eq!(debugger.cont(), Stop::Error(VmError::Halted { pc: 0x0010 }));
+    let write: usize = debugger.add_watchpoint(0x0080, 0x0080, WatchKind::Write);
+    let leaf: usize = debugger.add_breakpoint(0x0016, None);
+    // Stops just after the STA
+    match debugger.reverse_continue() {
+        Stop::Watchpoint { id, pc, access } => {
+            assert_eq!((id, pc, access.old, access.value), (write, 0x0008, 0x00, 0x03));
+        },
+        stop => panic!("unexpected stop: {:?}", stop),
+    }
+    assert_eq!(debugger.vm().pc, 0x000b);
+    assert_eq!(debugger.vm().memory.read_range(0x0080, 1)[0], 0x03);
+    // Stops just before LEAF's first instruction
+    assert_eq!(debugger.reverse_continue(), Stop::Breakpoint { id: leaf, addr: 0x0016 });
+    assert_eq!((debugger.vm().b, debugger.depth), (1, 2));
+    assert_eq!(debugger.vm().memory.read_range(0x0080, 1)[0], 0x00);
+    assert_eq!(debugger.reverse_continue(), Stop::HistoryStart);
+    assert_eq!(debugger.vm().pc, 0x0000);
+    assert_eq!(debugger.watchpoints()[0].hits, 1);
+    assert_eq!(debugger.breakpoints()[0].hits, 1);
+}
+
+#[test]
+fn reverse_continue_checks_conditions() {
+    let mut debugger = debugger();
+    debugger.cont();
+    // LOOP runs with a = 3, 2, 1; going backwards 1 comes first
+    let condition = Expr::parse("a == 2", &debugger.symbols).unwrap();
+    let id: usize = debugger.add_breakpoint(0x000b, Some(condition));
+    assert_eq!(debugger.reverse_continue(), Stop::Breakpoint { id, addr: 0x000b });
+    assert_eq!(debugger.vm().a, 2);
+    assert_eq!(debugger.breakpoints()[0].hits, 1);
+}
+
+#[test]
+fn finds_the_last_write() {
+    let mut debugger = debugger();
+    debugger.cont();
+    let last = debugger.last_write(0x0080).unwrap();
+    assert_eq!((last.pc, last.old, last.value), (Some(0x0008), 0x00, 0x03));
+    assert_eq!(debugger.last_write(0x0081), None);
+    // PUSH PSW in LEAF was the last to write the top of the stack
+    assert_eq!(debugger.last_write(0x00fa).map(|last| last.pc), Some(Some(0x0017)));
+
+    let mut output: Vec<u8> = Vec::new();
+    let mut input: &[u8] = b"ww 0x80\nrs 2\nq\n";
+    repl::run(&mut debugger, &mut input, &mut output).unwrap();
+    let output: String = String::from_utf8(output).unwrap();
+    assert!(output.contains("0x00 -> 0x03 at event 11 by 0x0008  STA     $0080"), "{}", output);
+    assert!(output.contains("=> 0x000c  JNZ"), "{}", output);
+    debugger.set_recording(false);
+    assert_eq!(repl::execute(&mut debugger, "rc"), Err("not recording, try record".to_string()));
+}
+
+#[test]
+fn drops_the_oldest_history() {
+    let mut history = History::new(4, 10);
+    for index in 0..25 {
+        if history.wants_snapshot() {
+            history.add_snapshot(SaveState::new("test"));
+        }
+        let write = Access { addr: index, value: index as u8, old: 0, write: true };
+        history.push(Record { pc: Some(index), depth: 0, writes: vec![write] });
+    }
+    assert_eq!(history.position(), 25);
+    // Whole stretches between snapshots go at once
+    assert_eq!(history.start(), 16);
+    assert_eq!(history.record(15), None);
+    assert_eq!(history.record(16).unwrap().pc, Some(16));
+    assert_eq!(history.last_write(20).map(|last| last.index), Some(20));
+    assert_eq!(history.last_write(3), None);
+    history.truncate(18);
+    assert_eq!(history.position(), 18);
+    assert_eq!(history.snapshot_before(17).map(|&(at, _)| at), Some(16));
+}
+
+#[test]
+fn rewinds_invaders_across_interrupts() {
+    let mut rom: Vec<u8> = Vec::new();
+    File::open("roms/invaders.rom").unwrap().read_to_end(&mut rom).unwrap();
+    let mut debugger = Debugger::new(Invaders::new(&rom), Default::default());
+    debugger.set_recording(true);
+    let mut marks: Vec<(u64, Vec<u8>)> = Vec::new();
+    for step in 0..5 {
+        debugger.run_frame();
+        for _ in 0..step * 1000 {
+            debugger.step();
+        }
+        marks.push((debugger.history().unwrap().position(), debugger.machine.save_state().to_bytes()));
+    }
+    let end: Vec<u8> = debugger.machine.save_state().to_bytes();
+    let (first, _) = marks[0];
+    while let Some((position, expected)) = marks.pop() {
+        debugger.rewind_to(position).unwrap();
+        assert_eq!(debugger.history().unwrap().position(), position);
+        assert_eq!(debugger.machine.save_state().to_bytes(), expected);
+    }
+    // And forward again from the first mark lands in the same place
+    debugger.rewind_to(first).unwrap();
+    for step in 1..5 {
+        debugger.run_frame();
+        for _ in 0..step * 1000 {
+            debugger.step();
+        }
+    }
+    assert_eq!(debugger.machine.save_state().to_bytes(), end);
+}
+
+// A machine whose device state never loads back
+struct BadDevices(Vm);
+
+impl Machine for BadDevices {
+    type Io = NullPorts;
+    fn vm(&self) -> &Vm {
+        &self.0
+    }
+    fn vm_mut(&mut self) -> &mut Vm {
+        &mut self.0
+    }
+    fn step(&mut self) -> Result<Event, VmError> {
+        self.0.run_current_opcode().map(Event::Instruction)
+    }
+    fn load_devices(&mut self, _state: &SaveState) -> Result<(), StateError> {
+        Err(StateError::Missing("DEV".to_string()))
+    }
+}
+
+#[test]
+fn drops_the_history_when_a_snapshot_will_not_restore() {
+    let assembly = assemble(PROGRAM).unwrap();
+    let mut vm = Vm::new();
+    vm.memory.load(0, &assembly.bytes);
+    let mut debugger = Debugger::new(BadDevices(vm), assembly.symbol_table());
+    debugger.set_recording(true);
+    for _ in 0..5 {
+        assert_eq!(debugger.step(), Stop::Done);
+    }
+    assert_eq!(debugger.step_back(), Stop::RewindFailed);
+    let history: &History = debugger.history().unwrap();
+    assert_eq!(history.start(), history.position());
+    assert_eq!(debugger.step_back(), Stop::HistoryStart);
+}
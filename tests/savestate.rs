@@ -0,0 +1,85 @@
+extern crate rust8080;
+
+use std::fs::File;
+use std::io::prelude::*;
+use rust8080::invaders::{video, Invaders, COIN};
+use rust8080::invaders::headless;
+use rust8080::savestate::{SaveState, StateError, VERSION};
+
+fn invaders_rom() -> Vec<u8> {
+    let mut rom: Vec<u8> = Vec::new();
+    File::open("roms/invaders.rom").unwrap().read_to_end(&mut rom).unwrap();
+    rom
+}
+
+fn run(machine: &mut Invaders, frames: u64) {
+    for _ in 0..frames {
+        machine.run_frame().unwrap();
+    }
+}
+
+fn screen(machine: &Invaders) -> u64 {
+    headless::hash_pixels(&video::frame(&machine.vm, false))
+}
+
+#[test]
+fn restored_machine_runs_identically() {
+    let rom: Vec<u8> = invaders_rom();
+    let mut original = Invaders::new(&rom);
+    original.vm.io.port1 |= COIN;
+    run(&mut original, 150);
+    original.vm.io.port1 &= !COIN;
+    // Part way through a frame, so the timing has to come back too
+    for _ in 0..1234 {
+        original.step().unwrap();
+    }
+    let bytes: Vec<u8> = original.save_state().to_bytes();
+
+    let mut restored = Invaders::new(&rom);
+    restored.load_state(&SaveState::parse(&bytes).unwrap()).unwrap();
+    assert_eq!(restored.save_state().to_bytes(), bytes);
+    assert_eq!(restored.frame, 150);
+    run(&mut original, 200);
+    run(&mut restored, 200);
+    assert_eq!(restored.vm.cycles, original.vm.cycles);
+    assert_eq!(screen(&restored), screen(&original));
+    assert_eq!(restored.save_state(), original.save_state());
+}
+
+#[test]
+fn rejects_bad_states_without_touching_the_machine() {
+    let rom: Vec<u8> = invaders_rom();
+    let mut source = Invaders::new(&rom);
+    run(&mut source, 10);
+    let bytes: Vec<u8> = source.save_state().to_bytes();
+    let mut machine = Invaders::new(&rom);
+    run(&mut machine, 3);
+    let before: Vec<u8> = machine.save_state().to_bytes();
+
+    assert!(matches!(SaveState::parse(b"invaders"), Err(StateError::NotAState)));
+    let mut newer: Vec<u8> = bytes.clone();
+    newer[8] = (VERSION + 1) as u8;
+    assert!(matches!(SaveState::parse(&newer), Err(StateError::Version(_))));
+    assert!(matches!(SaveState::parse(&bytes[..bytes.len() - 1]), Err(StateError::Corrupt(_))));
+
+    let io: usize = bytes.windows(4).position(|tag| tag == b"IO  ").unwrap();
+    let mut bad_shift: Vec<u8> = bytes.clone();
+    bad_shift[io + 8 + 2] = 9;
+    let error = machine.load_state(&SaveState::parse(&bad_shift).unwrap()).unwrap_err();
+    assert_eq!(error.to_string(), "corrupt save state: shift offset is 9");
+
+    let mut other_rom: Vec<u8> = rom.clone();
+    other_rom[0x100] ^= 0xff;
+    let error = Invaders::new(&other_rom).load_state(&SaveState::parse(&bytes).unwrap()).unwrap_err();
+    assert_eq!(error.to_string(), "save state is for a different ROM");
+
+    let mut state = SaveState::new("invaders");
+    state.add(b"CPU ", vec![0; 23]);
+    assert_eq!(machine.load_state(&state).unwrap_err().to_string(), "save state has no MEM section");
+    assert_eq!(machine.load_state(&SaveState::new("pacman")).unwrap_err().to_string(),
+               "save state is for a pacman machine");
+
+    assert_eq!(machine.save_state().to_bytes(), before);
+    machine.load_state(&SaveState::parse(&bytes).unwrap()).unwrap();
+    assert_eq!(machine.frame, 10);
+}
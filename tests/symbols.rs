@@ -0,0 +1,90 @@
+extern crate rust8080;
+
+use std::env;
+use std::fs;
+use rust8080::symbols::{parse_addr, DataType, Symbol, SymbolError, SymbolTable};
+
+const SYMBOLS: &str = "
+; address  name  type  ; comment
+20EB       numCoins        byte     ; credits, in BCD
+$2400      vram            byte*7168
+0x1947     DrawNumCredits  code     ; prints the credit count
+0008       ScanLine96
+1E00       messages        text*16
+2010       pointers        word*4
+";
+
+fn parse_error(text: &str) -> (usize, String) {
+    match SymbolTable::parse(text) {
+        Err(SymbolError::Parse { line, message }) => (line, message),
+        other => panic!("unexpected result: {:?}", other.map(|table| table.to_text())),
+    }
+}
+
+#[test]
+fn parses_every_field() {
+    let table = SymbolTable::parse(SYMBOLS).unwrap();
+    assert_eq!(table.len(), 6);
+    let coins: &Symbol = table.get(0x20eb).unwrap();
+    assert_eq!(coins.name, "numCoins");
+    assert_eq!(coins.data, Some((DataType::Byte, 1)));
+    assert_eq!(coins.comment.as_deref(), Some("credits, in BCD"));
+    assert_eq!(table.by_name("vram").map(|symbol| (symbol.addr, symbol.size())), Some((0x2400, 7168)));
+    assert_eq!(table.by_name("DrawNumCredits").unwrap().data, Some((DataType::Code, 1)));
+    let vector: &Symbol = table.get(0x0008).unwrap();
+    assert_eq!((vector.data, vector.comment.clone()), (None, None));
+    assert!(!vector.is_data());
+    assert_eq!(table.get(0x2010).unwrap().size(), 8);
+    assert_eq!(table.by_name("nothing"), None);
+    assert_eq!((parse_addr("$20eb"), parse_addr("0X20EB"), parse_addr("20g0")), (Some(0x20eb), Some(0x20eb), None));
+}
+
+#[test]
+fn names_offsets_into_data() {
+    let table = SymbolTable::parse(SYMBOLS).unwrap();
+    assert_eq!(table.name_for(0x2400), Some("vram".to_string()));
+    assert_eq!(table.name_for(0x2402), Some("vram+2".to_string()));
+    assert_eq!(table.name_for(0x2017), Some("pointers+7".to_string()));
+    // Past the end of the data, and code only covers its first byte
+    assert_eq!(table.name_for(0x2018), None);
+    assert_eq!(table.name_for(0x1948), None);
+    let (symbol, offset) = table.containing(0x1e05).unwrap();
+    assert_eq!((symbol.name.as_str(), offset), ("messages", 5));
+    // name+offset leads back to the address
+    assert_eq!(table.by_name("messages").unwrap().addr + offset as u16, 0x1e05);
+    assert_eq!(table.get(0x1e05), None);
+}
+
+#[test]
+fn round_trips_through_text_and_files() {
+    let table = SymbolTable::parse(SYMBOLS).unwrap();
+    let again = SymbolTable::parse(&table.to_text()).unwrap();
+    let symbols: Vec<&Symbol> = table.iter().collect();
+    assert_eq!(again.iter().collect::<Vec<&Symbol>>(), symbols);
+    assert!(table.to_text().contains("20EB  numCoins                  byte        ; credits, in BCD\n"));
+
+    let path = env::temp_dir().join(format!("rust8080-symbols-{}.sym", std::process::id()));
+    table.save(&path).unwrap();
+    let loaded = SymbolTable::load(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(loaded.iter().collect::<Vec<&Symbol>>(), symbols);
+    assert!(matches!(SymbolTable::load(&path), Err(SymbolError::Io(_))));
+}
+
+#[test]
+fn reports_bad_lines() {
+    assert_eq!(parse_error("0000 start\nXYZW  nowhere\n"), (2, "bad address `XYZW`".to_string()));
+    assert_eq!(parse_error("10000 tooFar"), (1, "bad address `10000`".to_string()));
+    assert_eq!(parse_error("0000"), (1, "missing name".to_string()));
+    assert_eq!(parse_error("0000 start float"), (1, "bad type `float`".to_string()));
+    assert_eq!(parse_error("0000 start byte*0"), (1, "bad type `byte*0`".to_string()));
+    assert_eq!(parse_error("0000 start code extra"), (1, "unexpected `extra`".to_string()));
+    assert_eq!(parse_error("0000 start\n\n0010 start ; again"),
+               (3, "duplicate name `start`, already at 0000".to_string()));
+    let error = SymbolTable::parse("0000 start\n0010 start").err().unwrap();
+    assert_eq!(error.to_string(), "symbol file line 2: duplicate name `start`, already at 0000");
+    assert_eq!(parse_error("0000 start\n0000 other"),
+               (2, "duplicate address 0000, already named `start`".to_string()));
+    assert_eq!(parse_error("0000 2start"), (1, "`2start` is not a name the assembler would accept".to_string()));
+    assert_eq!(parse_error("0000 a+b"), (1, "`a+b` is not a name the assembler would accept".to_string()));
+}
@@ -0,0 +1,118 @@
+extern crate rust8080;
+
+use std::cell::RefCell;
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::rc::Rc;
+use rust8080::assemble::assemble;
+use rust8080::invaders::Invaders;
+use rust8080::invaders::headless;
+use rust8080::invaders::headless::{HeadlessConfig, InputScript};
+use rust8080::trace;
+use rust8080::trace::{TraceFilter, Tracer};
+use rust8080::vm::Vm;
+
+const PROGRAM: &str = "
+        ORG 0
+        LXI SP,0100H
+        MVI A,2
+LOOP:   CALL SUB
+        DCR A
+        JNZ LOOP
+        HLT
+SUB:    INR B
+        RET
+";
+
+/// A writer the test can read back after the tracer is done with it.
+#[derive(Clone, Default)]
+struct Shared(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Shared {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(bytes);
+        Ok(bytes.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn trace_program(filter: TraceFilter) -> Vec<String> {
+    let mut vm = Vm::new();
+    vm.memory.load(0, &assemble(PROGRAM).unwrap().bytes);
+    let out = Shared::default();
+    let mut tracer = Tracer::new(Box::new(out.clone()), filter);
+    while tracer.step(&mut vm).is_ok() {}
+    tracer.finish().unwrap();
+    let text: String = String::from_utf8(out.0.borrow().clone()).unwrap();
+    text.lines().map(|line| line.to_string()).collect()
+}
+
+#[test]
+fn logs_state_before_each_instruction() {
+    let lines: Vec<String> = trace_program(TraceFilter::default());
+    assert_eq!(lines.len(), 13);
+    assert_eq!(lines[0], "         0 0000  31 00 01  LXI     SP,$0100    A=00 BC=0000 DE=0000 HL=0000 SP=0000 F=.....");
+    assert_eq!(lines[2], "        17 0005  cd 0d 00  CALL    $000d       A=02 BC=0000 DE=0000 HL=0000 SP=0100 F=.....");
+    assert_eq!(lines[12], "       111 000c  76        HLT                 A=00 BC=0200 DE=0000 HL=0000 SP=0100 F=.ZAP.");
+}
+
+#[test]
+fn filters_and_triggers() {
+    // Only SUB
+    let lines: Vec<String> = trace_program(TraceFilter { ranges: vec![(0x000d, 0x000e)], start: None, stop: None });
+    let pcs: Vec<&str> = lines.iter().map(|line| line.split_whitespace().nth(1).unwrap()).collect();
+    assert_eq!(pcs, ["000d", "000e", "000d", "000e"]);
+    // From the first RET to the DCR after it, then again from the second RET
+    let lines: Vec<String> = trace_program(TraceFilter { ranges: Vec::new(), start: Some(0x000e), stop: Some(0x0008) });
+    let pcs: Vec<&str> = lines.iter().map(|line| line.split_whitespace().nth(1).unwrap()).collect();
+    assert_eq!(pcs, ["000e", "0008", "000e", "0008"]);
+}
+
+#[test]
+fn diff_finds_the_first_divergence() {
+    let left: String = trace_program(TraceFilter::default()).join("\n");
+    assert_eq!(trace::diff(left.as_bytes(), left.as_bytes(), &[]).unwrap(), None);
+
+    let right: String = left.replacen("BC=0100", "BC=0101", 1).replacen("F=.ZAP.", "F=.Z.P.", 1);
+    let divergence = trace::diff(left.as_bytes(), right.as_bytes(), &[]).unwrap().unwrap();
+    assert_eq!(divergence.line, 5);
+    assert_eq!(divergence.fields, ["BC"]);
+    assert_eq!(divergence.previous.as_deref(), left.lines().nth(3));
+    let ignored = trace::diff(left.as_bytes(), right.as_bytes(), &["bc".to_string()]).unwrap().unwrap();
+    assert_eq!((ignored.line, ignored.fields), (12, vec!["F".to_string()]));
+
+    let short: String = left.lines().take(7).collect::<Vec<&str>>().join("\n");
+    let divergence = trace::diff(left.as_bytes(), short.as_bytes(), &[]).unwrap().unwrap();
+    assert_eq!((divergence.line, divergence.right), (8, None));
+}
+
+#[test]
+fn tracing_does_not_change_the_run() {
+    let mut rom: Vec<u8> = Vec::new();
+    File::open("roms/invaders.rom").unwrap().read_to_end(&mut rom).unwrap();
+    let path = env::temp_dir().join(format!("rust8080-trace-{}.txt", std::process::id()));
+    let mut config = HeadlessConfig {
+        frames: 30,
+        dump_frames: vec![30],
+        output_dir: None,
+        overlay: false,
+        script: InputScript::new(),
+        trace: None,
+        trace_filter: TraceFilter::default(),
+        play: None,
+        record: None,
+    };
+    let plain = headless::run(&mut Invaders::new(&rom), &config).unwrap();
+    config.trace = Some(path.clone());
+    let traced = headless::run(&mut Invaders::new(&rom), &config).unwrap();
+    let text: String = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(plain, traced);
+    assert!(text.lines().count() > 100_000);
+    assert!(text.starts_with("         0 0000  00        NOP"));
+}
@@ -0,0 +1,104 @@
+extern crate rust8080;
+
+use rust8080::image::{crc32, write_bmp, write_png};
+use rust8080::invaders::video::{frame, overlay_colour, render, HEIGHT, VRAM_LEN, VRAM_START, WIDTH};
+use rust8080::vm::Vm;
+
+fn pixel(pixels: &[u8], x: usize, y: usize) -> [u8; 4] {
+    let index: usize = (y * WIDTH + x) * 4;
+    [pixels[index], pixels[index + 1], pixels[index + 2], pixels[index + 3]]
+}
+
+const WHITE: [u8; 4] = [0xff, 0xff, 0xff, 0xff];
+const BLACK: [u8; 4] = [0x00, 0x00, 0x00, 0xff];
+
+#[test]
+fn rotates_the_framebuffer() {
+    let mut vram: Vec<u8> = vec![0; VRAM_LEN];
+    // First byte, lowest bit: the bottom left corner
+    vram[0] = 0x01;
+    // End of the first line, highest bit: the top left corner
+    vram[31] = 0x80;
+    // Start of the last line: the bottom right corner
+    vram[VRAM_LEN - 32] = 0x01;
+    // Bit 3 of byte 2 of line 10
+    vram[10 * 32 + 2] = 0x08;
+    let pixels: Vec<u8> = render(&vram, false);
+    assert_eq!(pixels.len(), WIDTH * HEIGHT * 4);
+    assert_eq!(pixel(&pixels, 0, HEIGHT - 1), WHITE);
+    assert_eq!(pixel(&pixels, 0, 0), WHITE);
+    assert_eq!(pixel(&pixels, WIDTH - 1, HEIGHT - 1), WHITE);
+    assert_eq!(pixel(&pixels, 10, HEIGHT - 1 - (2 * 8 + 3)), WHITE);
+    let lit: usize = pixels.chunks(4).filter(|&rgba| rgba == WHITE).count();
+    assert_eq!(lit, 4);
+    assert_eq!(pixel(&pixels, 1, HEIGHT - 1), BLACK);
+}
+
+#[test]
+fn tints_through_the_overlay() {
+    let mut vram: Vec<u8> = vec![0; VRAM_LEN];
+    // Line 5, rows 40 (red band), 200 (green band) and 100 (white) from the top
+    for &y in &[40usize, 200, 100] {
+        let bit: usize = HEIGHT - 1 - y;
+        vram[5 * 32 + bit / 8] |= 1 << (bit % 8);
+    }
+    let pixels: Vec<u8> = render(&vram, true);
+    assert_eq!(pixel(&pixels, 5, 40), overlay_colour(5, 40));
+    assert_eq!(pixel(&pixels, 5, 40), [0xff, 0x20, 0x20, 0xff]);
+    assert_eq!(pixel(&pixels, 5, 200), [0x20, 0xff, 0x20, 0xff]);
+    assert_eq!(pixel(&pixels, 5, 100), WHITE);
+    // The bottom row is only green under the reserve ships
+    assert_eq!(overlay_colour(10, 250), WHITE);
+    assert_eq!(overlay_colour(20, 250), [0x20, 0xff, 0x20, 0xff]);
+
+    let mut vm = Vm::new();
+    vm.memory.load(VRAM_START, &vram);
+    assert_eq!(frame(&vm, true), pixels);
+}
+
+#[test]
+fn writes_png() {
+    // 2x2: red, green / blue, transparent
+    let rgba: [u8; 16] = [0xff, 0, 0, 0xff, 0, 0xff, 0, 0xff, 0, 0, 0xff, 0xff, 0, 0, 0, 0];
+    let mut png: Vec<u8> = Vec::new();
+    write_png(&mut png, 2, 2, &rgba).unwrap();
+    assert_eq!(&png[..8], &[0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a]);
+    // IHDR: 13 bytes, 2x2, 8-bit RGBA, then its CRC
+    assert_eq!(&png[8..16], &[0, 0, 0, 13, b'I', b'H', b'D', b'R']);
+    assert_eq!(&png[16..29], &[0, 0, 0, 2, 0, 0, 0, 2, 8, 6, 0, 0, 0]);
+    assert_eq!(&png[29..33], &crc32(&png[12..29]).to_be_bytes());
+    // IDAT: one stored deflate block holding both filtered scanlines
+    let len: usize = u32::from_be_bytes([png[33], png[34], png[35], png[36]]) as usize;
+    assert_eq!(&png[37..41], b"IDAT");
+    let zlib: &[u8] = &png[41..41 + len];
+    assert_eq!(&zlib[..7], &[0x78, 0x01, 0x01, 18, 0, !18, 0xff]);
+    assert_eq!(&zlib[7..16], &[0, 0xff, 0, 0, 0xff, 0, 0xff, 0, 0xff]);
+    assert_eq!(&zlib[16..25], &[0, 0, 0, 0xff, 0xff, 0, 0, 0, 0]);
+    assert_eq!(&png[png.len() - 12..], &[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xae, 0x42, 0x60, 0x82]);
+    assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+}
+
+#[test]
+fn writes_bmp() {
+    // 3x2: a red top row over a blue bottom row
+    let mut rgba: Vec<u8> = Vec::new();
+    for _ in 0..3 {
+        rgba.extend_from_slice(&[0xff, 0, 0, 0xff]);
+    }
+    for _ in 0..3 {
+        rgba.extend_from_slice(&[0, 0, 0xff, 0xff]);
+    }
+    let mut bmp: Vec<u8> = Vec::new();
+    write_bmp(&mut bmp, 3, 2, &rgba).unwrap();
+    // Rows of 9 bytes pad to 12
+    assert_eq!(bmp.len(), 54 + 24);
+    assert_eq!(&bmp[..2], b"BM");
+    assert_eq!(&bmp[2..6], &78u32.to_le_bytes());
+    assert_eq!(&bmp[10..14], &54u32.to_le_bytes());
+    assert_eq!(&bmp[14..18], &40u32.to_le_bytes());
+    assert_eq!(&bmp[18..26], &[3, 0, 0, 0, 2, 0, 0, 0]);
+    assert_eq!(&bmp[28..30], &24u16.to_le_bytes());
+    // Bottom row first, BGR
+    assert_eq!(&bmp[54..66], &[0xff, 0, 0, 0xff, 0, 0, 0xff, 0, 0, 0, 0, 0]);
+    assert_eq!(&bmp[66..78], &[0, 0, 0xff, 0, 0, 0xff, 0, 0, 0xff, 0, 0, 0]);
+}